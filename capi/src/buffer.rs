@@ -0,0 +1,131 @@
+//! `BZ2_bzBuffToBuffCompress` and `BZ2_bzBuffToBuffDecompress`
+
+use std::os::raw::{c_char, c_int, c_uint};
+
+use crate::{
+    bz_stream, BZ2_bzCompress, BZ2_bzCompressEnd, BZ2_bzCompressInit, BZ2_bzDecompress,
+    BZ2_bzDecompressEnd, BZ2_bzDecompressInit, BZ_FINISH, BZ_FINISH_OK, BZ_OK, BZ_OUTBUFF_FULL,
+    BZ_PARAM_ERROR, BZ_STREAM_END, BZ_UNEXPECTED_EOF,
+};
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzBuffToBuffCompress(
+    dest: *mut c_char,
+    destLen: *mut c_uint,
+    source: *mut c_char,
+    sourceLen: c_uint,
+    blockSize100k: c_int,
+    verbosity: c_int,
+    workFactor: c_int,
+) -> c_int {
+    if dest.is_null() || destLen.is_null() || source.is_null() || !(0..=4).contains(&verbosity) {
+        return BZ_PARAM_ERROR;
+    }
+    let mut strm = bz_stream::default();
+    let ret = BZ2_bzCompressInit(&mut strm, blockSize100k, verbosity, workFactor);
+    if ret != BZ_OK {
+        return ret;
+    }
+    strm.next_in = source;
+    strm.avail_in = sourceLen;
+    strm.next_out = dest;
+    strm.avail_out = *destLen;
+
+    let ret = match BZ2_bzCompress(&mut strm, BZ_FINISH) {
+        BZ_STREAM_END => {
+            *destLen -= strm.avail_out;
+            BZ_OK
+        }
+        BZ_FINISH_OK => BZ_OUTBUFF_FULL,
+        ret => ret,
+    };
+    BZ2_bzCompressEnd(&mut strm);
+    ret
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzBuffToBuffDecompress(
+    dest: *mut c_char,
+    destLen: *mut c_uint,
+    source: *mut c_char,
+    sourceLen: c_uint,
+    small: c_int,
+    verbosity: c_int,
+) -> c_int {
+    if dest.is_null() || destLen.is_null() || source.is_null() {
+        return BZ_PARAM_ERROR;
+    }
+    let mut strm = bz_stream::default();
+    let ret = BZ2_bzDecompressInit(&mut strm, verbosity, small);
+    if ret != BZ_OK {
+        return ret;
+    }
+    strm.next_in = source;
+    strm.avail_in = sourceLen;
+    strm.next_out = dest;
+    strm.avail_out = *destLen;
+
+    let ret = match BZ2_bzDecompress(&mut strm) {
+        BZ_STREAM_END => {
+            *destLen -= strm.avail_out;
+            BZ_OK
+        }
+        // either the output is full or the input ended before the end of the stream
+        BZ_OK if strm.avail_out > 0 => BZ_UNEXPECTED_EOF,
+        BZ_OK => BZ_OUTBUFF_FULL,
+        ret => ret,
+    };
+    BZ2_bzDecompressEnd(&mut strm);
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::sample;
+
+    #[test]
+    pub fn round_trips_between_buffers() {
+        let mut input = sample();
+        let mut encoded = vec![0u8; input.len()];
+        let mut encoded_length = encoded.len() as c_uint;
+        let ret = unsafe {
+            BZ2_bzBuffToBuffCompress(
+                encoded.as_mut_ptr() as *mut _,
+                &mut encoded_length,
+                input.as_mut_ptr() as *mut _,
+                input.len() as c_uint,
+                9,
+                0,
+                30,
+            )
+        };
+        assert_eq!(ret, BZ_OK);
+        encoded.truncate(encoded_length as usize);
+
+        // decompress into a buffer of the given size, returns the result code and the decoded data
+        let decompress = |size: usize, encoded: &[u8]| {
+            let mut decoded = vec![0u8; size];
+            let mut decoded_length = size as c_uint;
+            let ret = unsafe {
+                BZ2_bzBuffToBuffDecompress(
+                    decoded.as_mut_ptr() as *mut _,
+                    &mut decoded_length,
+                    encoded.as_ptr() as *mut _,
+                    encoded.len() as c_uint,
+                    0,
+                    0,
+                )
+            };
+            decoded.truncate(decoded_length as usize);
+            (ret, decoded)
+        };
+        assert_eq!(
+            decompress(input.len() + 1, &encoded),
+            (BZ_OK, input.clone())
+        );
+        assert_eq!(decompress(input.len() - 1, &encoded).0, BZ_OUTBUFF_FULL);
+        let truncated = &encoded[..encoded.len() / 2];
+        assert_eq!(decompress(input.len(), truncated).0, BZ_UNEXPECTED_EOF);
+    }
+}
@@ -0,0 +1,179 @@
+//! `BZ2_bzCompressInit`, `BZ2_bzCompress` and `BZ2_bzCompressEnd`
+
+use std::os::raw::c_int;
+
+use libribzip2::stream::{Action, BlockSize, Compress, EncoderBuilder, Progress, Status};
+
+use crate::{
+    bz_stream, end_state, get_state, init_state, State, BZ_FINISH, BZ_FINISH_OK, BZ_FLUSH,
+    BZ_FLUSH_OK, BZ_IO_ERROR, BZ_OK, BZ_PARAM_ERROR, BZ_RUN, BZ_RUN_OK, BZ_SEQUENCE_ERROR,
+    BZ_STREAM_END,
+};
+
+/// What the caller asked for with the most recent action, as in libbzip2.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Running,
+    Flushing,
+    Finishing,
+    Idle,
+}
+
+pub(crate) struct CompressState {
+    compress: Compress,
+    mode: Mode,
+    /// Input left when flushing or finishing began, which must be consumed before the action completes.
+    avail_in_expect: u32,
+}
+
+impl CompressState {
+    /// Compress the input of `strm` into its output.
+    unsafe fn handle(&mut self, strm: &mut bz_stream, action: Action) -> std::io::Result<Progress> {
+        let progress = self
+            .compress
+            .compress(strm.input(), strm.output(), action)?;
+        strm.consume(progress.consumed);
+        strm.produce(progress.produced);
+        if self.mode != Mode::Running {
+            self.avail_in_expect -= progress.consumed as u32;
+        }
+        Ok(progress)
+    }
+}
+
+unsafe fn compress_state<'a>(strm: *mut bz_stream) -> Option<&'a mut CompressState> {
+    match get_state(strm)? {
+        State::Compress(state) => Some(state),
+        _ => None,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzCompressInit(
+    strm: *mut bz_stream,
+    blockSize100k: c_int,
+    verbosity: c_int,
+    workFactor: c_int,
+) -> c_int {
+    let _ = verbosity;
+    if strm.is_null() || !(1..=9).contains(&blockSize100k) || !(0..=250).contains(&workFactor) {
+        return BZ_PARAM_ERROR;
+    }
+    let block_size = BlockSize::new(blockSize100k as u8).unwrap();
+    let state = CompressState {
+        compress: EncoderBuilder::new().block_size(block_size).build_push(),
+        mode: Mode::Running,
+        avail_in_expect: 0,
+    };
+    init_state(strm, State::Compress(state));
+    BZ_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzCompress(strm: *mut bz_stream, action: c_int) -> c_int {
+    let Some(state) = compress_state(strm) else {
+        return BZ_PARAM_ERROR;
+    };
+    let strm = &mut *strm;
+    loop {
+        match state.mode {
+            Mode::Idle => return BZ_SEQUENCE_ERROR,
+            Mode::Running => match action {
+                BZ_RUN => {
+                    return match state.handle(strm, Action::Run) {
+                        Ok(progress) if progress.consumed > 0 || progress.produced > 0 => BZ_RUN_OK,
+                        Ok(_) => BZ_PARAM_ERROR,
+                        Err(_) => BZ_IO_ERROR,
+                    }
+                }
+                BZ_FLUSH => {
+                    state.avail_in_expect = strm.avail_in;
+                    state.mode = Mode::Flushing;
+                }
+                BZ_FINISH => {
+                    state.avail_in_expect = strm.avail_in;
+                    state.mode = Mode::Finishing;
+                }
+                _ => return BZ_PARAM_ERROR,
+            },
+            Mode::Flushing => {
+                if action != BZ_FLUSH || state.avail_in_expect != strm.avail_in {
+                    return BZ_SEQUENCE_ERROR;
+                }
+                return match state.handle(strm, Action::Flush) {
+                    Ok(progress) if progress.status == Status::NeedsInput => {
+                        state.mode = Mode::Running;
+                        BZ_RUN_OK
+                    }
+                    Ok(_) => BZ_FLUSH_OK,
+                    Err(_) => BZ_IO_ERROR,
+                };
+            }
+            Mode::Finishing => {
+                if action != BZ_FINISH || state.avail_in_expect != strm.avail_in {
+                    return BZ_SEQUENCE_ERROR;
+                }
+                return match state.handle(strm, Action::Finish) {
+                    Ok(progress) if progress.status == Status::StreamEnd => {
+                        state.mode = Mode::Idle;
+                        BZ_STREAM_END
+                    }
+                    Ok(_) => BZ_FINISH_OK,
+                    Err(_) => BZ_IO_ERROR,
+                };
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzCompressEnd(strm: *mut bz_stream) -> c_int {
+    if compress_state(strm).is_none() {
+        return BZ_PARAM_ERROR;
+    }
+    end_state(strm);
+    BZ_OK
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::sample;
+    use libribzip2::stream::decode_stream;
+
+    #[test]
+    pub fn compresses_with_small_buffers() {
+        let input = sample();
+        let mut strm = bz_stream::default();
+        assert_eq!(unsafe { BZ2_bzCompressInit(&mut strm, 1, 0, 0) }, BZ_OK);
+
+        let mut encoded = vec![];
+        let mut output = [0u8; 100];
+        let mut process = |strm: &mut bz_stream, action| {
+            strm.next_out = output.as_mut_ptr() as *mut _;
+            strm.avail_out = output.len() as u32;
+            let ret = unsafe { BZ2_bzCompress(strm, action) };
+            encoded.extend_from_slice(&output[..output.len() - strm.avail_out as usize]);
+            ret
+        };
+        for piece in input.chunks(30_000) {
+            strm.next_in = piece.as_ptr() as *mut _;
+            strm.avail_in = piece.len() as u32;
+            while strm.avail_in > 0 {
+                assert_eq!(process(&mut strm, BZ_RUN), BZ_RUN_OK);
+            }
+            // after flushing, the output so far contains all input written
+            while process(&mut strm, BZ_FLUSH) == BZ_FLUSH_OK {}
+        }
+        while process(&mut strm, BZ_FINISH) == BZ_FINISH_OK {}
+        assert_eq!(process(&mut strm, BZ_FINISH), BZ_SEQUENCE_ERROR);
+        assert_eq!(strm.total_in_lo32 as usize, input.len());
+        assert_eq!(strm.total_out_lo32 as usize, encoded.len());
+        assert_eq!(unsafe { BZ2_bzCompressEnd(&mut strm) }, BZ_OK);
+        assert_eq!(unsafe { BZ2_bzCompressEnd(&mut strm) }, BZ_PARAM_ERROR);
+
+        let mut decoded = vec![];
+        let summary = decode_stream(&encoded[..], &mut decoded).unwrap();
+        assert_eq!(decoded, input);
+        assert_eq!(summary.num_blocks, 5);
+    }
+}
@@ -0,0 +1,149 @@
+//! `BZ2_bzDecompressInit`, `BZ2_bzDecompress` and `BZ2_bzDecompressEnd`
+
+use std::os::raw::c_int;
+
+use libribzip2::stream::{Decompress, Status};
+use libribzip2::{DecodeError, DecodeErrorKind};
+
+use crate::{
+    bz_stream, end_state, get_state, init_state, State, BZ_DATA_ERROR, BZ_DATA_ERROR_MAGIC,
+    BZ_IO_ERROR, BZ_OK, BZ_PARAM_ERROR, BZ_SEQUENCE_ERROR, BZ_STREAM_END, BZ_UNEXPECTED_EOF,
+};
+
+pub(crate) struct DecompressState {
+    decompress: Decompress,
+    /// Return code of all further calls once the stream ended or an error occurred.
+    result: Option<c_int>,
+}
+
+impl DecompressState {
+    unsafe fn decompress(&mut self, strm: &mut bz_stream) -> c_int {
+        if let Some(result) = self.result {
+            return result;
+        }
+        match self.decompress.decompress(strm.input(), strm.output()) {
+            Ok(progress) => {
+                strm.consume(progress.consumed);
+                strm.produce(progress.produced);
+                if progress.status == Status::StreamEnd {
+                    // the end of the stream is reported only once
+                    self.result = Some(BZ_SEQUENCE_ERROR);
+                    return BZ_STREAM_END;
+                }
+                BZ_OK
+            }
+            Err(err) => {
+                let result = error_code(&err);
+                self.result = Some(result);
+                result
+            }
+        }
+    }
+}
+
+/// Map decoding errors to the closest return code of libbzip2.
+fn error_code(err: &DecodeError) -> c_int {
+    match err.kind() {
+        DecodeErrorKind::BadMagic if err.block_number() == 0 => BZ_DATA_ERROR_MAGIC,
+        DecodeErrorKind::TruncatedInput => BZ_UNEXPECTED_EOF,
+        DecodeErrorKind::Io(_) => BZ_IO_ERROR,
+        _ => BZ_DATA_ERROR,
+    }
+}
+
+unsafe fn decompress_state<'a>(strm: *mut bz_stream) -> Option<&'a mut DecompressState> {
+    match get_state(strm)? {
+        State::Decompress(state) => Some(state),
+        _ => None,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzDecompressInit(
+    strm: *mut bz_stream,
+    verbosity: c_int,
+    small: c_int,
+) -> c_int {
+    if strm.is_null() || !(0..=1).contains(&small) || !(0..=4).contains(&verbosity) {
+        return BZ_PARAM_ERROR;
+    }
+    let state = DecompressState {
+        decompress: Decompress::new(),
+        result: None,
+    };
+    init_state(strm, State::Decompress(state));
+    BZ_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzDecompress(strm: *mut bz_stream) -> c_int {
+    match decompress_state(strm) {
+        Some(state) => state.decompress(&mut *strm),
+        None => BZ_PARAM_ERROR,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzDecompressEnd(strm: *mut bz_stream) -> c_int {
+    if decompress_state(strm).is_none() {
+        return BZ_PARAM_ERROR;
+    }
+    end_state(strm);
+    BZ_OK
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::sample;
+    use libribzip2::stream::encode_stream;
+    use libribzip2::EncodingStrategy;
+
+    #[test]
+    pub fn decompresses_with_small_buffers() {
+        let input = sample();
+        let mut encoded = vec![];
+        encode_stream(&input[..], &mut encoded, 1, EncodingStrategy::Single).unwrap();
+        let stream_length = encoded.len();
+        encoded.extend_from_slice(b"trailing data");
+
+        let mut strm = bz_stream::default();
+        assert_eq!(unsafe { BZ2_bzDecompressInit(&mut strm, 0, 0) }, BZ_OK);
+        let mut decoded = vec![];
+        let mut output = [0u8; 1000];
+        let mut consumed = 0;
+        let ret = loop {
+            let end = (consumed + 777).min(encoded.len());
+            strm.next_in = encoded[consumed..end].as_ptr() as *mut _;
+            strm.avail_in = (end - consumed) as u32;
+            strm.next_out = output.as_mut_ptr() as *mut _;
+            strm.avail_out = output.len() as u32;
+            let ret = unsafe { BZ2_bzDecompress(&mut strm) };
+            consumed = end - strm.avail_in as usize;
+            decoded.extend_from_slice(&output[..output.len() - strm.avail_out as usize]);
+            if ret != BZ_OK {
+                break ret;
+            }
+        };
+        assert_eq!(ret, BZ_STREAM_END);
+        assert_eq!(decoded, input);
+        assert_eq!(consumed, stream_length);
+        assert_eq!(strm.total_in_lo32 as usize, stream_length);
+        assert_eq!(strm.total_out_lo32 as usize, input.len());
+        assert_eq!(unsafe { BZ2_bzDecompress(&mut strm) }, BZ_SEQUENCE_ERROR);
+        assert_eq!(unsafe { BZ2_bzDecompressEnd(&mut strm) }, BZ_OK);
+    }
+
+    #[test]
+    pub fn reports_invalid_data() {
+        let mut strm = bz_stream::default();
+        let mut output = [0u8; 100];
+        assert_eq!(unsafe { BZ2_bzDecompressInit(&mut strm, 0, 0) }, BZ_OK);
+        strm.next_in = b"BZh0".as_ptr() as *mut _;
+        strm.avail_in = 4;
+        strm.next_out = output.as_mut_ptr() as *mut _;
+        strm.avail_out = output.len() as u32;
+        assert_eq!(unsafe { BZ2_bzDecompress(&mut strm) }, BZ_DATA_ERROR_MAGIC);
+        assert_eq!(unsafe { BZ2_bzDecompressEnd(&mut strm) }, BZ_OK);
+    }
+}
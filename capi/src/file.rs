@@ -0,0 +1,550 @@
+//! The `FILE` based interface of libbzip2 (`BZ2_bzRead`, `BZ2_bzWrite`, ...) and the
+//! zlib style functions built upon it (`BZ2_bzopen`, `BZ2_bzread`, ...).
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+use libc::FILE;
+
+use crate::{
+    bz_stream, BZ2_bzCompress, BZ2_bzCompressEnd, BZ2_bzCompressInit, BZ2_bzDecompress,
+    BZ2_bzDecompressEnd, BZ2_bzDecompressInit, BZ_FINISH, BZ_FINISH_OK, BZ_IO_ERROR, BZ_OK,
+    BZ_PARAM_ERROR, BZ_RUN, BZ_RUN_OK, BZ_SEQUENCE_ERROR, BZ_STREAM_END, BZ_UNEXPECTED_EOF,
+};
+
+pub const BZ_MAX_UNUSED: c_int = 5000;
+
+/// Handle of a compressed file opened for reading or writing.
+pub type BZFILE = c_void;
+
+struct BzFile {
+    handle: *mut FILE,
+    buf: [u8; BZ_MAX_UNUSED as usize],
+    buf_n: c_int,
+    writing: bool,
+    strm: bz_stream,
+    last_err: c_int,
+    initialised_ok: bool,
+    /// Whether the handle was opened by [BZ2_bzopen] or [BZ2_bzdopen] and is closed with the file.
+    owns_handle: bool,
+}
+
+impl BzFile {
+    fn new(handle: *mut FILE, writing: bool) -> Box<Self> {
+        Box::new(BzFile {
+            handle,
+            buf: [0; BZ_MAX_UNUSED as usize],
+            buf_n: 0,
+            writing,
+            strm: bz_stream::default(),
+            last_err: BZ_OK,
+            initialised_ok: false,
+            owns_handle: false,
+        })
+    }
+}
+
+/// Report `err` both through `bzerror` and as the last error of the file.
+unsafe fn set_err(bzerror: *mut c_int, bzf: *mut BzFile, err: c_int) {
+    if !bzerror.is_null() {
+        *bzerror = err;
+    }
+    if !bzf.is_null() {
+        (*bzf).last_err = err;
+    }
+}
+
+/// Whether the end of `f` is reached, in contrast to `feof` this looks ahead.
+unsafe fn at_eof(f: *mut FILE) -> bool {
+    let c = libc::fgetc(f);
+    if c == libc::EOF {
+        return true;
+    }
+    libc::ungetc(c, f);
+    false
+}
+
+/// Write the output in the buffer of the file, the output starts at the beginning of the buffer.
+unsafe fn write_buffer(bzf: &mut BzFile) -> Result<(), c_int> {
+    let size = BZ_MAX_UNUSED as usize - bzf.strm.avail_out as usize;
+    if size > 0 {
+        let written = libc::fwrite(bzf.buf.as_ptr() as *const c_void, 1, size, bzf.handle);
+        if written != size || libc::ferror(bzf.handle) != 0 {
+            return Err(BZ_IO_ERROR);
+        }
+    }
+    Ok(())
+}
+
+/// Point the output of the stream to the buffer of the file.
+fn reset_output(bzf: &mut BzFile) {
+    bzf.strm.avail_out = BZ_MAX_UNUSED as c_uint;
+    bzf.strm.next_out = bzf.buf.as_mut_ptr() as *mut c_char;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzWriteOpen(
+    bzerror: *mut c_int,
+    f: *mut FILE,
+    blockSize100k: c_int,
+    verbosity: c_int,
+    workFactor: c_int,
+) -> *mut BZFILE {
+    set_err(bzerror, std::ptr::null_mut(), BZ_OK);
+    if f.is_null()
+        || !(1..=9).contains(&blockSize100k)
+        || !(0..=250).contains(&workFactor)
+        || !(0..=4).contains(&verbosity)
+    {
+        set_err(bzerror, std::ptr::null_mut(), BZ_PARAM_ERROR);
+        return std::ptr::null_mut();
+    }
+    if libc::ferror(f) != 0 {
+        set_err(bzerror, std::ptr::null_mut(), BZ_IO_ERROR);
+        return std::ptr::null_mut();
+    }
+    let bzf = Box::into_raw(BzFile::new(f, true));
+    let ret = BZ2_bzCompressInit(&mut (*bzf).strm, blockSize100k, verbosity, workFactor);
+    if ret != BZ_OK {
+        set_err(bzerror, bzf, ret);
+        drop(Box::from_raw(bzf));
+        return std::ptr::null_mut();
+    }
+    (*bzf).initialised_ok = true;
+    bzf as *mut BZFILE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzWrite(
+    bzerror: *mut c_int,
+    b: *mut BZFILE,
+    buf: *mut c_void,
+    len: c_int,
+) {
+    let bzf = b as *mut BzFile;
+    set_err(bzerror, bzf, BZ_OK);
+    if bzf.is_null() || buf.is_null() || len < 0 {
+        set_err(bzerror, bzf, BZ_PARAM_ERROR);
+        return;
+    }
+    let file = &mut *bzf;
+    if !file.writing {
+        set_err(bzerror, bzf, BZ_SEQUENCE_ERROR);
+        return;
+    }
+    if libc::ferror(file.handle) != 0 {
+        set_err(bzerror, bzf, BZ_IO_ERROR);
+        return;
+    }
+    if len == 0 {
+        return;
+    }
+    file.strm.avail_in = len as c_uint;
+    file.strm.next_in = buf as *mut c_char;
+    loop {
+        reset_output(file);
+        let ret = BZ2_bzCompress(&mut file.strm, BZ_RUN);
+        if ret != BZ_RUN_OK {
+            set_err(bzerror, bzf, ret);
+            return;
+        }
+        if let Err(err) = write_buffer(file) {
+            set_err(bzerror, bzf, err);
+            return;
+        }
+        if file.strm.avail_in == 0 {
+            return;
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzWriteClose(
+    bzerror: *mut c_int,
+    b: *mut BZFILE,
+    abandon: c_int,
+    nbytes_in: *mut c_uint,
+    nbytes_out: *mut c_uint,
+) {
+    BZ2_bzWriteClose64(
+        bzerror,
+        b,
+        abandon,
+        nbytes_in,
+        std::ptr::null_mut(),
+        nbytes_out,
+        std::ptr::null_mut(),
+    );
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzWriteClose64(
+    bzerror: *mut c_int,
+    b: *mut BZFILE,
+    abandon: c_int,
+    nbytes_in_lo32: *mut c_uint,
+    nbytes_in_hi32: *mut c_uint,
+    nbytes_out_lo32: *mut c_uint,
+    nbytes_out_hi32: *mut c_uint,
+) {
+    let bzf = b as *mut BzFile;
+    if bzf.is_null() {
+        set_err(bzerror, bzf, BZ_OK);
+        return;
+    }
+    let file = &mut *bzf;
+    if !file.writing {
+        set_err(bzerror, bzf, BZ_SEQUENCE_ERROR);
+        return;
+    }
+    if libc::ferror(file.handle) != 0 {
+        set_err(bzerror, bzf, BZ_IO_ERROR);
+        return;
+    }
+    for counter in [
+        nbytes_in_lo32,
+        nbytes_in_hi32,
+        nbytes_out_lo32,
+        nbytes_out_hi32,
+    ] {
+        if !counter.is_null() {
+            *counter = 0;
+        }
+    }
+
+    if abandon == 0 && file.last_err == BZ_OK {
+        loop {
+            reset_output(file);
+            let ret = BZ2_bzCompress(&mut file.strm, BZ_FINISH);
+            if ret != BZ_FINISH_OK && ret != BZ_STREAM_END {
+                set_err(bzerror, bzf, ret);
+                return;
+            }
+            if let Err(err) = write_buffer(file) {
+                set_err(bzerror, bzf, err);
+                return;
+            }
+            if ret == BZ_STREAM_END {
+                break;
+            }
+        }
+    }
+    if abandon == 0 && libc::ferror(file.handle) == 0 {
+        libc::fflush(file.handle);
+        if libc::ferror(file.handle) != 0 {
+            set_err(bzerror, bzf, BZ_IO_ERROR);
+            return;
+        }
+    }
+
+    let counters = [
+        (nbytes_in_lo32, file.strm.total_in_lo32),
+        (nbytes_in_hi32, file.strm.total_in_hi32),
+        (nbytes_out_lo32, file.strm.total_out_lo32),
+        (nbytes_out_hi32, file.strm.total_out_hi32),
+    ];
+    for (counter, value) in counters {
+        if !counter.is_null() {
+            *counter = value;
+        }
+    }
+    set_err(bzerror, bzf, BZ_OK);
+    BZ2_bzCompressEnd(&mut file.strm);
+    drop(Box::from_raw(bzf));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzReadOpen(
+    bzerror: *mut c_int,
+    f: *mut FILE,
+    verbosity: c_int,
+    small: c_int,
+    unused: *mut c_void,
+    nUnused: c_int,
+) -> *mut BZFILE {
+    set_err(bzerror, std::ptr::null_mut(), BZ_OK);
+    if f.is_null()
+        || !(0..=1).contains(&small)
+        || !(0..=4).contains(&verbosity)
+        || (unused.is_null() && nUnused != 0)
+        || (!unused.is_null() && !(0..=BZ_MAX_UNUSED).contains(&nUnused))
+    {
+        set_err(bzerror, std::ptr::null_mut(), BZ_PARAM_ERROR);
+        return std::ptr::null_mut();
+    }
+    if libc::ferror(f) != 0 {
+        set_err(bzerror, std::ptr::null_mut(), BZ_IO_ERROR);
+        return std::ptr::null_mut();
+    }
+    let bzf = Box::into_raw(BzFile::new(f, false));
+    let file = &mut *bzf;
+    if nUnused > 0 {
+        let unused = std::slice::from_raw_parts(unused as *const u8, nUnused as usize);
+        file.buf[..unused.len()].copy_from_slice(unused);
+        file.buf_n = nUnused;
+    }
+    let ret = BZ2_bzDecompressInit(&mut file.strm, verbosity, small);
+    if ret != BZ_OK {
+        set_err(bzerror, bzf, ret);
+        drop(Box::from_raw(bzf));
+        return std::ptr::null_mut();
+    }
+    file.strm.avail_in = file.buf_n as c_uint;
+    file.strm.next_in = file.buf.as_mut_ptr() as *mut c_char;
+    file.initialised_ok = true;
+    bzf as *mut BZFILE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzReadClose(bzerror: *mut c_int, b: *mut BZFILE) {
+    let bzf = b as *mut BzFile;
+    set_err(bzerror, bzf, BZ_OK);
+    if bzf.is_null() {
+        return;
+    }
+    if (*bzf).writing {
+        set_err(bzerror, bzf, BZ_SEQUENCE_ERROR);
+        return;
+    }
+    if (*bzf).initialised_ok {
+        BZ2_bzDecompressEnd(&mut (*bzf).strm);
+    }
+    drop(Box::from_raw(bzf));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzRead(
+    bzerror: *mut c_int,
+    b: *mut BZFILE,
+    buf: *mut c_void,
+    len: c_int,
+) -> c_int {
+    let bzf = b as *mut BzFile;
+    set_err(bzerror, bzf, BZ_OK);
+    if bzf.is_null() || buf.is_null() || len < 0 {
+        set_err(bzerror, bzf, BZ_PARAM_ERROR);
+        return 0;
+    }
+    let file = &mut *bzf;
+    if file.writing {
+        set_err(bzerror, bzf, BZ_SEQUENCE_ERROR);
+        return 0;
+    }
+    if len == 0 {
+        return 0;
+    }
+    file.strm.avail_out = len as c_uint;
+    file.strm.next_out = buf as *mut c_char;
+    loop {
+        if libc::ferror(file.handle) != 0 {
+            set_err(bzerror, bzf, BZ_IO_ERROR);
+            return 0;
+        }
+        if file.strm.avail_in == 0 && !at_eof(file.handle) {
+            let read = libc::fread(
+                file.buf.as_mut_ptr() as *mut c_void,
+                1,
+                BZ_MAX_UNUSED as usize,
+                file.handle,
+            );
+            if libc::ferror(file.handle) != 0 {
+                set_err(bzerror, bzf, BZ_IO_ERROR);
+                return 0;
+            }
+            file.buf_n = read as c_int;
+            file.strm.avail_in = read as c_uint;
+            file.strm.next_in = file.buf.as_mut_ptr() as *mut c_char;
+        }
+
+        let ret = BZ2_bzDecompress(&mut file.strm);
+        if ret != BZ_OK && ret != BZ_STREAM_END {
+            set_err(bzerror, bzf, ret);
+            return 0;
+        }
+        if ret == BZ_OK && at_eof(file.handle) && file.strm.avail_in == 0 && file.strm.avail_out > 0
+        {
+            set_err(bzerror, bzf, BZ_UNEXPECTED_EOF);
+            return 0;
+        }
+        if ret == BZ_STREAM_END {
+            set_err(bzerror, bzf, BZ_STREAM_END);
+            return len - file.strm.avail_out as c_int;
+        }
+        if file.strm.avail_out == 0 {
+            return len;
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzReadGetUnused(
+    bzerror: *mut c_int,
+    b: *mut BZFILE,
+    unused: *mut *mut c_void,
+    nUnused: *mut c_int,
+) {
+    let bzf = b as *mut BzFile;
+    if bzf.is_null() {
+        set_err(bzerror, bzf, BZ_PARAM_ERROR);
+        return;
+    }
+    if (*bzf).last_err != BZ_STREAM_END {
+        set_err(bzerror, bzf, BZ_SEQUENCE_ERROR);
+        return;
+    }
+    if unused.is_null() || nUnused.is_null() {
+        set_err(bzerror, bzf, BZ_PARAM_ERROR);
+        return;
+    }
+    set_err(bzerror, bzf, BZ_OK);
+    *nUnused = (*bzf).strm.avail_in as c_int;
+    *unused = (*bzf).strm.next_in as *mut c_void;
+}
+
+/// Open a compressed file by path (`bzopen`) or file descriptor (`bzdopen`). The mode consists of
+/// `r` or `w`, optionally the block size as digit and `s` for the small mode.
+unsafe fn open(path: *const c_char, fd: c_int, mode: *const c_char) -> *mut BZFILE {
+    if mode.is_null() {
+        return std::ptr::null_mut();
+    }
+    let mut block_size = 9;
+    let mut writing = false;
+    let mut small = 0;
+    for &c in CStr::from_ptr(mode).to_bytes() {
+        match c {
+            b'r' => writing = false,
+            b'w' => writing = true,
+            b's' => small = 1,
+            b'0'..=b'9' => block_size = c_int::from(c - b'0'),
+            _ => {}
+        }
+    }
+    let mode = if writing { c"wb" } else { c"rb" };
+
+    let use_std_stream = fd < 0 && (path.is_null() || *path == 0);
+    let f = if use_std_stream {
+        let fd = if writing { 1 } else { 0 };
+        libc::fdopen(fd, mode.as_ptr())
+    } else if fd < 0 {
+        libc::fopen(path, mode.as_ptr())
+    } else {
+        libc::fdopen(fd, mode.as_ptr())
+    };
+    if f.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let mut bzerr = BZ_OK;
+    let bzfp = if writing {
+        BZ2_bzWriteOpen(&mut bzerr, f, block_size.clamp(1, 9), 0, 30)
+    } else {
+        BZ2_bzReadOpen(&mut bzerr, f, 0, small, std::ptr::null_mut(), 0)
+    };
+    if bzfp.is_null() {
+        if !use_std_stream {
+            libc::fclose(f);
+        }
+        return std::ptr::null_mut();
+    }
+    // stdin and stdout are left open
+    (*(bzfp as *mut BzFile)).owns_handle = !use_std_stream;
+    bzfp
+}
+
+/// Open a compressed file for reading or writing, e.g. `BZ2_bzopen("file.bz2", "w9")`.
+/// An empty path or `NULL` stands for stdin or stdout.
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzopen(path: *const c_char, mode: *const c_char) -> *mut BZFILE {
+    open(path, -1, mode)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzdopen(fd: c_int, mode: *const c_char) -> *mut BZFILE {
+    open(std::ptr::null(), fd, mode)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzread(b: *mut BZFILE, buf: *mut c_void, len: c_int) -> c_int {
+    if b.is_null() {
+        return -1;
+    }
+    if (*(b as *mut BzFile)).last_err == BZ_STREAM_END {
+        return 0;
+    }
+    let mut bzerr = BZ_OK;
+    let read = BZ2_bzRead(&mut bzerr, b, buf, len);
+    if bzerr == BZ_OK || bzerr == BZ_STREAM_END {
+        read
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzwrite(b: *mut BZFILE, buf: *mut c_void, len: c_int) -> c_int {
+    let mut bzerr = BZ_OK;
+    BZ2_bzWrite(&mut bzerr, b, buf, len);
+    if bzerr == BZ_OK {
+        len
+    } else {
+        -1
+    }
+}
+
+/// Does nothing, as in libbzip2.
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzflush(b: *mut BZFILE) -> c_int {
+    let _ = b;
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzclose(b: *mut BZFILE) {
+    if b.is_null() {
+        return;
+    }
+    let file = &*(b as *mut BzFile);
+    let handle = file.handle;
+    let owns_handle = file.owns_handle;
+    let mut bzerr = BZ_OK;
+    if file.writing {
+        BZ2_bzWriteClose(&mut bzerr, b, 0, std::ptr::null_mut(), std::ptr::null_mut());
+        if bzerr != BZ_OK {
+            BZ2_bzWriteClose(
+                std::ptr::null_mut(),
+                b,
+                1,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            );
+        }
+    } else {
+        BZ2_bzReadClose(&mut bzerr, b);
+    }
+    if owns_handle {
+        libc::fclose(handle);
+    }
+}
+
+const BZ_ERROR_STRINGS: [&CStr; 10] = [
+    c"OK",
+    c"SEQUENCE_ERROR",
+    c"PARAM_ERROR",
+    c"MEM_ERROR",
+    c"DATA_ERROR",
+    c"DATA_ERROR_MAGIC",
+    c"IO_ERROR",
+    c"UNEXPECTED_EOF",
+    c"OUTBUFF_FULL",
+    c"CONFIG_ERROR",
+];
+
+/// Describe the last error of a file, its code is stored in `errnum`.
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzerror(b: *mut BZFILE, errnum: *mut c_int) -> *const c_char {
+    let err = (*(b as *mut BzFile)).last_err.min(0);
+    *errnum = err;
+    BZ_ERROR_STRINGS
+        .get(err.unsigned_abs() as usize)
+        .map_or(c"???".as_ptr(), |description| description.as_ptr())
+}
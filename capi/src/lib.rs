@@ -0,0 +1,201 @@
+//! A C interface to libribzip2 which is compatible with libbzip2 1.0.8.
+//!
+//! The library is built as `libbz2.so` and `libbz2.a` and exports the functions of the
+//! reference library with the same signatures and semantics, declared by the checked-in header
+//! `include/bzlib.h`. Hence C programs can be linked against it instead of libbzip2 without
+//! changes. The header is generated by cbindgen and the test `header_matches_exported_items`
+//! checks that it is up to date.
+//!
+//! Differences to libbzip2:
+//!
+//!  * `verbosity`, `workFactor` and `small` are checked but have no effect
+//!  * custom allocators (`bzalloc`, `bzfree`) are not used
+//!  * the decompressor returns output a block at a time
+
+#![allow(non_snake_case, non_camel_case_types, clippy::missing_safety_doc)]
+
+mod buffer;
+mod compress;
+mod decompress;
+mod file;
+
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+pub use buffer::*;
+pub use compress::*;
+pub use decompress::*;
+pub use file::*;
+
+pub const BZ_RUN: c_int = 0;
+pub const BZ_FLUSH: c_int = 1;
+pub const BZ_FINISH: c_int = 2;
+
+pub const BZ_OK: c_int = 0;
+pub const BZ_RUN_OK: c_int = 1;
+pub const BZ_FLUSH_OK: c_int = 2;
+pub const BZ_FINISH_OK: c_int = 3;
+pub const BZ_STREAM_END: c_int = 4;
+pub const BZ_SEQUENCE_ERROR: c_int = -1;
+pub const BZ_PARAM_ERROR: c_int = -2;
+pub const BZ_MEM_ERROR: c_int = -3;
+pub const BZ_DATA_ERROR: c_int = -4;
+pub const BZ_DATA_ERROR_MAGIC: c_int = -5;
+pub const BZ_IO_ERROR: c_int = -6;
+pub const BZ_UNEXPECTED_EOF: c_int = -7;
+pub const BZ_OUTBUFF_FULL: c_int = -8;
+pub const BZ_CONFIG_ERROR: c_int = -9;
+
+/// State of a compression or decompression, laid out like the struct of libbzip2.
+#[repr(C)]
+pub struct bz_stream {
+    pub next_in: *mut c_char,
+    pub avail_in: c_uint,
+    pub total_in_lo32: c_uint,
+    pub total_in_hi32: c_uint,
+
+    pub next_out: *mut c_char,
+    pub avail_out: c_uint,
+    pub total_out_lo32: c_uint,
+    pub total_out_hi32: c_uint,
+
+    pub state: *mut c_void,
+
+    pub bzalloc: Option<unsafe extern "C" fn(*mut c_void, c_int, c_int) -> *mut c_void>,
+    pub bzfree: Option<unsafe extern "C" fn(*mut c_void, *mut c_void)>,
+    pub opaque: *mut c_void,
+}
+
+impl Default for bz_stream {
+    fn default() -> Self {
+        bz_stream {
+            next_in: std::ptr::null_mut(),
+            avail_in: 0,
+            total_in_lo32: 0,
+            total_in_hi32: 0,
+            next_out: std::ptr::null_mut(),
+            avail_out: 0,
+            total_out_lo32: 0,
+            total_out_hi32: 0,
+            state: std::ptr::null_mut(),
+            bzalloc: None,
+            bzfree: None,
+            opaque: std::ptr::null_mut(),
+        }
+    }
+}
+
+impl bz_stream {
+    /// Input which has not been consumed yet.
+    unsafe fn input<'a>(&self) -> &'a [u8] {
+        if self.avail_in == 0 {
+            return &[];
+        }
+        std::slice::from_raw_parts(self.next_in as *const u8, self.avail_in as usize)
+    }
+
+    /// Advance the input by `size` bytes.
+    unsafe fn consume(&mut self, size: usize) {
+        self.next_in = self.next_in.add(size);
+        self.avail_in -= size as c_uint;
+        add_to_total(&mut self.total_in_lo32, &mut self.total_in_hi32, size);
+    }
+
+    /// Space for output which has not been filled yet.
+    unsafe fn output<'a>(&self) -> &'a mut [u8] {
+        if self.avail_out == 0 {
+            return &mut [];
+        }
+        std::slice::from_raw_parts_mut(self.next_out as *mut u8, self.avail_out as usize)
+    }
+
+    /// Advance the output by `size` bytes written into [bz_stream::output].
+    unsafe fn produce(&mut self, size: usize) {
+        self.next_out = self.next_out.add(size);
+        self.avail_out -= size as c_uint;
+        add_to_total(&mut self.total_out_lo32, &mut self.total_out_hi32, size);
+    }
+
+    fn reset_totals(&mut self) {
+        self.total_in_lo32 = 0;
+        self.total_in_hi32 = 0;
+        self.total_out_lo32 = 0;
+        self.total_out_hi32 = 0;
+    }
+}
+
+fn add_to_total(lo32: &mut c_uint, hi32: &mut c_uint, size: usize) {
+    let total = ((u64::from(*hi32) << 32) | u64::from(*lo32)) + size as u64;
+    *lo32 = total as c_uint;
+    *hi32 = (total >> 32) as c_uint;
+}
+
+/// State of a compression or decompression attached to a [bz_stream].
+enum State {
+    Compress(compress::CompressState),
+    Decompress(decompress::DecompressState),
+}
+
+struct AttachedState {
+    /// The stream the state was attached to, the state is unusable with any other stream.
+    strm: *const bz_stream,
+    state: State,
+}
+
+/// Attach `state` to `strm`, which must not be null.
+unsafe fn init_state(strm: *mut bz_stream, state: State) {
+    let attached = AttachedState { strm, state };
+    (*strm).state = Box::into_raw(Box::new(attached)) as *mut c_void;
+    (*strm).reset_totals();
+}
+
+/// The state attached to `strm` by [init_state], unless it was attached to another stream.
+unsafe fn get_state<'a>(strm: *mut bz_stream) -> Option<&'a mut State> {
+    if strm.is_null() || (*strm).state.is_null() {
+        return None;
+    }
+    let attached = &mut *((*strm).state as *mut AttachedState);
+    if !std::ptr::eq(attached.strm, strm) {
+        return None;
+    }
+    Some(&mut attached.state)
+}
+
+/// Release the state attached to `strm` after it was checked using [get_state].
+unsafe fn end_state(strm: *mut bz_stream) {
+    drop(Box::from_raw((*strm).state as *mut AttachedState));
+    (*strm).state = std::ptr::null_mut();
+}
+
+/// Version of libbzip2 this library is compatible with.
+#[no_mangle]
+pub extern "C" fn BZ2_bzlibVersion() -> *const c_char {
+    concat!("1.0.8, libribzip2 ", env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod test {
+    pub fn sample() -> Vec<u8> {
+        (0..150_000u32)
+            .map(|x| b"abcdefgh"[(x.wrapping_mul(2_654_435_761) >> 29) as usize])
+            .collect()
+    }
+
+    #[test]
+    pub fn header_matches_exported_items() {
+        let crate_dir = env!("CARGO_MANIFEST_DIR");
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+        let mut generated = vec![];
+        cbindgen::Builder::new()
+            .with_src(format!("{}/src/lib.rs", crate_dir))
+            .with_config(config)
+            .generate()
+            .unwrap()
+            .write(&mut generated);
+        let header = std::fs::read(format!("{}/include/bzlib.h", crate_dir)).unwrap();
+        assert!(
+            generated == header,
+            "include/bzlib.h is outdated, regenerate it using \
+             `cbindgen --config cbindgen.toml --output include/bzlib.h` in capi"
+        );
+    }
+}
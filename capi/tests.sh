@@ -0,0 +1,13 @@
+#!/bin/bash
+# Links a C program against the static library and checks interoperability with the reference bzip2.
+set -eux
+
+cargo build
+mkdir -p ../target/capi-test
+cc -Wall -Werror -I include tests/test_bzlib.c ../target/debug/libbz2.a -lpthread -ldl -lm \
+    -o ../target/capi-test/test_bzlib
+
+bzip2 -c ../cli/samples/idiot.txt > ../target/capi-test/idiot.txt.bz2
+../target/capi-test/test_bzlib ../cli/samples/idiot.txt ../target/capi-test/idiot.txt.bz2 \
+    ../target/capi-test/output.bz2
+bunzip2 -c ../target/capi-test/output.bz2 | cmp - ../cli/samples/idiot.txt
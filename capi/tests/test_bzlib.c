@@ -0,0 +1,221 @@
+/* Exercises the libbzip2 compatible interface from C.
+
+   Usage: test_bzlib [FILE FILE.bz2 OUTPUT.bz2]
+
+   Without arguments only in-memory round trips are checked. Otherwise FILE.bz2 (e.g. created
+   by the reference bzip2) is decompressed and compared with FILE, and FILE is compressed into
+   OUTPUT.bz2 to be checked by the reference bunzip2. */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "bzlib.h"
+
+#define SAMPLE_SIZE 250000
+
+#define CHECK(condition)                                                  \
+   do {                                                                   \
+      if (!(condition)) {                                                 \
+         fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+                 #condition);                                             \
+         exit(1);                                                         \
+      }                                                                   \
+   } while (0)
+
+static char *sample(void)
+{
+   char *data = malloc(SAMPLE_SIZE);
+   unsigned int state = 42;
+   for (int i = 0; i < SAMPLE_SIZE; i++) {
+      state = state * 1103515245 + 12345;
+      data[i] = "abcdefgh"[(state >> 16) % 8];
+   }
+   return data;
+}
+
+static void test_buff_to_buff(char *input)
+{
+   unsigned int encoded_length = SAMPLE_SIZE;
+   char *encoded = malloc(encoded_length);
+   CHECK(BZ2_bzBuffToBuffCompress(encoded, &encoded_length, input, SAMPLE_SIZE, 9, 0, 30) == BZ_OK);
+   CHECK(memcmp(encoded, "BZh9", 4) == 0);
+
+   unsigned int decoded_length = SAMPLE_SIZE + 1;
+   char *decoded = malloc(decoded_length);
+   CHECK(BZ2_bzBuffToBuffDecompress(decoded, &decoded_length, encoded, encoded_length, 0, 0) == BZ_OK);
+   CHECK(decoded_length == SAMPLE_SIZE);
+   CHECK(memcmp(decoded, input, SAMPLE_SIZE) == 0);
+
+   decoded_length = SAMPLE_SIZE - 1;
+   CHECK(BZ2_bzBuffToBuffDecompress(decoded, &decoded_length, encoded, encoded_length, 0, 0) == BZ_OUTBUFF_FULL);
+   decoded_length = SAMPLE_SIZE;
+   CHECK(BZ2_bzBuffToBuffDecompress(decoded, &decoded_length, input, SAMPLE_SIZE, 0, 0) == BZ_DATA_ERROR_MAGIC);
+   CHECK(BZ2_bzBuffToBuffCompress(encoded, &encoded_length, input, SAMPLE_SIZE, 10, 0, 30) == BZ_PARAM_ERROR);
+
+   free(encoded);
+   free(decoded);
+}
+
+static void test_streams(char *input)
+{
+   char *encoded = malloc(SAMPLE_SIZE);
+   char *decoded = malloc(SAMPLE_SIZE);
+   char output[100];
+   unsigned int encoded_length = 0;
+   bz_stream strm;
+   memset(&strm, 0, sizeof(strm));
+   CHECK(BZ2_bzCompressInit(&strm, 1, 0, 0) == BZ_OK);
+
+   /* flush after every 50000 bytes, finish after all input was passed */
+   for (int position = 0; position <= SAMPLE_SIZE; position += 50000) {
+      int action = position == SAMPLE_SIZE ? BZ_FINISH : BZ_FLUSH;
+      int ret;
+      strm.next_in = input + position;
+      strm.avail_in = position == SAMPLE_SIZE ? 0 : 50000;
+      while (strm.avail_in > 0) {
+         strm.next_out = encoded + encoded_length;
+         strm.avail_out = 1000;
+         CHECK(BZ2_bzCompress(&strm, BZ_RUN) == BZ_RUN_OK);
+         encoded_length += 1000 - strm.avail_out;
+      }
+      do {
+         strm.next_out = output;
+         strm.avail_out = sizeof(output);
+         ret = BZ2_bzCompress(&strm, action);
+         memcpy(encoded + encoded_length, output, sizeof(output) - strm.avail_out);
+         encoded_length += sizeof(output) - strm.avail_out;
+      } while (ret == BZ_FLUSH_OK || ret == BZ_FINISH_OK);
+      CHECK(ret == (action == BZ_FINISH ? BZ_STREAM_END : BZ_RUN_OK));
+   }
+   CHECK(strm.total_in_lo32 == SAMPLE_SIZE && strm.total_in_hi32 == 0);
+   CHECK(strm.total_out_lo32 == encoded_length);
+   CHECK(BZ2_bzCompress(&strm, BZ_RUN) == BZ_SEQUENCE_ERROR);
+   CHECK(BZ2_bzCompressEnd(&strm) == BZ_OK);
+
+   /* decompress using small pieces of input and output */
+   unsigned int consumed = 0;
+   unsigned int decoded_length = 0;
+   int ret = BZ_OK;
+   CHECK(BZ2_bzDecompressInit(&strm, 0, 0) == BZ_OK);
+   while (ret == BZ_OK) {
+      unsigned int available = encoded_length - consumed < 333 ? encoded_length - consumed : 333;
+      strm.next_in = encoded + consumed;
+      strm.avail_in = available;
+      strm.next_out = output;
+      strm.avail_out = sizeof(output);
+      ret = BZ2_bzDecompress(&strm);
+      consumed += available - strm.avail_in;
+      CHECK(decoded_length + sizeof(output) - strm.avail_out <= SAMPLE_SIZE);
+      memcpy(decoded + decoded_length, output, sizeof(output) - strm.avail_out);
+      decoded_length += sizeof(output) - strm.avail_out;
+   }
+   CHECK(ret == BZ_STREAM_END);
+   CHECK(consumed == encoded_length);
+   CHECK(decoded_length == SAMPLE_SIZE);
+   CHECK(memcmp(decoded, input, SAMPLE_SIZE) == 0);
+   CHECK(BZ2_bzDecompressEnd(&strm) == BZ_OK);
+   CHECK(BZ2_bzDecompressEnd(&strm) == BZ_PARAM_ERROR);
+
+   free(encoded);
+   free(decoded);
+}
+
+static void test_files(char *input)
+{
+   int bzerror;
+   unsigned int nbytes_in, nbytes_out;
+   char trailer[] = "trailer";
+   FILE *f = tmpfile();
+   CHECK(f != NULL);
+
+   BZFILE *b = BZ2_bzWriteOpen(&bzerror, f, 5, 0, 0);
+   CHECK(bzerror == BZ_OK);
+   for (int position = 0; position < SAMPLE_SIZE; position += 10000) {
+      BZ2_bzWrite(&bzerror, b, input + position, 10000);
+      CHECK(bzerror == BZ_OK);
+   }
+   BZ2_bzWriteClose(&bzerror, b, 0, &nbytes_in, &nbytes_out);
+   CHECK(bzerror == BZ_OK);
+   CHECK(nbytes_in == SAMPLE_SIZE);
+   CHECK(fwrite(trailer, 1, strlen(trailer), f) == strlen(trailer));
+   rewind(f);
+
+   char *decoded = malloc(SAMPLE_SIZE + 1);
+   int decoded_length = 0;
+   b = BZ2_bzReadOpen(&bzerror, f, 0, 0, NULL, 0);
+   CHECK(bzerror == BZ_OK);
+   while (bzerror == BZ_OK) {
+      decoded_length += BZ2_bzRead(&bzerror, b, decoded + decoded_length, 777);
+   }
+   CHECK(bzerror == BZ_STREAM_END);
+   CHECK(decoded_length == SAMPLE_SIZE);
+   CHECK(memcmp(decoded, input, SAMPLE_SIZE) == 0);
+
+   void *unused;
+   int unused_length;
+   BZ2_bzReadGetUnused(&bzerror, b, &unused, &unused_length);
+   CHECK(bzerror == BZ_OK);
+   CHECK(unused_length == (int)strlen(trailer));
+   CHECK(memcmp(unused, trailer, unused_length) == 0);
+   BZ2_bzReadClose(&bzerror, b);
+   CHECK(bzerror == BZ_OK);
+
+   fclose(f);
+   free(decoded);
+}
+
+static char *read_file(const char *path, long *length)
+{
+   FILE *f = fopen(path, "rb");
+   CHECK(f != NULL);
+   CHECK(fseek(f, 0, SEEK_END) == 0);
+   *length = ftell(f);
+   rewind(f);
+   char *data = malloc(*length + 1);
+   CHECK(fread(data, 1, *length, f) == (size_t)*length);
+   fclose(f);
+   return data;
+}
+
+static void test_reference_files(const char *path, const char *compressed_path, const char *output_path)
+{
+   long length;
+   char *expected = read_file(path, &length);
+   char *decoded = malloc(length + 1);
+   long decoded_length = 0;
+   int read;
+
+   BZFILE *b = BZ2_bzopen(compressed_path, "r");
+   CHECK(b != NULL);
+   while ((read = BZ2_bzread(b, decoded + decoded_length, length + 1 - decoded_length)) > 0) {
+      decoded_length += read;
+   }
+   CHECK(read == 0);
+   BZ2_bzclose(b);
+   CHECK(decoded_length == length);
+   CHECK(memcmp(decoded, expected, length) == 0);
+
+   b = BZ2_bzopen(output_path, "w9");
+   CHECK(b != NULL);
+   CHECK(BZ2_bzwrite(b, expected, length) == length);
+   BZ2_bzclose(b);
+
+   free(expected);
+   free(decoded);
+}
+
+int main(int argc, char **argv)
+{
+   char *input = sample();
+   CHECK(strncmp(BZ2_bzlibVersion(), "1.0.8", 5) == 0);
+   test_buff_to_buff(input);
+   test_streams(input);
+   test_files(input);
+   if (argc == 4) {
+      test_reference_files(argv[1], argv[2], argv[3]);
+   }
+   free(input);
+   printf("all tests passed\n");
+   return 0;
+}
@@ -1,9 +1,11 @@
-use libribzip2::stream::{decode_stream, encode_stream};
-use libribzip2::EncodingStrategy;
-use num_cpus;
+use libribzip2::stream::{
+    recover, BlockProgress, BlockSize, CorruptionPolicy, DecoderBuilder, EncoderBuilder,
+};
+use libribzip2::{DecodeError, EncodingStrategy};
 use std::fmt;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
 use std::{ffi::OsString, io::BufWriter};
 use structopt::StructOpt;
 
@@ -12,15 +14,101 @@ enum Opt {
     Decompress {
         #[structopt(parse(from_os_str), required = true)]
         input: Vec<PathBuf>,
+        /// Stop after the first stream of concatenated streams
+        #[structopt(long)]
+        single_stream: bool,
+        #[structopt(long)]
+        threads: Option<usize>,
+        /// Use less memory like bunzip2 -s, always decodes with a single thread
+        #[structopt(short, long)]
+        small: bool,
+        /// Show the progress of every file on stderr
+        #[structopt(long)]
+        progress: bool,
+        /// What to do with blocks failing their checksum: fail, skip, zero-fill, unverified
+        /// or marker:TEXT to write TEXT instead
+        #[structopt(long, default_value = "fail", parse(try_from_str = parse_corruption_policy))]
+        on_crc_error: CorruptionPolicy,
+        /// Fail once a file decodes to more bytes, with an optional suffix K, M or G
+        #[structopt(long, parse(try_from_str = parse_size))]
+        max_output: Option<u64>,
+        /// Fail once the output exceeds the compressed input read by this factor
+        #[structopt(long)]
+        max_ratio: Option<u64>,
+        /// Fail once a file contains more blocks
+        #[structopt(long)]
+        max_blocks: Option<usize>,
+        /// Fail once a file contains more concatenated streams
+        #[structopt(long)]
+        max_streams: Option<usize>,
+        /// Limit the memory of the blocks being decoded, with an optional suffix K, M or G
+        #[structopt(long, parse(try_from_str = parse_size))]
+        max_memory: Option<u64>,
     },
     Compress {
         #[structopt(parse(from_os_str), required = true)]
         input: Vec<PathBuf>,
         #[structopt(long)]
         threads: Option<usize>,
+        /// Block size from 1 (100k) to 9 (900k)
+        #[structopt(long, default_value = "9", parse(try_from_str = parse_block_size))]
+        block_size: BlockSize,
+        /// Write the location of every block into a sidecar file with the extension .bz2.idx
+        #[structopt(long)]
+        index: bool,
+        /// Show the progress of every file on stderr
+        #[structopt(long)]
+        progress: bool,
         #[structopt(subcommand)]
         encoding_options: Option<EncodingOptions>,
     },
+    /// Extract the intact blocks of damaged files like bzip2recover, every block is written
+    /// into a file of its own named rec00001file.bz2 and so on
+    Recover {
+        #[structopt(parse(from_os_str), required = true)]
+        input: Vec<PathBuf>,
+        /// Decode the intact blocks into a single file instead
+        #[structopt(long)]
+        decode: bool,
+    },
+}
+
+fn parse_block_size(level: &str) -> Result<BlockSize, String> {
+    level
+        .parse()
+        .ok()
+        .and_then(BlockSize::new)
+        .ok_or_else(|| format!("invalid block size {}, expected 1 to 9", level))
+}
+
+fn parse_size(size: &str) -> Result<u64, String> {
+    let (number, factor) = match size.char_indices().last() {
+        Some((index, 'K' | 'k')) => (&size[..index], 1 << 10),
+        Some((index, 'M' | 'm')) => (&size[..index], 1 << 20),
+        Some((index, 'G' | 'g')) => (&size[..index], 1 << 30),
+        _ => (size, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(factor))
+        .ok_or_else(|| format!("invalid size {}, expected e.g. 500M", size))
+}
+
+fn parse_corruption_policy(policy: &str) -> Result<CorruptionPolicy, String> {
+    match policy {
+        "fail" => Ok(CorruptionPolicy::Fail),
+        "skip" => Ok(CorruptionPolicy::Skip),
+        "zero-fill" => Ok(CorruptionPolicy::ZeroFill),
+        "unverified" => Ok(CorruptionPolicy::EmitUnverified),
+        _ => match policy.strip_prefix("marker:") {
+            Some(marker) => Ok(CorruptionPolicy::Marker(marker.as_bytes().to_vec())),
+            None => Err(format!(
+                "invalid policy {}, expected fail, skip, zero-fill, unverified or marker:TEXT",
+                policy
+            )),
+        },
+    }
 }
 
 #[derive(StructOpt, Clone, Copy)]
@@ -38,6 +126,7 @@ pub(crate) enum EncodingOptions {
 pub enum FileError {
     DuplicateError(PathBuf),
     IoError(std::io::Error),
+    DecodeError(PathBuf, DecodeError),
 }
 
 impl fmt::Display for FileError {
@@ -47,6 +136,9 @@ impl fmt::Display for FileError {
                 write!(f, "Output file {} already exists", file_path.display())
             }
             FileError::IoError(io_error) => write!(f, "{}", io_error),
+            FileError::DecodeError(file_path, decode_error) => {
+                write!(f, "{}: {}", file_path.display(), decode_error)
+            }
         }
     }
 }
@@ -63,29 +155,114 @@ fn create_file(file_path: &PathBuf) -> Result<File, FileError> {
     if file_path.exists() {
         return Err(FileError::DuplicateError(file_path.clone()));
     }
-    let file = File::create(&file_path)?;
+    let file = File::create(file_path)?;
     Ok(file)
 }
 
 fn open_file(file_path: &PathBuf) -> Result<File, FileError> {
-    let file = File::open(&file_path)?;
+    let file = File::open(file_path)?;
     Ok(file)
 }
 
+/// Display the progress of `file_path`, whose size is `file_size`, in a line on stderr which
+/// is rewritten after every block.
+fn show_progress(file_path: &Path, file_size: u64) -> impl Fn(&BlockProgress) + Send + Sync {
+    let name = file_path.display().to_string();
+    move |progress| {
+        let percent = match file_size {
+            0 => 100.0,
+            size => (progress.input_bytes as f64 * 100.0 / size as f64).min(100.0),
+        };
+        let seconds = progress.elapsed.as_secs_f64().max(0.001);
+        eprint!(
+            "\r{}: {:5.1}%, block {}, {:.1} MB/s",
+            name,
+            percent,
+            progress.block_index + 1,
+            progress.input_bytes as f64 / seconds / 1_000_000.0
+        );
+    }
+}
+
 fn try_main(opt: Opt) -> Result<(), FileError> {
     match opt {
-        Opt::Decompress { input } => {
+        Opt::Decompress {
+            input,
+            single_stream,
+            threads,
+            small,
+            progress,
+            on_crc_error,
+            max_output,
+            max_ratio,
+            max_blocks,
+            max_streams,
+            max_memory,
+        } => {
+            let mut decoder_builder = DecoderBuilder::new()
+                .multi_stream(!single_stream)
+                .num_threads(threads.unwrap_or(num_cpus::get()))
+                .small(small)
+                .corruption_policy(on_crc_error);
+            if let Some(max_output) = max_output {
+                decoder_builder = decoder_builder.max_output(max_output);
+            }
+            if let Some(max_ratio) = max_ratio {
+                decoder_builder = decoder_builder.max_ratio(max_ratio);
+            }
+            if let Some(max_blocks) = max_blocks {
+                decoder_builder = decoder_builder.max_blocks(max_blocks);
+            }
+            if let Some(max_streams) = max_streams {
+                decoder_builder = decoder_builder.max_streams(max_streams);
+            }
+            if let Some(max_memory) = max_memory {
+                decoder_builder =
+                    decoder_builder.max_memory(usize::try_from(max_memory).unwrap_or(usize::MAX));
+            }
             for file_name in input {
                 let mut in_file = open_file(&file_name)?;
                 let mut out_file_name = file_name.clone();
                 out_file_name.set_extension(OsString::from(""));
                 let out_file = create_file(&out_file_name)?;
-                decode_stream(&mut in_file, out_file).unwrap();
+                let name = file_name.display().to_string();
+                let mut decoder_builder = decoder_builder.clone().on_corruption(move |incident| {
+                    eprintln!(
+                        "{}: block {} at bit {} failed its checksum (expected {:#010x}, got {:#010x}), {} bytes at {} affected",
+                        name,
+                        incident.block_number,
+                        incident.bit_offset,
+                        incident.expected_crc,
+                        incident.actual_crc,
+                        incident.decoded_bytes,
+                        incident.output_offset
+                    )
+                });
+                if progress {
+                    let file_size = in_file.metadata()?.len();
+                    decoder_builder =
+                        decoder_builder.on_progress(show_progress(&file_name, file_size));
+                }
+                let result = decoder_builder.decode_stream(&mut in_file, out_file);
+                if progress {
+                    eprintln!();
+                }
+                let summary =
+                    result.map_err(|err| FileError::DecodeError(file_name.clone(), err))?;
+                if summary.trailing_garbage {
+                    eprintln!(
+                        "{}: trailing garbage after EOF ignored",
+                        file_name.display()
+                    );
+                }
             }
         }
         Opt::Compress {
             input,
             threads,
+            block_size,
+            index,
+            progress,
             encoding_options,
         } => {
             for file_name in input {
@@ -108,6 +285,13 @@ fn try_main(opt: Opt) -> Result<(), FileError> {
 
                 let out_file = create_file(&out_file_name)?;
                 let mut out_file = BufWriter::new(out_file);
+                let mut index_file_name = out_file_name.clone().into_os_string();
+                index_file_name.push(".idx");
+                let index_file = if index {
+                    Some(create_file(&PathBuf::from(index_file_name))?)
+                } else {
+                    None
+                };
                 let encoding_strategy = match encoding_options {
                     Some(EncodingOptions::Single) | None => EncodingStrategy::Single,
                     Some(EncodingOptions::KMeans {
@@ -119,7 +303,62 @@ fn try_main(opt: Opt) -> Result<(), FileError> {
                     },
                 };
                 let threads_val = threads.unwrap_or(num_cpus::get());
-                encode_stream(&mut in_file, &mut out_file, threads_val, encoding_strategy);
+                let mut encoder_builder = EncoderBuilder::new()
+                    .num_threads(threads_val)
+                    .encoding_strategy(encoding_strategy)
+                    .block_size(block_size)
+                    .build_index(index);
+                if progress {
+                    let file_size = in_file.metadata()?.len();
+                    encoder_builder =
+                        encoder_builder.on_progress(show_progress(&file_name, file_size));
+                }
+                let result = encoder_builder.encode_stream(&mut in_file, &mut out_file);
+                if progress {
+                    eprintln!();
+                }
+                let summary = result?;
+                if let (Some(index_file), Some(index)) = (index_file, summary.index) {
+                    index.write_sidecar(BufWriter::new(index_file))?;
+                }
+            }
+        }
+        Opt::Recover { input, decode } => {
+            for file_name in input {
+                let in_file = BufReader::new(open_file(&file_name)?);
+                let summary = if decode {
+                    let mut out_file_name = file_name.clone();
+                    out_file_name.set_extension(OsString::from(""));
+                    let mut out_file = BufWriter::new(create_file(&out_file_name)?);
+                    let summary = recover(in_file, |block| out_file.write_all(block.decoded()))
+                        .map_err(|err| FileError::DecodeError(file_name.clone(), err))?;
+                    out_file.flush()?;
+                    summary
+                } else {
+                    let mut number = 0;
+                    recover(in_file, |block| {
+                        number += 1;
+                        let mut block_file_name = OsString::from(format!("rec{:05}", number));
+                        block_file_name.push(file_name.file_name().unwrap_or_default());
+                        let block_file = create_file(&file_name.with_file_name(block_file_name))
+                            .map_err(std::io::Error::other)?;
+                        block.write_stream(BufWriter::new(block_file))
+                    })
+                    .map_err(|err| FileError::DecodeError(file_name.clone(), err))?
+                };
+                for lost in summary.lost {
+                    eprintln!(
+                        "{}: bits {} to {} lost",
+                        file_name.display(),
+                        lost.start,
+                        lost.end
+                    );
+                }
+                eprintln!(
+                    "{}: {} intact blocks recovered",
+                    file_name.display(),
+                    summary.num_blocks
+                );
             }
         }
     }
@@ -19,6 +19,45 @@ rm temp/pepper.txt
 
 cargo run -- compress samples/idiot.txt
 mv samples/idiot.txt.bz2 temp/
-cargo run -- decompress temp/idiot.txt.bz2
+cargo run -- decompress --small temp/idiot.txt.bz2
+cmp samples/idiot.txt temp/idiot.txt
+rm temp/idiot.txt.bz2
+rm temp/idiot.txt
+
+cargo run -- compress --progress samples/idiot.txt
+mv samples/idiot.txt.bz2 temp/
+cargo run -- decompress --progress temp/idiot.txt.bz2
+cmp samples/idiot.txt temp/idiot.txt
+rm temp/idiot.txt.bz2
+rm temp/idiot.txt
+
+cargo run -- compress --block-size 1 samples/idiot.txt
+mv samples/idiot.txt.bz2 temp/
+printf '\x00\x00\x00' | dd of=temp/idiot.txt.bz2 bs=1 seek=100000 conv=notrunc
+cargo run -- recover temp/idiot.txt.bz2
+bunzip2 -t temp/rec*idiot.txt.bz2
+cargo run -- recover --decode temp/idiot.txt.bz2
+test -s temp/idiot.txt
+rm temp/rec*idiot.txt.bz2
+rm temp/idiot.txt.bz2
+rm temp/idiot.txt
+
+cargo run -- compress --block-size 1 samples/idiot.txt
+mv samples/idiot.txt.bz2 temp/
+# overwrite the checksum of the first block, which follows the stream header and block magic
+printf '\x00\x00\x00\x00' | dd of=temp/idiot.txt.bz2 bs=1 seek=10 conv=notrunc
+! cargo run -- decompress temp/idiot.txt.bz2
+rm -f temp/idiot.txt
+cargo run -- decompress --on-crc-error zero-fill temp/idiot.txt.bz2
+cmp -n 100000 -i 100000 samples/idiot.txt temp/idiot.txt
+rm temp/idiot.txt.bz2
+rm temp/idiot.txt
+
+cargo run -- compress samples/idiot.txt
+mv samples/idiot.txt.bz2 temp/
+! cargo run -- decompress --max-output 100K temp/idiot.txt.bz2
+rm temp/idiot.txt
+cargo run -- decompress --max-output 10M --max-ratio 100 --max-blocks 10 --max-streams 1 --max-memory 512M temp/idiot.txt.bz2
+cmp samples/idiot.txt temp/idiot.txt
 rm temp/idiot.txt.bz2
 rm temp/idiot.txt
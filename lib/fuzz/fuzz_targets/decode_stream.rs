@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use libribzip2::stream::{CorruptionPolicy, DecoderBuilder};
+
+// The first byte selects the configuration of the decoder, the rest is decoded.
+fuzz_target!(|data: &[u8]| {
+    let Some((&options, input)) = data.split_first() else {
+        return;
+    };
+    let policy = if options & 8 == 0 {
+        CorruptionPolicy::Fail
+    } else {
+        CorruptionPolicy::Skip
+    };
+    let _ = DecoderBuilder::new()
+        .num_threads(if options & 1 == 0 { 1 } else { 2 })
+        .small(options & 2 != 0)
+        .multi_stream(options & 4 == 0)
+        .corruption_policy(policy)
+        .decode_stream(input, std::io::sink());
+});
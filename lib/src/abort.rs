@@ -0,0 +1,100 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::{DecodeError, DecodeErrorKind};
+
+/// Cancels encoding or decoding from another thread.
+///
+/// Pass a clone of the handle to [crate::stream::EncoderBuilder::abort_handle] or
+/// [crate::stream::DecoderBuilder::abort_handle] and call [AbortHandle::abort] to stop all
+/// encoders and decoders built with it. They notice between blocks, and encoders also
+/// repeatedly while sorting a block, then stop their worker threads and fail with [Cancelled].
+#[derive(Debug, Clone, Default)]
+pub struct AbortHandle(Arc<AtomicBool>);
+
+impl AbortHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request all encoders and decoders using this handle to stop.
+    pub fn abort(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fail if [AbortHandle::abort] has been called.
+    pub(crate) fn check(&self) -> Result<(), Cancelled> {
+        if self.is_aborted() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Error of an encoder or decoder stopped using an [AbortHandle].
+///
+/// Encoders return it wrapped into a [std::io::Error], decoders as [DecodeErrorKind::Cancelled].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl Cancelled {
+    /// Whether `err` was returned because of cancellation, also if it was passed on by a
+    /// [std::io::Read] or [std::io::Write] implementation of this crate.
+    pub fn is_cause_of(err: &std::io::Error) -> bool {
+        match err.get_ref() {
+            Some(inner) if inner.is::<Cancelled>() => true,
+            Some(inner) => matches!(
+                inner.downcast_ref::<DecodeError>().map(DecodeError::kind),
+                Some(DecodeErrorKind::Cancelled)
+            ),
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "operation was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl From<Cancelled> for std::io::Error {
+    fn from(err: Cancelled) -> Self {
+        std::io::Error::other(err)
+    }
+}
+
+impl From<Cancelled> for DecodeErrorKind {
+    fn from(_: Cancelled) -> Self {
+        DecodeErrorKind::Cancelled
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn recognizes_cancellation() {
+        let handle = AbortHandle::new();
+        let clone = handle.clone();
+        assert!(handle.check().is_ok());
+        clone.abort();
+        assert_eq!(handle.check(), Err(Cancelled));
+
+        assert!(Cancelled::is_cause_of(&Cancelled.into()));
+        let decode_error = DecodeError::new(DecodeErrorKind::Cancelled, 1, 80);
+        assert!(Cancelled::is_cause_of(&decode_error.into()));
+        let decode_error = DecodeError::new(DecodeErrorKind::BadMagic, 1, 80);
+        assert!(!Cancelled::is_cause_of(&decode_error.into()));
+        assert!(!Cancelled::is_cause_of(&std::io::Error::other("other")));
+    }
+}
@@ -1,18 +1,24 @@
 use std::io::Read;
 
 use crate::bitwise::bitwriter::convert_to_number;
+use crate::error::DecodeErrorKind;
 
 use super::Bit;
 
-pub struct BitReaderImpl<'a, T: Read> {
-    byte_reader: &'a mut T,
+const BUFFER_SIZE: usize = 64 * 1024;
+
+pub struct BitReaderImpl<T: Read> {
+    byte_reader: T,
+    buffer: Vec<u8>,
+    buffer_position: usize,
+    bytes_consumed: u64,
     current_byte_cursor: u8,
     current_byte: Option<u8>,
 }
 
 pub trait BitReader {
-    fn read_bits(&mut self, num: usize) -> Result<Vec<Bit>, ()>;
-    fn read_bytes(&mut self, number: usize) -> Result<Vec<u8>, ()> {
+    fn read_bits(&mut self, num: usize) -> Result<Vec<Bit>, DecodeErrorKind>;
+    fn read_bytes(&mut self, number: usize) -> Result<Vec<u8>, DecodeErrorKind> {
         let mut out = vec![];
         for _ in 0..number {
             out.push(convert_to_number(&self.read_bits(8)?).try_into().unwrap());
@@ -25,49 +31,97 @@ impl<T> BitReader for &mut T
 where
     T: BitReader,
 {
-    fn read_bits(&mut self, num: usize) -> Result<Vec<Bit>, ()> {
+    fn read_bits(&mut self, num: usize) -> Result<Vec<Bit>, DecodeErrorKind> {
         (**self).read_bits(num)
     }
 }
 
-impl<'a, T: Read> BitReaderImpl<'a, T> {
-    pub fn from_reader(reader: &'a mut T) -> Self {
+impl<T: Read> BitReaderImpl<T> {
+    pub fn from_reader(reader: T) -> Self {
         BitReaderImpl {
             byte_reader: reader,
+            buffer: vec![],
+            buffer_position: 0,
+            bytes_consumed: 0,
             current_byte_cursor: 0u8,
             current_byte: None,
         }
     }
+
+    pub fn get_ref(&self) -> &T {
+        &self.byte_reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.byte_reader
+    }
+
+    pub fn into_inner(self) -> T {
+        self.byte_reader
+    }
+
+    /// Number of bits read so far.
+    pub fn position(&self) -> u64 {
+        match self.current_byte {
+            Some(_) => (self.bytes_consumed - 1) * 8 + u64::from(self.current_byte_cursor),
+            None => 0,
+        }
+    }
+
+    /// Skip the remaining bits of the current byte.
+    pub fn align_to_byte(&mut self) {
+        if self.current_byte.is_some() {
+            self.current_byte_cursor = 8;
+        }
+    }
+
+    /// Read the next byte from the internal buffer, refilling it from the underlying reader if required.
+    fn next_byte(&mut self) -> Result<u8, DecodeErrorKind> {
+        if self.buffer_position >= self.buffer.len() {
+            self.buffer.resize(BUFFER_SIZE, 0);
+            let size = loop {
+                match self.byte_reader.read(&mut self.buffer) {
+                    Ok(size) => break size,
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        self.buffer.clear();
+                        return Err(DecodeErrorKind::Io(err));
+                    }
+                }
+            };
+            self.buffer.truncate(size);
+            self.buffer_position = 0;
+            if size == 0 {
+                return Err(DecodeErrorKind::TruncatedInput);
+            }
+        }
+        let byte = self.buffer[self.buffer_position];
+        self.buffer_position += 1;
+        self.bytes_consumed += 1;
+        Ok(byte)
+    }
 }
 
-impl<'a, T: Read> BitReader for BitReaderImpl<'a, T> {
-    fn read_bits(&mut self, mut num: usize) -> Result<Vec<Bit>, ()> {
+impl<T: Read> BitReader for BitReaderImpl<T> {
+    fn read_bits(&mut self, mut num: usize) -> Result<Vec<Bit>, DecodeErrorKind> {
         let mut out = vec![];
-        let mut buf = [0u8; 1];
 
         while num > 0 {
             match self.current_byte {
-                Some(current_byte) => {
-                    if self.current_byte_cursor >= 8 {
-                        self.byte_reader.read_exact(&mut buf).map_err(|_| ())?;
-                        self.current_byte = Some(buf[0]);
-                        self.current_byte_cursor = 0;
+                Some(current_byte) if self.current_byte_cursor < 8 => {
+                    let offset = 8 - self.current_byte_cursor - 1;
+                    let current_bit = (current_byte >> offset) & 1;
+                    out.push(if current_bit == 0 {
+                        Bit::Zero
                     } else {
-                        let offset = 8 - self.current_byte_cursor - 1;
-                        let current_bit = (current_byte >> offset) & 1;
-                        out.push(if current_bit == 0 {
-                            Bit::Zero
-                        } else {
-                            Bit::One
-                        });
-
-                        self.current_byte_cursor += 1;
-                        num -= 1;
-                    }
+                        Bit::One
+                    });
+
+                    self.current_byte_cursor += 1;
+                    num -= 1;
                 }
-                None => {
-                    self.byte_reader.read_exact(&mut buf).map_err(|_| ())?;
-                    self.current_byte = Some(buf[0]);
+                _ => {
+                    self.current_byte = Some(self.next_byte()?);
                     self.current_byte_cursor = 0;
                 }
             }
@@ -76,6 +130,65 @@ impl<'a, T: Read> BitReader for BitReaderImpl<'a, T> {
     }
 }
 
+/// Reads bits from input held in memory, which consists of the bytes kept from earlier input
+/// followed by newly received bytes. Reading beyond the end fails with
+/// [DecodeErrorKind::TruncatedInput] without consuming anything, so that reading can be
+/// retried from a previous [SliceBitReader::position] once more input is available.
+pub struct SliceBitReader<'a> {
+    kept: &'a [u8],
+    received: &'a [u8],
+    position: u64,
+}
+
+impl<'a> SliceBitReader<'a> {
+    /// Start reading at bit `position` of `kept`.
+    pub fn new(kept: &'a [u8], received: &'a [u8], position: u64) -> Self {
+        SliceBitReader {
+            kept,
+            received,
+            position,
+        }
+    }
+
+    /// Number of bits read from the start of `kept`.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    fn len_bits(&self) -> u64 {
+        (self.kept.len() + self.received.len()) as u64 * 8
+    }
+
+    fn byte(&self, index: usize) -> u8 {
+        match index.checked_sub(self.kept.len()) {
+            Some(index) => self.received[index],
+            None => self.kept[index],
+        }
+    }
+}
+
+impl BitReader for SliceBitReader<'_> {
+    fn read_bits(&mut self, num: usize) -> Result<Vec<Bit>, DecodeErrorKind> {
+        if self.position + num as u64 > self.len_bits() {
+            return Err(DecodeErrorKind::TruncatedInput);
+        }
+        let out = (self.position..self.position + num as u64)
+            .map(
+                |bit| match (self.byte((bit / 8) as usize) >> (7 - bit % 8)) & 1 {
+                    0 => Bit::Zero,
+                    _ => Bit::One,
+                },
+            )
+            .collect();
+        self.position += num as u64;
+        Ok(out)
+    }
+}
+
 #[cfg(test)]
 pub struct InMemoryBitReader {
     bits: Vec<Bit>,
@@ -94,9 +207,9 @@ impl InMemoryBitReader {
 
 #[cfg(test)]
 impl BitReader for InMemoryBitReader {
-    fn read_bits(&mut self, num: usize) -> Result<Vec<Bit>, ()> {
-        if num + self.cursor > num + self.bits.len() {
-            Err(())
+    fn read_bits(&mut self, num: usize) -> Result<Vec<Bit>, DecodeErrorKind> {
+        if num + self.cursor > self.bits.len() {
+            Err(DecodeErrorKind::TruncatedInput)
         } else {
             let out = self.bits[self.cursor..self.cursor + num].to_vec();
             self.cursor += num;
@@ -118,8 +231,8 @@ mod test {
         let mut reader = BitReaderImpl::from_reader(&mut cursor);
         let bits = reader.read_bits(8);
         assert_eq!(
-            bits,
-            Ok(vec![
+            bits.unwrap(),
+            vec![
                 Bit::Zero,
                 Bit::Zero,
                 Bit::Zero,
@@ -128,7 +241,7 @@ mod test {
                 Bit::Zero,
                 Bit::Zero,
                 Bit::Zero
-            ])
+            ]
         );
     }
 
@@ -139,8 +252,8 @@ mod test {
         let mut reader = BitReaderImpl::from_reader(&mut cursor);
         let bits = reader.read_bits(8);
         assert_eq!(
-            bits,
-            Ok(vec![
+            bits.unwrap(),
+            vec![
                 Bit::Zero,
                 Bit::Zero,
                 Bit::Zero,
@@ -149,7 +262,7 @@ mod test {
                 Bit::Zero,
                 Bit::Zero,
                 Bit::Zero
-            ])
+            ]
         );
     }
 
@@ -162,8 +275,8 @@ mod test {
         let bits = reader.read_bits(8);
 
         assert_eq!(
-            bits,
-            Ok(vec![
+            bits.unwrap(),
+            vec![
                 Bit::Zero,
                 Bit::Zero,
                 Bit::Zero,
@@ -172,7 +285,7 @@ mod test {
                 Bit::Zero,
                 Bit::Zero,
                 Bit::Zero
-            ])
+            ]
         );
     }
 
@@ -185,8 +298,8 @@ mod test {
         let bits_2 = reader.read_bits(1);
 
         assert_eq!(
-            bits_1,
-            Ok(vec![
+            bits_1.unwrap(),
+            vec![
                 Bit::Zero,
                 Bit::Zero,
                 Bit::Zero,
@@ -196,9 +309,9 @@ mod test {
                 Bit::Zero,
                 Bit::Zero,
                 Bit::One,
-            ])
+            ]
         );
-        assert_eq!(bits_2, Ok(vec![Bit::One]));
+        assert_eq!(bits_2.unwrap(), vec![Bit::One]);
     }
 
     #[test]
@@ -224,4 +337,44 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    pub fn tracks_position() {
+        let vec = vec![42u8, 42u8, 2, 3];
+        let mut cursor = Cursor::new(&vec);
+        let mut reader = BitReaderImpl::from_reader(&mut cursor);
+        assert_eq!(reader.position(), 0);
+        let _ = reader.read_bits(3);
+        assert_eq!(reader.position(), 3);
+        let _ = reader.read_bits(13);
+        assert_eq!(reader.position(), 16);
+        let _ = reader.read_bits(1);
+        assert_eq!(reader.position(), 17);
+    }
+
+    #[test]
+    pub fn reads_across_slices_and_retries() {
+        let mut reader = SliceBitReader::new(&[0b1010_0000, 0xff], &[0x0f], 3);
+        assert_eq!(reader.read_bits(1).unwrap(), vec![Bit::Zero]);
+        assert_eq!(convert_to_number(&reader.read_bits(16).unwrap()), 0x0ff0);
+        assert_eq!(reader.position(), 20);
+        assert!(matches!(
+            reader.read_bits(5),
+            Err(DecodeErrorKind::TruncatedInput)
+        ));
+        assert_eq!(reader.position(), 20);
+        reader.set_position(0);
+        assert_eq!(reader.read_bytes(1).unwrap(), vec![0b1010_0000]);
+    }
+
+    #[test]
+    pub fn reports_truncated_input() {
+        let vec = vec![42u8];
+        let mut cursor = Cursor::new(&vec);
+        let mut reader = BitReaderImpl::from_reader(&mut cursor);
+        assert!(matches!(
+            reader.read_bits(9),
+            Err(DecodeErrorKind::TruncatedInput)
+        ));
+    }
 }
@@ -1,66 +1,92 @@
-use std::{io::Write, iter::repeat};
+use std::{io::Write, iter::repeat_n};
 
 use super::Bit;
 
-pub struct BitWriterImpl<'a, T>
+pub struct BitWriterImpl<T>
 where
     T: Write,
 {
     pending_bits: Vec<Bit>,
-    byte_writer: &'a mut T,
+    bytes_written: u64,
+    byte_writer: T,
 }
 
-impl<'a, T> BitWriterImpl<'a, T>
+impl<T> BitWriterImpl<T>
 where
     T: Write,
 {
-    pub fn from_writer(byte_writer: &'a mut T) -> Self {
+    pub fn from_writer(byte_writer: T) -> Self {
         BitWriterImpl {
             pending_bits: vec![],
+            bytes_written: 0,
             byte_writer,
         }
     }
+
+    pub fn get_ref(&self) -> &T {
+        &self.byte_writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.byte_writer
+    }
+
+    /// Number of complete bytes handed to the underlying writer.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Number of bits written so far, including those not handed to the underlying writer yet.
+    pub fn bits_written(&self) -> u64 {
+        self.bytes_written * 8 + self.pending_bits.len() as u64
+    }
+
+    pub fn into_inner(self) -> T {
+        self.byte_writer
+    }
 }
 
 pub trait BitWriter {
-    fn write_bits(&mut self, bits_to_write: &[Bit]) -> Result<(), ()>;
-    fn finalize(&mut self) -> Result<(), ()>;
+    fn write_bits(&mut self, bits_to_write: &[Bit]) -> std::io::Result<()>;
+    fn finalize(&mut self) -> std::io::Result<()>;
 }
 
 impl<W: BitWriter> BitWriter for &mut W {
-    fn write_bits(&mut self, bits_to_write: &[Bit]) -> Result<(), ()> {
+    fn write_bits(&mut self, bits_to_write: &[Bit]) -> std::io::Result<()> {
         (**self).write_bits(bits_to_write)
     }
 
-    fn finalize(&mut self) -> Result<(), ()> {
+    fn finalize(&mut self) -> std::io::Result<()> {
         (**self).finalize()
     }
 }
 
-impl<'a, T> BitWriter for BitWriterImpl<'a, T>
+impl<T> BitWriter for BitWriterImpl<T>
 where
     T: Write,
 {
-    fn write_bits(&mut self, bits_to_write: &[Bit]) -> Result<(), ()> {
-        self.pending_bits.append(&mut bits_to_write.to_vec());
+    fn write_bits(&mut self, bits_to_write: &[Bit]) -> std::io::Result<()> {
+        self.pending_bits.extend_from_slice(bits_to_write);
         let mut chunks = self.pending_bits.chunks_exact(8);
-        for chunk in &mut chunks {
-            let number = convert_to_number(chunk);
-            self.byte_writer.write(&[number as u8]).map_err(|_| ())?;
-        }
+        let bytes = (&mut chunks)
+            .map(|chunk| convert_to_number(chunk) as u8)
+            .collect::<Vec<_>>();
         self.pending_bits = chunks.remainder().to_vec();
+        self.byte_writer.write_all(&bytes)?;
+        self.bytes_written += bytes.len() as u64;
         Ok(())
     }
 
-    fn finalize(&mut self) -> Result<(), ()> {
+    fn finalize(&mut self) -> std::io::Result<()> {
         if self.pending_bits.is_empty() {
             return Ok(());
         }
         let mut trailing_zeros = vec![Bit::Zero; 8 - self.pending_bits.len()];
         self.pending_bits.append(&mut trailing_zeros);
         let byte = convert_to_number(&self.pending_bits);
-        self.byte_writer.write(&[byte as u8]).map_err(|_| ())?;
-
+        self.pending_bits.clear();
+        self.byte_writer.write_all(&[byte as u8])?;
+        self.bytes_written += 1;
         Ok(())
     }
 }
@@ -88,11 +114,7 @@ pub fn convert_to_code_pad_to_byte(mut input: u8) -> Vec<Bit> {
         input >>= 1;
         counter += 1;
     }
-    output.append(
-        &mut repeat(Bit::Zero)
-            .take(8 - counter as usize)
-            .collect::<Vec<_>>(),
-    );
+    output.append(&mut repeat_n(Bit::Zero, 8 - counter as usize).collect::<Vec<_>>());
     output.reverse();
     output
 }
@@ -115,11 +137,7 @@ pub fn convert_to_code_pad_to_15_bits(mut input: u16) -> Vec<Bit> {
         input >>= 1;
         counter += 1;
     }
-    output.append(
-        &mut repeat(Bit::Zero)
-            .take(15 - counter as usize)
-            .collect::<Vec<_>>(),
-    );
+    output.append(&mut repeat_n(Bit::Zero, 15 - counter as usize).collect::<Vec<_>>());
     output.reverse();
     output
 }
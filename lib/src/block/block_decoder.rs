@@ -2,63 +2,423 @@ use std::io::Write;
 
 use crate::{
     bitwise::{bitreader::BitReader, bitwriter::convert_to_number},
+    error::DecodeErrorKind,
     {
         bitwise::Bit,
         block::{
-            bwt::bwt_inverse::inverse_bwt,
+            bwt::bwt_inverse::{inverse_bwt, CompactInverseBwt},
             code_table::ReadDelta,
-            crc32::crc32,
-            huffman::{reader::ReadSymbols, CodeTable, HuffmanSymbol},
+            crc32::{crc32, Crc32},
+            huffman::{reader::ReadSymbols, CanonicalCodeTable, CodeTable, HuffmanSymbol},
             mtf::inverse_mtf,
+            randomization::{derandomize, Derandomizer},
             rle::inverse_rle,
             selectors::ReadUnary,
             symbol_map::GetSymbolTable,
-            zle::{decode_zle, ZleSymbol},
+            zle::{decode_zle, decode_zle_runs, ZleSymbol},
         },
     },
 };
 
-pub fn decode_block(mut reader: impl BitReader, mut writer: impl Write) -> Result<(), ()> {
-    let crc = convert_to_number(&reader.read_bits(32).unwrap())
-        .try_into()
-        .unwrap();
-    let _randomized = matches!(reader.read_bits(1)?[..], [Bit::One]);
-    let orig_ptr = convert_to_number(&reader.read_bits(24).unwrap());
-    let symbols = reader.get_symbol_table().unwrap();
-    let num_trees = convert_to_number(&reader.read_bits(3).unwrap());
-    let num_selectors = convert_to_number(&reader.read_bits(15).unwrap());
-    let selectors = reader.read_unary(num_selectors).unwrap();
-    let selectors = inverse_mtf(
-        &selectors,
-        &(0u8..num_trees as u8).into_iter().collect::<Vec<_>>(),
-    );
-    let mut trees = vec![];
-    for _ in 0..num_trees {
-        trees.push(reader.read_delta(symbols.len() + 2).unwrap());
+/// Maximum number of selectors used, enough for the largest block. Like the reference
+/// implementation further selectors are read but ignored.
+const MAX_SELECTORS: usize = 2 + 900_000 / 50;
+
+/// Decode a single block into `writer` and return its checksum. Blocks whose length before
+/// the inverse initial run length encoding exceeds `max_block_length` are rejected.
+pub fn decode_block(
+    reader: impl BitReader,
+    writer: impl Write,
+    max_block_length: usize,
+) -> Result<u32, DecodeErrorKind> {
+    parse_block(reader)?.reconstruct(writer, max_block_length)
+}
+
+/// A block whose bits are read, but which is not decoded yet.
+pub struct ParsedBlock {
+    crc: u32,
+    randomized: bool,
+    orig_ptr: usize,
+    symbols: Vec<u8>,
+    zle_input: Vec<ZleSymbol>,
+}
+
+/// Read all bits of a block following the block magic, without decoding it.
+pub fn parse_block(mut reader: impl BitReader) -> Result<ParsedBlock, DecodeErrorKind> {
+    let mut parser = BlockParser::default();
+    loop {
+        if let Some(block) = parser.step(&mut reader)? {
+            return Ok(block);
+        }
     }
-    let mut code_tables = vec![];
-    for tree in trees.iter() {
-        code_tables.push(CodeTable::<HuffmanSymbol<ZleSymbol>>::from_weights(tree).canonicalize());
+}
+
+/// Part of a block read by the next [BlockParser::step].
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    #[default]
+    Header,
+    SymbolMap,
+    TableCounts,
+    Selectors,
+    Tables,
+    Symbols,
+}
+
+/// Reads a block following the block magic one part at a time, so that parsing can be
+/// suspended between parts when the input is incomplete. A step either reads its part
+/// completely or fails without changing the parser.
+#[derive(Default)]
+pub struct BlockParser {
+    stage: Stage,
+    crc: u32,
+    randomized: bool,
+    orig_ptr: usize,
+    symbols: Vec<u8>,
+    num_trees: usize,
+    num_selectors: usize,
+    selectors: Vec<u8>,
+    code_tables: Vec<CanonicalCodeTable<HuffmanSymbol<ZleSymbol>>>,
+    zle_input: Vec<ZleSymbol>,
+    groups_read: usize,
+}
+
+impl BlockParser {
+    /// Read the next part of the block, returns the block once all of it was read. The parser
+    /// then starts over with the next block.
+    pub fn step(
+        &mut self,
+        mut reader: impl BitReader,
+    ) -> Result<Option<ParsedBlock>, DecodeErrorKind> {
+        match self.stage {
+            Stage::Header => {
+                let crc = convert_to_number(&reader.read_bits(32)?) as u32;
+                let randomized = matches!(reader.read_bits(1)?[..], [Bit::One]);
+                let orig_ptr = convert_to_number(&reader.read_bits(24)?);
+                self.crc = crc;
+                self.randomized = randomized;
+                self.orig_ptr = orig_ptr;
+                self.stage = Stage::SymbolMap;
+            }
+            Stage::SymbolMap => {
+                let symbols = reader.get_symbol_table()?;
+                if symbols.is_empty() {
+                    return Err(DecodeErrorKind::InvalidHuffmanTable);
+                }
+                self.symbols = symbols;
+                self.stage = Stage::TableCounts;
+            }
+            Stage::TableCounts => {
+                let num_trees = convert_to_number(&reader.read_bits(3)?);
+                if !(2..=6).contains(&num_trees) {
+                    return Err(DecodeErrorKind::InvalidHuffmanTable);
+                }
+                let num_selectors = convert_to_number(&reader.read_bits(15)?);
+                if num_selectors == 0 {
+                    return Err(DecodeErrorKind::InvalidSelector);
+                }
+                self.num_trees = num_trees;
+                self.num_selectors = num_selectors;
+                self.stage = Stage::Selectors;
+            }
+            Stage::Selectors => {
+                let selector = reader.read_unary(1)?[0];
+                if usize::from(selector) >= self.num_trees {
+                    return Err(DecodeErrorKind::InvalidSelector);
+                }
+                self.selectors.push(selector);
+                if self.selectors.len() == self.num_selectors {
+                    self.selectors = inverse_mtf(
+                        &self.selectors,
+                        &(0u8..self.num_trees as u8).collect::<Vec<_>>(),
+                    );
+                    self.selectors.truncate(MAX_SELECTORS);
+                    self.stage = Stage::Tables;
+                }
+            }
+            Stage::Tables => {
+                let tree = reader.read_delta(self.symbols.len() + 2)?;
+                self.code_tables.push(
+                    CodeTable::<HuffmanSymbol<ZleSymbol>>::from_weights(&tree).canonicalize(),
+                );
+                if self.code_tables.len() == self.num_trees {
+                    self.stage = Stage::Symbols;
+                }
+            }
+            Stage::Symbols => {
+                // the block has to end before the selectors run out
+                let selector = *self
+                    .selectors
+                    .get(self.groups_read)
+                    .ok_or(DecodeErrorKind::InvalidSelector)?;
+                let table = &self.code_tables[usize::from(selector)];
+                let (mut symbols, end_of_block) = reader.read_symbols(table, 50)?;
+                self.zle_input.append(&mut symbols);
+                self.groups_read += 1;
+                if end_of_block {
+                    let parser = std::mem::take(self);
+                    return Ok(Some(ParsedBlock {
+                        crc: parser.crc,
+                        randomized: parser.randomized,
+                        orig_ptr: parser.orig_ptr,
+                        symbols: parser.symbols,
+                        zle_input: parser.zle_input,
+                    }));
+                }
+            }
+        }
+        Ok(None)
     }
+}
 
-    let mut zle_input = vec![];
-    for selector in selectors {
-        let table = &code_tables[usize::from(selector)];
-        zle_input.append(&mut reader.read_symbols(table, 50).unwrap());
+impl ParsedBlock {
+    /// The checksum stored in the block header.
+    pub fn crc(&self) -> u32 {
+        self.crc
     }
 
-    let mtf_input = decode_zle(&zle_input);
+    /// Decode the block into `writer` and return its checksum, see [decode_block].
+    pub fn reconstruct(
+        self,
+        mut writer: impl Write,
+        max_block_length: usize,
+    ) -> Result<u32, DecodeErrorKind> {
+        let expected_crc = self.crc;
+        let (decoded, computed_crc) = self.reconstruct_unverified(max_block_length, usize::MAX)?;
+        if computed_crc != expected_crc {
+            return Err(DecodeErrorKind::BlockCrcMismatch {
+                expected: expected_crc,
+                actual: computed_crc,
+            });
+        }
+        writer.write_all(&decoded)?;
+        Ok(computed_crc)
+    }
 
-    let bwt_input = inverse_mtf(&mtf_input, &symbols);
-    let rle_input = inverse_bwt(&bwt_input, orig_ptr);
-    let decoded = inverse_rle(&rle_input);
-    let computed_crc = crc32(&decoded);
-    if computed_crc != crc {
-        return Err(());
+    /// Decode the block without comparing it with the checksum in its header, returns the
+    /// decoded data and its checksum. Decoding stops early once the decoded data exceeds
+    /// `max_output` bytes, the data is then cut short at `max_output + 1` bytes.
+    pub fn reconstruct_unverified(
+        self,
+        max_block_length: usize,
+        max_output: usize,
+    ) -> Result<(Vec<u8>, u32), DecodeErrorKind> {
+        let mtf_input = decode_zle(&self.zle_input, max_block_length)?;
+
+        let bwt_input = inverse_mtf(&mtf_input, &self.symbols);
+        if self.orig_ptr >= bwt_input.len() {
+            return Err(DecodeErrorKind::OrigPtrOutOfRange);
+        }
+        let mut rle_input = inverse_bwt(&bwt_input, self.orig_ptr);
+        if self.randomized {
+            derandomize(&mut rle_input);
+        }
+        let decoded = inverse_rle(&rle_input, max_output);
+        let computed_crc = crc32(&decoded);
+        Ok((decoded, computed_crc))
+    }
+
+    /// Prepare decoding the block with little memory, see [SmallBlock].
+    pub fn into_small(self, max_block_length: usize) -> Result<SmallBlock, DecodeErrorKind> {
+        let mut data = Vec::with_capacity(max_block_length);
+        let mut dictionary = self.symbols;
+        // undo the zero length encoding and the move-to-front transform in one pass
+        decode_zle_runs(&self.zle_input, max_block_length, |index, count| {
+            let index = usize::from(index);
+            let value = dictionary[index];
+            dictionary.copy_within(..index, 1);
+            dictionary[0] = value;
+            data.resize(data.len() + count, u16::from(value));
+        })?;
+        drop(self.zle_input);
+        if self.orig_ptr >= data.len() {
+            return Err(DecodeErrorKind::OrigPtrOutOfRange);
+        }
+        Ok(SmallBlock {
+            expected_crc: self.crc,
+            inverse_bwt: CompactInverseBwt::new(data, self.orig_ptr),
+            derandomizer: self.randomized.then(Derandomizer::default),
+            previous: None,
+            equal_count: 0,
+            run: (0, 0),
+            crc: Crc32::new(),
+        })
+    }
+}
+
+/// A block decoded piecewise into buffers of the caller, like the small mode of the reference
+/// implementation.
+///
+/// The inverse Burrows-Wheeler transform takes 2.5 bytes per byte of the block instead of
+/// about 20 bytes for [ParsedBlock::reconstruct], and the initial run length encoding is undone
+/// while reading, so the decoded block is never held in memory.
+pub struct SmallBlock {
+    expected_crc: u32,
+    inverse_bwt: CompactInverseBwt,
+    derandomizer: Option<Derandomizer>,
+    /// State of the inverse initial run length encoding.
+    previous: Option<u8>,
+    equal_count: u8,
+    /// Byte and number of its repetitions still to be written.
+    run: (u8, usize),
+    crc: Crc32,
+}
+
+impl SmallBlock {
+    /// Decode the next part of the block into `buf`, returns the number of bytes written.
+    /// Once the block is complete 0 is returned.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut written = 0;
+        while written < buf.len() {
+            let (byte, count) = self.run;
+            if count > 0 {
+                let size = count.min(buf.len() - written);
+                buf[written..written + size].fill(byte);
+                self.run.1 -= size;
+                written += size;
+                continue;
+            }
+            let Some(mut byte) = self.inverse_bwt.next() else {
+                break;
+            };
+            if let Some(derandomizer) = self.derandomizer.as_mut() {
+                byte ^= derandomizer.next_mask();
+            }
+            match self.previous {
+                Some(previous) if self.equal_count == 3 => {
+                    // the run length byte never starts a new run
+                    self.run = (previous, usize::from(byte));
+                    self.equal_count = 0;
+                    self.previous = None;
+                    continue;
+                }
+                Some(previous) if previous == byte => self.equal_count += 1,
+                _ => self.equal_count = 0,
+            }
+            self.previous = Some(byte);
+            buf[written] = byte;
+            written += 1;
+        }
+        self.crc.update(&buf[..written]);
+        written
+    }
+
+    /// Verify the checksum of the block once it was read completely and return it.
+    pub fn finish(self) -> Result<u32, DecodeErrorKind> {
+        let computed_crc = self.crc.value();
+        if computed_crc != self.expected_crc {
+            return Err(DecodeErrorKind::BlockCrcMismatch {
+                expected: self.expected_crc,
+                actual: computed_crc,
+            });
+        }
+        Ok(computed_crc)
     }
-    writer.write(&decoded).map_err(|_| ())?;
-    Ok(())
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+    use crate::bitwise::bitreader::{BitReaderImpl, InMemoryBitReader};
+    use crate::bitwise::bitwriter::convert_to_code_pad_to_n_bits;
+    use crate::block::symbol_map::get_symbol_table;
+
+    /// A single block written by a bzip2 with randomisation enabled, as produced by versions
+    /// before 0.9.5, and accepted by the reference implementation.
+    const RANDOMISED_STREAM: [u8; 192] = [
+        0x42, 0x5a, 0x68, 0x31, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x3c, 0xd7, 0x05, 0x59, 0x80,
+        0x01, 0x8f, 0xdf, 0x80, 0x7c, 0x10, 0x60, 0x04, 0x00, 0x00, 0x80, 0x20, 0x40, 0x00, 0x2f,
+        0x6c, 0xde, 0x80, 0x30, 0x01, 0x38, 0x00, 0xa3, 0x43, 0x40, 0x00, 0x00, 0x14, 0x68, 0x68,
+        0x00, 0x00, 0x00, 0xa5, 0x28, 0x12, 0x6a, 0x31, 0xb5, 0x02, 0x7a, 0x68, 0x9d, 0x24, 0xcd,
+        0xf7, 0xcb, 0xa4, 0xda, 0x5b, 0x1b, 0x9c, 0x1c, 0x36, 0xcc, 0xeb, 0x13, 0x94, 0x9d, 0xd2,
+        0x72, 0x27, 0xb4, 0x4f, 0x98, 0x99, 0xed, 0x13, 0x04, 0xd4, 0x4d, 0xf7, 0x49, 0x82, 0x7d,
+        0xc4, 0xe6, 0x27, 0x59, 0x3d, 0x4c, 0x4c, 0xc9, 0x30, 0xc9, 0x99, 0x26, 0x25, 0xc3, 0x68,
+        0x9d, 0x62, 0x73, 0x3e, 0xb0, 0x8d, 0xea, 0xa7, 0x19, 0x2b, 0xf8, 0x13, 0xa6, 0x6e, 0xa9,
+        0xf5, 0x94, 0x34, 0x44, 0xfb, 0xf3, 0x89, 0xb4, 0x4d, 0x21, 0x3a, 0x89, 0xdc, 0x4c, 0xcd,
+        0x22, 0x78, 0x49, 0x89, 0x36, 0x89, 0x91, 0x3b, 0xa4, 0xee, 0x93, 0xc4, 0x4d, 0x77, 0x0f,
+        0xcc, 0x17, 0xec, 0x4d, 0x51, 0x38, 0x49, 0xeb, 0x13, 0xf4, 0x89, 0xfd, 0x89, 0xa4, 0x13,
+        0x9c, 0x76, 0x49, 0xc4, 0x4d, 0x28, 0x9d, 0xaa, 0x8b, 0x58, 0x9d, 0xa2, 0x68, 0x93, 0x54,
+        0x4f, 0xf8, 0xbb, 0x92, 0x29, 0xc2, 0x84, 0x81, 0xe6, 0xb8, 0x2a, 0xc8,
+    ];
+
+    fn randomised_sample() -> Vec<u8> {
+        (0..)
+            .flat_map(|line| {
+                let mut line_bytes =
+                    b"If Peter Piper picked a peck of pickled peppers, where is the peck? "
+                        .to_vec();
+                line_bytes.extend(std::iter::repeat_n(b'!', line % 9));
+                line_bytes.push(b'\n');
+                line_bytes
+            })
+            .take(3000)
+            .collect()
+    }
+
+    #[test]
+    pub fn decodes_randomised_block() {
+        // skip stream header and block magic
+        let mut reader = BitReaderImpl::from_reader(&RANDOMISED_STREAM[10..]);
+        let mut decoded = vec![];
+        let crc = decode_block(&mut reader, &mut decoded, 100_000).unwrap();
+        assert_eq!(crc, 0x3cd70559);
+        assert_eq!(decoded, randomised_sample());
+    }
+
+    #[test]
+    pub fn decodes_randomised_block_in_small_mode() {
+        let mut reader = BitReaderImpl::from_reader(&RANDOMISED_STREAM[10..]);
+        let mut small_block = parse_block(&mut reader)
+            .unwrap()
+            .into_small(100_000)
+            .unwrap();
+        let mut decoded = vec![];
+        let mut buf = [0u8; 100];
+        loop {
+            let size = small_block.read(&mut buf);
+            if size == 0 {
+                break;
+            }
+            decoded.extend_from_slice(&buf[..size]);
+        }
+        assert_eq!(small_block.finish().unwrap(), 0x3cd70559);
+        assert_eq!(decoded, randomised_sample());
+    }
+
+    /// A block of the single byte `a` with two code tables, in which RunA is encoded as 0 and
+    /// the end of block as 11, and `num_selectors` selectors followed by `symbols`.
+    fn single_byte_block(num_selectors: usize, symbols: &[Bit]) -> Vec<Bit> {
+        let mut bits = convert_to_code_pad_to_n_bits(crc32(b"a") as usize, 32);
+        bits.extend(convert_to_code_pad_to_n_bits(0, 25));
+        bits.extend(get_symbol_table(vec![b'a']));
+        bits.extend(convert_to_code_pad_to_n_bits(2, 3));
+        bits.extend(convert_to_code_pad_to_n_bits(num_selectors, 15));
+        bits.extend(vec![Bit::Zero; num_selectors]);
+        for _ in 0..2 {
+            // code lengths 1, 2 and 2 for RunA, RunB and the end of block: start at 1, stop,
+            // increment, stop, stop
+            bits.extend(convert_to_code_pad_to_n_bits(1, 5));
+            bits.extend([Bit::Zero, Bit::One, Bit::Zero, Bit::Zero, Bit::Zero]);
+        }
+        bits.extend_from_slice(symbols);
+        bits
+    }
+
+    #[test]
+    pub fn stops_at_end_of_block() {
+        let block = single_byte_block(3, &[Bit::Zero, Bit::One, Bit::One]);
+        let mut decoded = vec![];
+        let crc = decode_block(InMemoryBitReader::from_bits(&block), &mut decoded, 100).unwrap();
+        assert_eq!(crc, crc32(b"a"));
+        assert_eq!(decoded, b"a");
+    }
+
+    #[test]
+    pub fn rejects_block_exceeding_selectors() {
+        let block = single_byte_block(1, &[Bit::Zero; 60]);
+        assert!(matches!(
+            decode_block(InMemoryBitReader::from_bits(&block), vec![], 100),
+            Err(DecodeErrorKind::InvalidSelector)
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{
+    abort::{AbortHandle, Cancelled},
     bitwise::bitwriter::{
         convert_to_code_pad_to_15_bits, convert_to_code_pad_to_byte, convert_to_code_pad_to_bytes,
         convert_to_code_pad_to_n_bits,
@@ -17,18 +18,49 @@ use crate::{
     },
 };
 
+use std::time::{Duration, Instant};
+
 use super::symbol_statistics::{
     BlockWisePropabilityMap, EncodingStrategy, ReportedSymbols, SinglePropabilityMap,
 };
 
+/// Statistics of an encoded block, see [crate::stream::EncodeSummary::blocks].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BlockStats {
+    /// Number of uncompressed bytes in the block.
+    pub uncompressed_bytes: u64,
+    /// Length of the block after the initial run length encoding.
+    pub rle_bytes: usize,
+    /// Number of symbols after the move-to-front transform and the run length encoding of
+    /// zeros, without the end of block symbol.
+    pub symbols: usize,
+    /// Number of Huffman tables selected for at least one group of symbols.
+    pub tables_used: usize,
+    /// Number of groups of 50 symbols, each of which selects a Huffman table.
+    pub num_selectors: usize,
+    /// Length of the encoded block including its header.
+    pub compressed_bits: u64,
+    /// Time spent on the Burrows-Wheeler transform.
+    pub bwt_time: Duration,
+    /// Time spent on the following stages, from the move-to-front transform to Huffman coding.
+    pub entropy_time: Duration,
+}
+
+/// Encode a block whose checksum is `checksum`, returns its bits and statistics.
+/// The uncompressed size in the statistics is left to the caller.
 pub(crate) fn generate_block_data(
     checksum: u32,
-    rle_data: &Vec<u8>,
+    rle_data: &[u8],
     encoding_strategy: EncodingStrategy,
-) -> (Vec<Bit>, u32) {
+    abort: &AbortHandle,
+) -> Result<(Vec<Bit>, BlockStats), Cancelled> {
     let mut output = Vec::<Bit>::new();
 
-    let bwt_data = bwt(rle_data);
+    let started = Instant::now();
+    let bwt_data = bwt(rle_data, abort)?;
+    let bwt_time = started.elapsed();
+    let started = Instant::now();
     let mtf_data = mtf(&bwt_data.data);
     let (zle_data, frequencies) = match encoding_strategy {
         EncodingStrategy::BlockWise {
@@ -58,19 +90,22 @@ pub(crate) fn generate_block_data(
         .collect::<Vec<_>>();
     let mut selectors = create_selectors(&selected_tables);
     let num_tables = code_tables.len();
+    let tables_used = (0..num_tables as u8)
+        .filter(|table| selected_tables.contains(table))
+        .count();
     let mut symbol_map = get_symbol_table(mtf_data.used_symbols);
 
     let trees = code_tables
         .iter()
         .cloned()
-        .map(|code_table| encode_code_table(code_table))
+        .map(encode_code_table)
         .collect::<Vec<_>>();
 
     // block
-    output.append(&mut block_header(checksum, bwt_data.end_of_string as u32));
+    output.append(&mut block_header(checksum, bwt_data.end_of_string));
     output.append(&mut symbol_map);
 
-    output.append(&mut &mut convert_to_code_pad_to_n_bits(num_tables, 3));
+    output.append(&mut convert_to_code_pad_to_n_bits(num_tables, 3));
 
     output.append(&mut convert_to_code_pad_to_15_bits(selectors.1 as u16));
 
@@ -109,11 +144,21 @@ pub(crate) fn generate_block_data(
             .code
             .clone(),
     );
-    (output, checksum)
+    let stats = BlockStats {
+        uncompressed_bytes: 0,
+        rle_bytes: rle_data.len(),
+        symbols: zle_data.len(),
+        tables_used,
+        num_selectors: selectors.1,
+        compressed_bits: output.len() as u64,
+        bwt_time,
+        entropy_time: started.elapsed(),
+    };
+    Ok((output, stats))
 }
 
 pub(crate) fn block_header(crc: u32, orig_pointer: u32) -> Vec<Bit> {
-    let mut magic: Vec<Bit> = vec![0x31u8, 0x41u8, 0x59u8, 0x26u8, 0x53u8, 0x59u8]
+    let mut magic: Vec<Bit> = [0x31u8, 0x41u8, 0x59u8, 0x26u8, 0x53u8, 0x59u8]
         .iter()
         .flat_map(|x| convert_to_code_pad_to_byte(*x))
         .collect::<Vec<_>>();
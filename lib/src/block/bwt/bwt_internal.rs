@@ -1,9 +1,10 @@
 use super::{duval::rotate_duval, sais::build_suffix_array};
+use crate::abort::{AbortHandle, Cancelled};
 
-fn bwt_private(string: &[u8]) -> (Vec<u8>, usize) {
-    let (rotated, shift) = rotate_duval(&string);
+fn bwt_private(string: &[u8], abort: &AbortHandle) -> Result<(Vec<u8>, usize), Cancelled> {
+    let (rotated, shift) = rotate_duval(string);
 
-    let entries = build_suffix_array(&rotated);
+    let entries = build_suffix_array(&rotated, abort)?;
     let len = string.len();
     let bwt = entries
         .iter()
@@ -20,20 +21,21 @@ fn bwt_private(string: &[u8]) -> (Vec<u8>, usize) {
         .find(|(_, x)| x.index == (len - shift) % len)
         .unwrap()
         .0;
-    (bwt, orig_ptr)
+    Ok((bwt, orig_ptr))
 }
 
 /// Computes the Burrows-Wheeler-Transform of the input data without a sentinel value.
 /// It uses the duval algorithm to provide a lexicographically minimal rotation of the input string
 /// and passes this to the SAIS algorithm. The rotation makes sure that the BWT is computed
 /// correctly because the rotation is lexicographically minimal.
-pub fn bwt(input: &Vec<u8>) -> BwtData {
-    let res = bwt_private(&input);
+/// Fails if `abort` is triggered while sorting.
+pub fn bwt(input: &[u8], abort: &AbortHandle) -> Result<BwtData, Cancelled> {
+    let res = bwt_private(input, abort)?;
 
-    BwtData {
+    Ok(BwtData {
         data: res.0,
         end_of_string: res.1 as u32,
-    }
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -44,37 +46,37 @@ pub struct BwtData {
 
 #[cfg(test)]
 mod test {
-    use super::bwt;
+    use super::{bwt, AbortHandle};
 
     #[test]
     pub fn banana() {
-        let bwt_result = bwt(&b"banana".to_vec());
+        let bwt_result = bwt(b"banana", &AbortHandle::new()).unwrap();
         assert_eq!(bwt_result.data, b"nnbaaa".to_vec());
     }
 
     #[test]
     pub fn bananaa() {
-        let bwt_result = bwt(&b"bananaa".to_vec());
+        let bwt_result = bwt(b"bananaa", &AbortHandle::new()).unwrap();
         assert_eq!(bwt_result.data, b"nanbaaa".to_vec());
     }
 
     #[test]
     pub fn banana2() {
-        let bwt_result = bwt(&b"banana".to_vec());
+        let bwt_result = bwt(b"banana", &AbortHandle::new()).unwrap();
         assert_eq!(bwt_result.data, b"nnbaaa".to_vec());
         assert_eq!(bwt_result.end_of_string, 3);
     }
 
     #[test]
     pub fn longer_text() {
-        let bwt_result = bwt(&b"If Peter Piper picked a peck of pickled peppers, where's the peck of pickled peppers Peter Piper picked?????".to_vec());
+        let bwt_result = bwt(b"If Peter Piper picked a peck of pickled peppers, where's the peck of pickled peppers Peter Piper picked?????", &AbortHandle::new()).unwrap();
         assert_eq!(24, bwt_result.end_of_string);
         assert_eq!(b"fsrrdkkeaddrrffs,esd?????     eeiiiieeeehrppkllkppttpphppPPIootwppppPPcccccckk      iipp    eeeeeeeeer'ree  ".to_vec(), bwt_result.data);
     }
 
     #[test]
     pub fn banana3() {
-        let bwt_result = bwt(&b"bananaaar".to_vec());
+        let bwt_result = bwt(b"bananaaar", &AbortHandle::new()).unwrap();
         assert_eq!(bwt_result.data, b"nanbaraaa".to_vec());
         assert_eq!(bwt_result.end_of_string, 5);
     }
@@ -12,6 +12,92 @@ pub(crate) fn inverse_bwt(data: &[u8], orig_ptr: usize) -> Vec<u8> {
     out
 }
 
+/// Inverse Burrows-Wheeler transform using 2.5 bytes per byte of the block, as in the small
+/// mode of the reference implementation. Positions take 20 bits, stored as 16 bit words and
+/// packed 4 bit nibbles, and the byte at a position is found by searching the cumulative
+/// byte counts. Yields the bytes of the original block one at a time.
+pub(crate) struct CompactInverseBwt {
+    low: Vec<u16>,
+    high: Vec<u8>,
+    /// Number of bytes of the block less than each byte value.
+    counts: [u32; 257],
+    position: u32,
+    remaining: usize,
+}
+
+impl CompactInverseBwt {
+    /// Prepare the inverse transform of `data`, which holds a byte of the transformed block
+    /// in each word. `orig_ptr` has to be a position within `data`, which is at most 2^20 long.
+    pub(crate) fn new(data: Vec<u16>, orig_ptr: usize) -> Self {
+        debug_assert!(orig_ptr < data.len() && data.len() <= 1 << 20);
+        let mut counts = [0u32; 257];
+        for &byte in data.iter() {
+            counts[usize::from(byte) + 1] += 1;
+        }
+        for byte in 1..counts.len() {
+            counts[byte] += counts[byte - 1];
+        }
+        let mut inverse = CompactInverseBwt {
+            high: vec![0; data.len().div_ceil(2)],
+            remaining: data.len(),
+            low: data,
+            counts,
+            position: orig_ptr as u32,
+        };
+
+        // replace every byte by the position of its rotation in the sorted order
+        let mut next = counts;
+        for index in 0..inverse.remaining {
+            let byte = usize::from(inverse.low[index]);
+            inverse.set(index, next[byte]);
+            next[byte] += 1;
+        }
+
+        // reverse the links, so that following them yields the block from front to back
+        let orig_ptr = orig_ptr as u32;
+        let mut current = orig_ptr;
+        let mut next = inverse.get(current);
+        loop {
+            let following = inverse.get(next);
+            inverse.set(next as usize, current);
+            current = next;
+            next = following;
+            if current == orig_ptr {
+                break;
+            }
+        }
+        inverse
+    }
+
+    fn get(&self, index: u32) -> u32 {
+        let index = index as usize;
+        let high = (self.high[index / 2] >> (index % 2 * 4)) & 0xf;
+        u32::from(self.low[index]) | u32::from(high) << 16
+    }
+
+    fn set(&mut self, index: usize, value: u32) {
+        let shift = index % 2 * 4;
+        self.low[index] = value as u16;
+        self.high[index / 2] =
+            (self.high[index / 2] & !(0xf << shift)) | ((value >> 16) as u8) << shift;
+    }
+}
+
+impl Iterator for CompactInverseBwt {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let position = self.position;
+        let byte = self.counts[..256].partition_point(|&count| count <= position) - 1;
+        self.position = self.get(position);
+        Some(byte as u8)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -30,4 +116,14 @@ mod test {
         let res = inverse_bwt(transformed, orig_ptr);
         assert_eq!(res, original);
     }
+
+    #[test]
+    pub fn compact_matches_inverse_bwt() {
+        let transformed = b"fsrrdkkeaddrrffs,esd?????     eeiiiieeeehrppkllkppttpphppPPIootwppppPPcccccckk      iipp    eeeeeeeeer'ree  ";
+        for (data, orig_ptr) in [(&b"nnbaaa"[..], 3), (&b"x"[..], 0), (&transformed[..], 24)] {
+            let words = data.iter().map(|&byte| u16::from(byte)).collect();
+            let compact = CompactInverseBwt::new(words, orig_ptr).collect::<Vec<_>>();
+            assert_eq!(compact, inverse_bwt(data, orig_ptr));
+        }
+    }
 }
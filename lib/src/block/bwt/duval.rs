@@ -1,13 +1,19 @@
+/// Compute the start of the lexicographically minimal rotation by running
+/// Duval's Lyndon factorization on the (virtually) doubled input. If the
+/// minimal rotation is a repetition of Lyndon words, the start of the first one is
+/// returned.
 fn duval(input: &[u8]) -> usize {
-    let mut final_start = 0;
     let n = input.len();
+    let at = |position: usize| input[position % n];
+    let mut final_start = 0;
     let mut i = 0;
 
     while i < n {
+        final_start = i;
         let mut j = i + 1;
         let mut k = i;
-        while j < n && input[k] <= input[j] {
-            if input[k] < input[j] {
+        while j < 2 * n && at(k) <= at(j) {
+            if at(k) < at(j) {
                 k = i;
             } else {
                 k += 1;
@@ -15,12 +21,10 @@ fn duval(input: &[u8]) -> usize {
             j += 1;
         }
         while i <= k {
-            final_start = i;
-
             i += j - k;
         }
     }
-    return final_start;
+    final_start
 }
 
 /// Compute lexicographically minimal rotation using the duval algorithm.
@@ -41,12 +45,23 @@ mod test {
     #[test]
     pub fn rotates() {
         let rotated = rotate_duval(b"abacabab");
-        assert_eq!(rotated.0, b"ababacab")
+        assert_eq!(rotated.0, b"abababac")
     }
 
     #[test]
-    pub fn finds_final_lyndon_word() {
+    pub fn finds_minimal_rotation() {
         let rotated = rotate_duval(b"bananaa");
-        assert_eq!(rotated.1, 6)
+        assert_eq!(rotated.0, b"aabanan");
+        assert_eq!(rotated.1, 5)
+    }
+
+    #[test]
+    pub fn starts_at_first_of_repeated_lyndon_words() {
+        let mut input = b"aaaa".to_vec();
+        for _ in 0..3 {
+            input.extend_from_slice(b"\x00\x01\x02");
+        }
+        let rotated = rotate_duval(&input);
+        assert_eq!(rotated.1, 4);
     }
 }
@@ -1,3 +1,5 @@
+use crate::abort::{AbortHandle, Cancelled};
+
 #[derive(PartialEq, Eq, Clone)]
 enum SuffixType {
     L,
@@ -9,26 +11,40 @@ pub(crate) struct SuffixTableEntry {
     pub index: usize,
 }
 
-/// Build suffix array using the SAIS algorithm
-pub(crate) fn build_suffix_array(bytes: &[u8]) -> Vec<SuffixTableEntry> {
+/// Build suffix array using the SAIS algorithm.
+/// Cancellation is checked between the passes over the text.
+pub(crate) fn build_suffix_array(
+    bytes: &[u8],
+    abort: &AbortHandle,
+) -> Result<Vec<SuffixTableEntry>, Cancelled> {
     let data = bytes.iter().map(|x| *x as usize).collect::<Vec<usize>>();
-    return orchestrate_build_suffix_array(&data, u8::MAX as usize + 1)
-        .iter()
-        .map(|x| SuffixTableEntry { index: x.unwrap() })
-        .collect::<Vec<_>>();
+    Ok(
+        orchestrate_build_suffix_array(&data, u8::MAX as usize + 1, abort)?
+            .iter()
+            .map(|x| SuffixTableEntry { index: x.unwrap() })
+            .collect::<Vec<_>>(),
+    )
 }
 
-fn orchestrate_build_suffix_array(text: &[usize], alphabet_size: usize) -> Vec<Option<usize>> {
+fn orchestrate_build_suffix_array(
+    text: &[usize],
+    alphabet_size: usize,
+    abort: &AbortHandle,
+) -> Result<Vec<Option<usize>>, Cancelled> {
+    abort.check()?;
     let suffix_types = get_suffix_types(text);
     let bucket_sizes = get_bucket_sizes(text, alphabet_size);
     let n = text.len();
     let mut suffix_array = vec![None; n + 1];
 
     identify_lms_characters(&mut suffix_array, text, &suffix_types, &bucket_sizes);
+    abort.check()?;
     induction_sort_l(&mut suffix_array, text, &suffix_types, &bucket_sizes);
+    abort.check()?;
     induction_sort_s(&mut suffix_array, text, &suffix_types, &bucket_sizes);
+    abort.check()?;
     let summary = reduce_problem(&mut suffix_array, text, &suffix_types);
-    let summary_suffix_array = build_summary_suffix_array(&summary);
+    let summary_suffix_array = build_summary_suffix_array(&summary, abort)?;
 
     suffix_array = vec![None; n + 1];
 
@@ -39,12 +55,17 @@ fn orchestrate_build_suffix_array(text: &[usize], alphabet_size: usize) -> Vec<O
         &summary_suffix_array,
         &summary.offsets,
     );
+    abort.check()?;
     induction_sort_l(&mut suffix_array, text, &suffix_types, &bucket_sizes);
+    abort.check()?;
     induction_sort_s(&mut suffix_array, text, &suffix_types, &bucket_sizes);
-    suffix_array
+    Ok(suffix_array)
 }
 
-fn build_summary_suffix_array(summary: &ReducedProblem) -> Vec<Option<usize>> {
+fn build_summary_suffix_array(
+    summary: &ReducedProblem,
+    abort: &AbortHandle,
+) -> Result<Vec<Option<usize>>, Cancelled> {
     if summary.alphabet_size == summary.reduced_text.len() {
         let mut suffix_array = vec![Some(0); summary.reduced_text.len() + 1];
 
@@ -52,9 +73,9 @@ fn build_summary_suffix_array(summary: &ReducedProblem) -> Vec<Option<usize>> {
         for i in 1..summary.reduced_text.len() {
             suffix_array[summary.reduced_text[i] + 1] = Some(i);
         }
-        return suffix_array;
+        return Ok(suffix_array);
     }
-    orchestrate_build_suffix_array(&summary.reduced_text, summary.alphabet_size)
+    orchestrate_build_suffix_array(&summary.reduced_text, summary.alphabet_size, abort)
 }
 
 fn reduce_problem(
@@ -98,7 +119,7 @@ fn reduce_problem(
         j += 1;
     }
     ReducedProblem {
-        reduced_text: reduced_text.into_iter().map(|x| x).collect::<Vec<_>>(),
+        reduced_text: reduced_text.into_iter().collect::<Vec<_>>(),
         offsets,
         alphabet_size: current_name + 1,
     }
@@ -227,8 +248,8 @@ fn is_lms_character(index: usize, suffix_types: &[SuffixType]) -> bool {
     if index == 0 {
         return false;
     };
-    return suffix_types.get(index).unwrap() == &SuffixType::S
-        && suffix_types.get(index.checked_sub(1).unwrap()).unwrap() == &SuffixType::L;
+    suffix_types.get(index).unwrap() == &SuffixType::S
+        && suffix_types.get(index.checked_sub(1).unwrap()).unwrap() == &SuffixType::L
 }
 
 fn get_bucket_heads(bucket_sizes: &[usize]) -> Vec<usize> {
@@ -287,3 +308,18 @@ struct ReducedProblem {
     offsets: Vec<usize>,
     alphabet_size: usize,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn stops_when_aborted() {
+        let abort = AbortHandle::new();
+        let entries = build_suffix_array(b"banana", &abort).unwrap();
+        let indices = entries.iter().map(|entry| entry.index).collect::<Vec<_>>();
+        assert_eq!(indices, vec![6, 5, 3, 1, 0, 4, 2]);
+        abort.abort();
+        assert_eq!(build_suffix_array(b"banana", &abort), Err(Cancelled));
+    }
+}
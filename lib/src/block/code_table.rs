@@ -3,12 +3,13 @@ use crate::{
         bitreader::BitReader,
         bitwriter::{convert_to_code_pad_to_n_bits, convert_to_number},
     },
+    error::DecodeErrorKind,
     {bitwise::Bit, block::delta::DeltaSymbol},
 };
 
 use super::{
     delta::{encode_delta, DeltaEncoded},
-    huffman::CanonicalCodeTable,
+    huffman::{CanonicalCodeTable, MAX_CODE_LENGTH},
 };
 
 pub(crate) fn encode_code_table<T>(table: CanonicalCodeTable<T>) -> Vec<Bit> {
@@ -52,19 +53,22 @@ fn encode_bit_lengths(code_lengths: &[u8]) -> Vec<Bit> {
 }
 
 pub(crate) trait ReadDelta {
-    fn read_delta(&mut self, amount: usize) -> Result<Vec<u8>, ()>;
+    fn read_delta(&mut self, amount: usize) -> Result<Vec<u8>, DecodeErrorKind>;
 }
 
 impl<T> ReadDelta for T
 where
     T: BitReader,
 {
-    fn read_delta(&mut self, amount: usize) -> Result<Vec<u8>, ()> {
+    fn read_delta(&mut self, amount: usize) -> Result<Vec<u8>, DecodeErrorKind> {
         let mut out = vec![];
         let mut read = 0;
 
         let mut start = convert_to_number(&self.read_bits(5)?);
         loop {
+            if !(1..=MAX_CODE_LENGTH).contains(&start) {
+                return Err(DecodeErrorKind::InvalidHuffmanTable);
+            }
             match &self.read_bits(1)?[..] {
                 [Bit::One] => match &self.read_bits(1)?[..] {
                     [Bit::Zero] => start += 1,
@@ -88,7 +92,7 @@ mod test {
     use std::io::Cursor;
 
     use crate::bitwise::{
-        bitreader::BitReaderImpl,
+        bitreader::{BitReaderImpl, InMemoryBitReader},
         bitwriter::{BitWriter, BitWriterImpl},
     };
 
@@ -109,4 +113,24 @@ mod test {
 
         assert_eq!(lengths, read.unwrap());
     }
+
+    #[test]
+    pub fn rejects_invalid_code_length() {
+        // start value 1 followed by a decrement
+        let bits = vec![
+            Bit::Zero,
+            Bit::Zero,
+            Bit::Zero,
+            Bit::Zero,
+            Bit::One,
+            Bit::One,
+            Bit::One,
+            Bit::Zero,
+        ];
+        let mut bit_reader = InMemoryBitReader::from_bits(&bits);
+        assert!(matches!(
+            bit_reader.read_delta(1),
+            Err(DecodeErrorKind::InvalidHuffmanTable)
+        ));
+    }
 }
@@ -3,10 +3,13 @@ use crate::bitwise::Bit;
 
 use crate::block::symbol_statistics::IntoFrequencyTable;
 use crate::block::zle::ZleSymbol;
-use std::{fmt::Debug, iter::repeat};
+use std::{fmt::Debug, iter::repeat_n};
 
 use super::package_merge::compute_lis;
 
+/// Maximum length of a huffman code accepted by the original implementation.
+pub(crate) const MAX_CODE_LENGTH: usize = 20;
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
 pub(crate) enum HuffmanSymbol<T> {
     NormalSymbol(T),
@@ -70,7 +73,7 @@ pub(crate) fn compute_huffman(
         symbol: HuffmanSymbol::EoB,
     });
 
-    frequency_table.sort_by(|x, y| x.frequency.cmp(&y.frequency));
+    frequency_table.sort_by_key(|x| x.frequency);
 
     // Step 1 break ties between frequencies of symbols
     let mut last: Option<FrequencyTableEntry<_>> = None;
@@ -112,9 +115,7 @@ where
         self.0.sort();
         let mut iter = self.0.iter();
         let code_table_entry = iter.next().unwrap();
-        let mut last = repeat(Bit::Zero)
-            .take(code_table_entry.code)
-            .collect::<Vec<_>>();
+        let mut last = repeat_n(Bit::Zero, code_table_entry.code).collect::<Vec<_>>();
 
         canonical_code_table_entries.push(CanonicalCodeTableEntry {
             code: last.clone(),
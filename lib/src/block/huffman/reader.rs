@@ -1,15 +1,18 @@
 use crate::bitwise::{bitreader::BitReader, Bit};
 
 use crate::block::zle::ZleSymbol;
+use crate::error::DecodeErrorKind;
 
-use super::{CanonicalCodeTable, HuffmanSymbol};
+use super::{CanonicalCodeTable, HuffmanSymbol, MAX_CODE_LENGTH};
 
 pub(crate) trait ReadSymbols {
+    /// Read up to `max_number` symbols, stopping early at the end of block symbol. Returns the
+    /// symbols read and whether the end of the block was reached.
     fn read_symbols(
         &mut self,
         tree: &CanonicalCodeTable<HuffmanSymbol<ZleSymbol>>,
         max_number: usize,
-    ) -> Result<Vec<ZleSymbol>, ()>;
+    ) -> Result<(Vec<ZleSymbol>, bool), DecodeErrorKind>;
 }
 
 impl<T> ReadSymbols for T
@@ -20,7 +23,7 @@ where
         &mut self,
         table: &CanonicalCodeTable<HuffmanSymbol<ZleSymbol>>,
         max_number: usize,
-    ) -> Result<Vec<ZleSymbol>, ()> {
+    ) -> Result<(Vec<ZleSymbol>, bool), DecodeErrorKind> {
         let mut all_symbols = vec![];
         let mut current_symbol = vec![];
         let mut symbols_read = 0;
@@ -35,14 +38,16 @@ where
                         current_symbol.clear();
                         symbols_read += 1;
                     }
-                    HuffmanSymbol::EoB => break,
+                    HuffmanSymbol::EoB => return Ok((all_symbols, true)),
                 }
+            } else if current_symbol.len() > MAX_CODE_LENGTH {
+                return Err(DecodeErrorKind::InvalidHuffmanTable);
             }
             if symbols_read == max_number {
                 break;
             }
         }
-        Ok(all_symbols)
+        Ok((all_symbols, false))
     }
 }
 
@@ -122,7 +127,7 @@ mod test {
 
         let mut bit_reader = BitReaderImpl::from_reader(&mut cursor);
 
-        let code = bit_reader.read_symbols(&table, 5).unwrap();
+        let (code, end_of_block) = bit_reader.read_symbols(&table, 5).unwrap();
 
         let expected_code = vec![
             ZleSymbol::RunA,
@@ -131,5 +136,6 @@ mod test {
             ZleSymbol::Number(1),
         ];
         assert_eq!(code, expected_code);
+        assert!(end_of_block);
     }
 }
@@ -6,6 +6,7 @@ pub mod crc32;
 mod delta;
 mod huffman;
 mod mtf;
+mod randomization;
 pub mod rle;
 mod selectors;
 mod symbol_map;
@@ -44,12 +44,10 @@ fn bring_to_front_of_dict(position: u8, dict: &mut VecDeque<u8>) {
 }
 
 fn find_pos(i: u8, dict: &VecDeque<u8>) -> u8 {
-    let mut position = 0;
-    for dict_element in dict.iter() {
+    for (position, dict_element) in dict.iter().enumerate() {
         if *dict_element == i {
-            return position;
+            return position as u8;
         }
-        position += 1;
     }
     unreachable!()
 }
@@ -90,7 +88,7 @@ mod test {
         let input = b"nnbaaaa";
 
         let res: Vec<u8> = vec![2, 0, 2, 2, 0, 0, 0];
-        assert_eq!(mtf(&input.to_vec()).encoded, res);
+        assert_eq!(mtf(input).encoded, res);
     }
 
     #[test]
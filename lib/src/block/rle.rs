@@ -69,27 +69,37 @@ pub fn rle_augment(input: &[u8], counter: usize, last: Option<u8>) -> Vec<u8> {
     output
 }
 
-pub(crate) fn inverse_rle(input: &[u8]) -> Vec<u8> {
+/// Undo the initial run length encoding. Stops early once the output exceeds `max_length`
+/// bytes, the output is then cut short at `max_length + 1` bytes.
+pub(crate) fn inverse_rle(input: &[u8], max_length: usize) -> Vec<u8> {
     let mut output = vec![];
     let mut equal_count = 0;
-    let mut previous = None;
-    for el in input {
-        if let Some(&previous) = previous {
+    let mut previous: Option<u8> = None;
+    for &el in input {
+        if output.len() > max_length {
+            output.truncate(max_length + 1);
+            return output;
+        }
+        if let Some(previous_byte) = previous {
             if equal_count == 3 {
-                output.append(&mut vec![previous; usize::from(*el)]);
+                output.append(&mut vec![previous_byte; usize::from(el)]);
                 equal_count = 0;
-            } else if previous == *el {
+                // the run length byte never starts a new run
+                previous = None;
+                continue;
+            } else if previous_byte == el {
                 equal_count += 1;
-                output.push(*el);
+                output.push(el);
             } else {
-                output.push(*el);
+                output.push(el);
                 equal_count = 0;
             }
         } else {
-            output.push(*el);
+            output.push(el);
         }
         previous = Some(el);
     }
+    output.truncate(max_length.saturating_add(1));
     output
 }
 
@@ -234,11 +244,7 @@ mod test {
 
     #[test]
     pub fn max_block_length() {
-        let rle_result = rle(
-            &std::iter::repeat(3).take(255).collect::<Vec<u8>>(),
-            0,
-            None,
-        );
+        let rle_result = rle(&std::iter::repeat_n(3, 255).collect::<Vec<u8>>(), 0, None);
 
         let rle_data = rle_result.data;
         let rle_count = rle_result.counter;
@@ -255,11 +261,7 @@ mod test {
 
     #[test]
     pub fn more_than_max_block_length() {
-        let rle_result = rle(
-            &std::iter::repeat(3).take(256).collect::<Vec<u8>>(),
-            0,
-            None,
-        );
+        let rle_result = rle(&std::iter::repeat_n(3, 256).collect::<Vec<u8>>(), 0, None);
 
         let rle_data = rle_result.data;
         let rle_count = rle_result.counter;
@@ -275,11 +277,7 @@ mod test {
     }
     #[test]
     pub fn twice_max_block_length() {
-        let rle_result = rle(
-            &std::iter::repeat(3).take(510).collect::<Vec<u8>>(),
-            0,
-            None,
-        );
+        let rle_result = rle(&std::iter::repeat_n(3, 510).collect::<Vec<u8>>(), 0, None);
 
         let rle_data = rle_result.data;
         let rle_count = rle_result.counter;
@@ -297,7 +295,7 @@ mod test {
     #[test]
     pub fn inverse_rle_works() {
         assert_eq!(
-            inverse_rle(&[1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 3, 3, 3]),
+            inverse_rle(&[1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 3, 3, 3], usize::MAX),
             vec![1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 3, 3, 3]
         );
     }
@@ -305,8 +303,22 @@ mod test {
     #[test]
     pub fn inverse_mixed_sequences_with_and_without_length_2() {
         assert_eq!(
-            inverse_rle(&[1, 1, 1, 1, 0, 2, 2, 2]),
+            inverse_rle(&[1, 1, 1, 1, 0, 2, 2, 2], usize::MAX),
             vec![1, 1, 1, 1, 2, 2, 2]
         );
     }
+
+    #[test]
+    pub fn inverse_run_followed_by_byte_equal_to_run_length() {
+        assert_eq!(
+            inverse_rle(&[7, 7, 7, 7, 2, 2, 2, 2, 2], usize::MAX),
+            vec![7, 7, 7, 7, 7, 7, 2, 2, 2, 2]
+        );
+    }
+
+    #[test]
+    pub fn inverse_stops_beyond_max_length() {
+        assert_eq!(inverse_rle(&[1, 1, 1, 1, 255, 2], 10), vec![1; 11]);
+        assert_eq!(inverse_rle(&[1, 2, 3], 3), vec![1, 2, 3]);
+    }
 }
@@ -1,10 +1,11 @@
 use crate::bitwise::{bitreader::BitReader, Bit};
+use crate::error::DecodeErrorKind;
 
 use super::mtf::mtf;
 
 pub(crate) fn create_selectors(selectors: &[u8]) -> (Vec<Bit>, usize) {
     let mut out = vec![];
-    let selectors_mtf = mtf(&selectors);
+    let selectors_mtf = mtf(selectors);
 
     for selector in selectors_mtf.encoded {
         for _ in 0..selector as usize {
@@ -17,26 +18,30 @@ pub(crate) fn create_selectors(selectors: &[u8]) -> (Vec<Bit>, usize) {
 }
 
 pub(crate) trait ReadUnary {
-    fn read_unary(&mut self, amount: usize) -> Result<Vec<u8>, ()>;
+    fn read_unary(&mut self, amount: usize) -> Result<Vec<u8>, DecodeErrorKind>;
 }
 
 impl<T> ReadUnary for T
 where
     T: BitReader,
 {
-    fn read_unary(&mut self, amount: usize) -> Result<Vec<u8>, ()> {
+    fn read_unary(&mut self, amount: usize) -> Result<Vec<u8>, DecodeErrorKind> {
         let mut output = vec![];
         let mut current_symbol = 0u8;
         let mut symbol_count = 0;
         loop {
             match &self.read_bits(1)?[..] {
-                [Bit::One] => current_symbol += 1,
+                [Bit::One] => {
+                    current_symbol = current_symbol
+                        .checked_add(1)
+                        .ok_or(DecodeErrorKind::InvalidSelector)?
+                }
                 [Bit::Zero] => {
                     symbol_count += 1;
                     output.push(current_symbol);
                     current_symbol = 0;
                 }
-                _ => return Err(()),
+                _ => unreachable!(),
             }
             if symbol_count >= amount {
                 break;
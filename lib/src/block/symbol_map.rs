@@ -1,4 +1,5 @@
 use crate::bitwise::{bitreader::BitReader, Bit};
+use crate::error::DecodeErrorKind;
 
 pub(crate) fn get_symbol_table(table: Vec<u8>) -> Vec<Bit> {
     let mut table2 = table.clone();
@@ -24,7 +25,7 @@ pub(crate) fn get_symbol_table(table: Vec<u8>) -> Vec<Bit> {
         }
     }
 
-    for (_, used) in used_symbols_details.iter().enumerate() {
+    for used in used_symbols_details.iter() {
         match used {
             Some(detail) => {
                 out_region.push(Bit::One);
@@ -65,14 +66,14 @@ fn get_used_symbols_from_regions(regions: &[u8], input: Vec<Bit>) -> Vec<u8> {
 }
 
 pub(crate) trait GetSymbolTable {
-    fn get_symbol_table(&mut self) -> Result<Vec<u8>, ()>;
+    fn get_symbol_table(&mut self) -> Result<Vec<u8>, DecodeErrorKind>;
 }
 
 impl<T> GetSymbolTable for T
 where
     T: BitReader,
 {
-    fn get_symbol_table(&mut self) -> Result<Vec<u8>, ()> {
+    fn get_symbol_table(&mut self) -> Result<Vec<u8>, DecodeErrorKind> {
         let index = self.read_bits(16)?;
         let used_regions = get_used_regions(&index);
         let regions = self.read_bits(16 * used_regions.len())?;
@@ -81,9 +82,8 @@ where
 }
 
 #[cfg(test)]
-
 mod test {
-    use std::iter::repeat;
+    use std::iter::repeat_n;
 
     use super::*;
     #[test]
@@ -147,7 +147,7 @@ mod test {
     }
 
     fn zeros(num: usize) -> Vec<Bit> {
-        repeat(Bit::Zero).take(num).collect::<Vec<_>>()
+        repeat_n(Bit::Zero, num).collect::<Vec<_>>()
     }
 
     #[test]
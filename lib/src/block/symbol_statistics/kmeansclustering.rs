@@ -69,7 +69,7 @@ impl<'a> KMeansProblem<'a> {
                     .iter_mut()
                     .zip(data_point.iter())
                 {
-                    *entry = ((*entry as f32) * current_cluster_size as f32 + (*point as f32))
+                    *entry = ((*entry) * current_cluster_size as f32 + (*point as f32))
                         / (current_cluster_size as f32 + 1.0);
                 }
                 cluster_sizes[*assigment as usize] += 1;
@@ -3,11 +3,11 @@ use self::kmeansclustering::KMeansProblem;
 use super::zle::ZleSymbol;
 mod kmeansclustering;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 #[non_exhaustive]
 /// Strategy for using Huffman Tables
 ///
-/// * [EncodingStrategy::Single] - every block of 900k uses a single huffman code table
+/// * [EncodingStrategy::Single] - every block uses a single huffman code table
 /// * [EncodingStrategy::BlockWise] - usage of code tables in 50 byte chunks is optimized using Lloyd's algorithm with given parameters
 pub enum EncodingStrategy {
     BlockWise {
@@ -122,7 +122,7 @@ impl SymbolReporter for BlockWisePropabilityMap {
                 .means
                 .iter()
                 .map(|x| IntoFrequencyTable {
-                    frequencies: x.iter().map(|x| *x as usize).collect::<Vec<_>>(),
+                    frequencies: x.to_vec(),
                 })
                 .collect::<Vec<_>>(),
             selectors: tables.assignments,
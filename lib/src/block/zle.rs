@@ -1,6 +1,7 @@
 use std::cmp::Ordering;
 
 use super::symbol_statistics::{ReportedSymbols, SymbolReporter};
+use crate::error::DecodeErrorKind;
 
 #[derive(PartialEq, Clone, Debug, Hash, Eq)]
 pub(crate) enum ZleSymbol {
@@ -11,23 +12,7 @@ pub(crate) enum ZleSymbol {
 
 impl PartialOrd for ZleSymbol {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match self {
-            ZleSymbol::RunA => match other {
-                ZleSymbol::RunA => Some(Ordering::Equal),
-                ZleSymbol::RunB => Some(Ordering::Less),
-                ZleSymbol::Number(_) => Some(Ordering::Less),
-            },
-            ZleSymbol::RunB => match other {
-                ZleSymbol::RunA => Some(Ordering::Greater),
-                ZleSymbol::RunB => Some(Ordering::Equal),
-                ZleSymbol::Number(_) => Some(Ordering::Less),
-            },
-            ZleSymbol::Number(this_number) => match other {
-                ZleSymbol::RunA => Some(Ordering::Greater),
-                ZleSymbol::RunB => Some(Ordering::Greater),
-                ZleSymbol::Number(other_number) => this_number.partial_cmp(other_number),
-            },
-        }
+        Some(self.cmp(other))
     }
 }
 
@@ -108,26 +93,64 @@ pub(crate) fn zle_transform(
     (zle_result, symbol_reporter.finalize())
 }
 
-pub(crate) fn decode_zle(input: &[ZleSymbol]) -> Vec<u8> {
-    let mut output = vec![];
+/// Undo the zero length encoding. Fails if the result would exceed `max_length` bytes.
+pub(crate) fn decode_zle(
+    input: &[ZleSymbol],
+    max_length: usize,
+) -> Result<Vec<u8>, DecodeErrorKind> {
+    let mut output = Vec::with_capacity(max_length);
+    decode_zle_runs(input, max_length, |value, count| {
+        output.resize(output.len() + count, value)
+    })?;
+    Ok(output)
+}
+
+/// Undo the zero length encoding without collecting the result, `emit` is called with every
+/// value and the number of times it is repeated. Fails if the result would exceed `max_length` bytes.
+pub(crate) fn decode_zle_runs(
+    input: &[ZleSymbol],
+    max_length: usize,
+    mut emit: impl FnMut(u8, usize),
+) -> Result<(), DecodeErrorKind> {
+    let mut length = 0;
     let mut zeros = vec![];
     for element in input {
         match element {
             ZleSymbol::Number(element) => {
-                if !zeros.is_empty() {
-                    output.append(&mut vec![0u8; decode_zero_amount(&zeros)]);
-                    zeros.clear();
+                append_zeros(&mut length, &mut zeros, max_length, &mut emit)?;
+                if length == max_length {
+                    return Err(DecodeErrorKind::BlockTooLarge);
                 }
-                output.push(*element);
+                length += 1;
+                emit(*element, 1);
             }
             _ => zeros.push(element.clone()),
         }
     }
-    if !zeros.is_empty() {
-        output.append(&mut vec![0u8; decode_zero_amount(&zeros)]);
-        zeros.clear();
+    append_zeros(&mut length, &mut zeros, max_length, &mut emit)
+}
+
+fn append_zeros(
+    length: &mut usize,
+    zeros: &mut Vec<ZleSymbol>,
+    max_length: usize,
+    emit: &mut impl FnMut(u8, usize),
+) -> Result<(), DecodeErrorKind> {
+    if zeros.is_empty() {
+        return Ok(());
+    }
+    // n symbols encode at least 2^n - 1 zeros, long runs would overflow
+    if zeros.len() >= usize::BITS as usize - 1 {
+        return Err(DecodeErrorKind::BlockTooLarge);
+    }
+    let amount = decode_zero_amount(zeros);
+    if *length + amount > max_length {
+        return Err(DecodeErrorKind::BlockTooLarge);
     }
-    output
+    *length += amount;
+    emit(0, amount);
+    zeros.clear();
+    Ok(())
 }
 
 fn decode_zero_amount(input: &[ZleSymbol]) -> usize {
@@ -148,7 +171,6 @@ fn decode_zero_amount(input: &[ZleSymbol]) -> usize {
 }
 
 #[cfg(test)]
-
 mod test {
 
     use crate::block::symbol_statistics::SinglePropabilityMap;
@@ -242,7 +264,11 @@ mod test {
 
     #[test]
     fn decodes_zeros_and_numbers() {
-        let encoded = decode_zle(&[ZleSymbol::Number(1), ZleSymbol::RunA, ZleSymbol::RunA]);
+        let encoded = decode_zle(
+            &[ZleSymbol::Number(1), ZleSymbol::RunA, ZleSymbol::RunA],
+            10,
+        )
+        .unwrap();
         assert_eq!(encoded, vec![1, 0, 0, 0]);
     }
 
@@ -262,15 +288,33 @@ mod test {
 
     #[test]
     fn decodes_zeros_and_trailing_numbers() {
-        let encoded = decode_zle(&[
-            ZleSymbol::Number(1),
-            ZleSymbol::RunA,
-            ZleSymbol::RunA,
-            ZleSymbol::Number(2),
-        ]);
+        let encoded = decode_zle(
+            &[
+                ZleSymbol::Number(1),
+                ZleSymbol::RunA,
+                ZleSymbol::RunA,
+                ZleSymbol::Number(2),
+            ],
+            10,
+        )
+        .unwrap();
         assert_eq!(encoded, vec![1, 0, 0, 0, 2]);
     }
 
+    #[test]
+    fn rejects_output_exceeding_max_length() {
+        let input = [ZleSymbol::Number(1), ZleSymbol::RunA, ZleSymbol::RunA];
+        assert!(decode_zle(&input, 4).is_ok());
+        assert!(matches!(
+            decode_zle(&input, 3),
+            Err(DecodeErrorKind::BlockTooLarge)
+        ));
+        assert!(matches!(
+            decode_zle(&vec![ZleSymbol::RunB; 100], 1000),
+            Err(DecodeErrorKind::BlockTooLarge)
+        ));
+    }
+
     #[test]
     fn encodes_numbers_and_trailing_zeroes() {
         let encoded = zle_transform(vec![1, 0, 0, 0, 2, 0, 0], SinglePropabilityMap::create(3));
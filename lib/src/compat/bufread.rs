@@ -0,0 +1,5 @@
+//! I/O streams for wrapping `BufRead` types as encoders/decoders.
+//!
+//! These are the types of [super::read], which accept any reader and buffer internally.
+
+pub use super::read::{BzDecoder, BzEncoder, MultiBzDecoder};
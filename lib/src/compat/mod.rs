@@ -0,0 +1,73 @@
+//! Types named like those of the [bzip2](https://crates.io/crates/bzip2) crate, so that code using
+//! it can be migrated by replacing `bzip2::` with `libribzip2::compat::` in its `use` declarations.
+//!
+//! Differences to the original:
+//!
+//!  * decoders read ahead of the end of a stream, hence the position of the underlying reader
+//!    is not exactly at the end of the stream afterwards
+//!  * [read::MultiBzDecoder] ignores data following the last stream which is not a bzip2 stream
+//!  * [write::BzDecoder] decodes the data written to it on a separate thread
+
+pub mod bufread;
+pub mod read;
+pub mod write;
+
+use crate::stream::BlockSize;
+
+/// When compressing data, the compression level can be specified by a value in this struct.
+///
+/// The level is the block size in units of 100k, levels outside of 1 to 9 are clamped.
+#[derive(Copy, Clone, Debug)]
+pub struct Compression(u32);
+
+impl Compression {
+    /// Create a new compression spec with a specific numeric level (0-9).
+    pub fn new(level: u32) -> Compression {
+        Compression(level)
+    }
+
+    /// Compress with the smallest block size, as there is no uncompressed mode.
+    pub fn none() -> Compression {
+        Compression(0)
+    }
+
+    /// Optimize for the best speed of encoding.
+    pub fn fast() -> Compression {
+        Compression(1)
+    }
+
+    /// Optimize for the size of data being encoded.
+    pub fn best() -> Compression {
+        Compression(9)
+    }
+
+    /// Return the compression level as an integer.
+    pub fn level(&self) -> u32 {
+        self.0
+    }
+
+    pub(crate) fn block_size(&self) -> BlockSize {
+        BlockSize::new(self.0.clamp(1, 9) as u8).unwrap()
+    }
+}
+
+impl Default for Compression {
+    /// Choose the default compression, a balance between speed and size.
+    fn default() -> Compression {
+        Compression(6)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn maps_levels_to_block_sizes() {
+        assert_eq!(Compression::none().block_size().level(), 1);
+        assert_eq!(Compression::fast().block_size().level(), 1);
+        assert_eq!(Compression::default().block_size().level(), 6);
+        assert_eq!(Compression::best().block_size().level(), 9);
+        assert_eq!(Compression::new(42).block_size().level(), 9);
+    }
+}
@@ -0,0 +1,243 @@
+//! Reader-based compression/decompression streams
+
+use std::io::{Read, Write};
+
+use crate::stream::{Bz2Decoder, Bz2Encoder, DecoderBuilder, EncoderBuilder};
+
+use super::Compression;
+
+const INPUT_CHUNK_SIZE: usize = 32 * 1024;
+
+/// A compression stream which wraps an uncompressed stream of data. Compressed
+/// data will be read from the stream.
+pub struct BzEncoder<R> {
+    reader: R,
+    encoder: Bz2Encoder<Vec<u8>>,
+    input: Vec<u8>,
+    output_position: usize,
+    done: bool,
+}
+
+impl<R: Read> BzEncoder<R> {
+    /// Create a new compression stream which will compress at the given level
+    /// to read compress output to the give output stream.
+    pub fn new(r: R, level: Compression) -> BzEncoder<R> {
+        BzEncoder {
+            reader: r,
+            encoder: EncoderBuilder::new()
+                .block_size(level.block_size())
+                .build(vec![]),
+            input: vec![0; INPUT_CHUNK_SIZE],
+            output_position: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R> BzEncoder<R> {
+    /// Acquires a reference to the underlying stream
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Acquires a mutable reference to the underlying stream
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this encoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Consumes this encoder, returning the underlying reader.
+    pub fn into_inner(mut self) -> R {
+        // the stream is incomplete anyway, so there is no point in finishing it
+        self.encoder.abandon();
+        self.reader
+    }
+
+    /// Returns the number of bytes produced by the compressor
+    /// (e.g. the number of bytes read from this stream)
+    pub fn total_out(&self) -> u64 {
+        self.encoder.total_out()
+    }
+
+    /// Returns the number of bytes consumed by the compressor
+    /// (e.g. the number of bytes read from the underlying stream)
+    pub fn total_in(&self) -> u64 {
+        self.encoder.total_in()
+    }
+}
+
+impl<R: Read> Read for BzEncoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let output = self.encoder.get_mut();
+            if self.output_position < output.len() {
+                let size = buf.len().min(output.len() - self.output_position);
+                buf[..size]
+                    .copy_from_slice(&output[self.output_position..self.output_position + size]);
+                self.output_position += size;
+                if self.output_position == output.len() {
+                    output.clear();
+                    self.output_position = 0;
+                }
+                return Ok(size);
+            }
+            if self.done {
+                return Ok(0);
+            }
+            let read = self.reader.read(&mut self.input)?;
+            if read == 0 {
+                self.encoder.try_finish()?;
+                self.done = true;
+            } else {
+                self.encoder.write_all(&self.input[..read])?;
+            }
+        }
+    }
+}
+
+/// A decompression stream which wraps a compressed stream of data. Decompressed
+/// data will be read from the stream.
+///
+/// Only the first stream is decoded, see [MultiBzDecoder] for concatenated streams.
+pub struct BzDecoder<R: Read> {
+    decoder: Bz2Decoder<R>,
+}
+
+impl<R: Read> BzDecoder<R> {
+    /// Create a new decompression stream, which will read compressed
+    /// data from the given input stream and decompress it.
+    pub fn new(r: R) -> BzDecoder<R> {
+        BzDecoder {
+            decoder: DecoderBuilder::new().multi_stream(false).build(r),
+        }
+    }
+
+    /// Acquires a reference to the underlying stream
+    pub fn get_ref(&self) -> &R {
+        self.decoder.get_ref()
+    }
+
+    /// Acquires a mutable reference to the underlying stream
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this encoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.decoder.get_mut()
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.decoder.into_inner()
+    }
+
+    /// Returns the number of bytes that the decompressor has produced.
+    pub fn total_out(&self) -> u64 {
+        self.decoder.total_out()
+    }
+
+    /// Returns the number of bytes that the decompressor has consumed.
+    pub fn total_in(&self) -> u64 {
+        self.decoder.total_in()
+    }
+}
+
+impl<R: Read> Read for BzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.decoder.read(buf)
+    }
+}
+
+/// A bzip2 streaming decoder that decodes all members of a multistream.
+///
+/// Data following the last stream which is not a bzip2 stream is ignored.
+pub struct MultiBzDecoder<R: Read> {
+    decoder: Bz2Decoder<R>,
+}
+
+impl<R: Read> MultiBzDecoder<R> {
+    /// Creates a new decoder from the given reader. If the bzip2 stream contains multiple members
+    /// all will be decoded.
+    pub fn new(r: R) -> MultiBzDecoder<R> {
+        MultiBzDecoder {
+            decoder: DecoderBuilder::new().multi_stream(true).build(r),
+        }
+    }
+
+    /// Acquires a reference to the underlying stream
+    pub fn get_ref(&self) -> &R {
+        self.decoder.get_ref()
+    }
+
+    /// Acquires a mutable reference to the underlying stream
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this encoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.decoder.get_mut()
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.decoder.into_inner()
+    }
+}
+
+impl<R: Read> Read for MultiBzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.decoder.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::decode_stream;
+    use crate::test_util::sample;
+
+    #[test]
+    pub fn round_trips_through_readers() {
+        let input = sample(150_000);
+        let mut encoder = BzEncoder::new(&input[..], Compression::fast());
+        let mut encoded = vec![];
+        encoder.read_to_end(&mut encoded).unwrap();
+        assert_eq!(encoder.total_in(), input.len() as u64);
+        assert_eq!(encoder.total_out(), encoded.len() as u64);
+        assert_eq!(encoded[..4], *b"BZh1");
+
+        let mut decoded = vec![];
+        decode_stream(&encoded[..], &mut decoded).unwrap();
+        assert_eq!(decoded, input);
+
+        let mut decoder = BzDecoder::new(&encoded[..]);
+        let mut decoded = vec![];
+        decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, input);
+        assert_eq!(decoder.total_out(), input.len() as u64);
+        assert_eq!(decoder.total_in(), encoded.len() as u64);
+    }
+
+    #[test]
+    pub fn decodes_one_or_all_streams() {
+        let mut encoded = vec![];
+        BzEncoder::new(&b"first"[..], Compression::best())
+            .read_to_end(&mut encoded)
+            .unwrap();
+        BzEncoder::new(&b" second"[..], Compression::best())
+            .read_to_end(&mut encoded)
+            .unwrap();
+
+        let mut decoded = String::new();
+        BzDecoder::new(&encoded[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "first");
+
+        let mut decoded = String::new();
+        MultiBzDecoder::new(&encoded[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "first second");
+    }
+}
@@ -0,0 +1,387 @@
+//! Writer-based compression/decompression streams
+
+use std::io::{Read, Write};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread;
+
+use crate::error::DecodeError;
+use crate::stream::{worker_failure, Bz2Encoder, DecodeSummary, DecoderBuilder, EncoderBuilder};
+
+use super::Compression;
+
+/// Number of chunks of compressed input buffered for the decoder thread.
+const INPUT_CAPACITY: usize = 4;
+/// Number of messages, e.g. chunks of decoded data, buffered for the writer.
+const OUTPUT_CAPACITY: usize = 4;
+
+/// A compression stream which will have uncompressed data written to it and
+/// will write compressed data to an output stream.
+pub struct BzEncoder<W: Write> {
+    encoder: Bz2Encoder<W>,
+}
+
+impl<W: Write> BzEncoder<W> {
+    /// Create a new compression stream which will compress at the given level
+    /// to write compress output to the give output stream.
+    pub fn new(obj: W, level: Compression) -> BzEncoder<W> {
+        BzEncoder {
+            encoder: EncoderBuilder::new()
+                .block_size(level.block_size())
+                .build(obj),
+        }
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.encoder.get_ref()
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt this
+    /// object, so care must be taken when using this method.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.encoder.get_mut()
+    }
+
+    /// Attempt to finish this output stream, writing out final chunks of data.
+    ///
+    /// Note that this function can only be used once data has finished being
+    /// written to the output stream. After this function is called then further
+    /// calls to `write` will fail.
+    pub fn try_finish(&mut self) -> std::io::Result<()> {
+        self.encoder.try_finish()
+    }
+
+    /// Consumes this encoder, flushing the output stream.
+    ///
+    /// This will flush the underlying data stream and then return the contained
+    /// writer if the flush succeeded.
+    pub fn finish(self) -> std::io::Result<W> {
+        self.encoder.finish()
+    }
+
+    /// Returns the number of bytes produced by the compressor
+    pub fn total_out(&self) -> u64 {
+        self.encoder.total_out()
+    }
+
+    /// Returns the number of bytes consumed by the compressor
+    /// (e.g. the number of bytes written to this stream.)
+    pub fn total_in(&self) -> u64 {
+        self.encoder.total_in()
+    }
+}
+
+impl<W: Write> Write for BzEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.encoder.write(data)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+/// Message sent from the decoder thread.
+enum Output {
+    Data(Vec<u8>),
+    /// The decoder thread ran out of input and waits for more.
+    NeedInput,
+    Done(Result<DecodeSummary, DecodeError>),
+}
+
+struct ChannelReader {
+    receiver: Receiver<Vec<u8>>,
+    sender: SyncSender<Output>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.chunk.len() {
+            let chunk = match self.receiver.try_recv() {
+                Ok(chunk) => Ok(chunk),
+                Err(TryRecvError::Empty) => {
+                    // wakes up a writer waiting for room in the input channel
+                    let _ = self.sender.send(Output::NeedInput);
+                    self.receiver.recv()
+                }
+                Err(TryRecvError::Disconnected) => return Ok(0),
+            };
+            match chunk {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let size = buf.len().min(self.chunk.len() - self.position);
+        buf[..size].copy_from_slice(&self.chunk[self.position..self.position + size]);
+        self.position += size;
+        Ok(size)
+    }
+}
+
+struct ChannelWriter {
+    sender: SyncSender<Output>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.sender
+            .send(Output::Data(buf.to_vec()))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A decompression stream which will have compressed data written to it and
+/// will write uncompressed data to an output stream.
+///
+/// The data is decoded on a separate thread, decoded data is written into the
+/// output stream by calls to `write`, `flush` and `try_finish`.
+pub struct BzDecoder<W: Write> {
+    obj: Option<W>,
+    input: Option<SyncSender<Vec<u8>>>,
+    output: Receiver<Output>,
+    total_in: u64,
+    total_out: u64,
+    done: bool,
+}
+
+impl<W: Write> BzDecoder<W> {
+    /// Create a new decoding stream which will decompress all data written
+    /// to it into `obj`.
+    pub fn new(obj: W) -> BzDecoder<W> {
+        let (send_input, receive_input) = sync_channel(INPUT_CAPACITY);
+        let (send_output, receive_output) = sync_channel(OUTPUT_CAPACITY);
+        thread::Builder::new()
+            .name("Decoder".into())
+            .spawn(move || {
+                let reader = ChannelReader {
+                    receiver: receive_input,
+                    sender: send_output.clone(),
+                    chunk: vec![],
+                    position: 0,
+                };
+                let mut writer = ChannelWriter {
+                    sender: send_output,
+                };
+                let result = DecoderBuilder::new()
+                    .multi_stream(false)
+                    .decode_stream(reader, &mut writer);
+                let _ = writer.sender.send(Output::Done(result));
+            })
+            .unwrap();
+        BzDecoder {
+            obj: Some(obj),
+            input: Some(send_input),
+            output: receive_output,
+            total_in: 0,
+            total_out: 0,
+            done: false,
+        }
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.obj.as_ref().unwrap()
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt this
+    /// object, so care must be taken when using this method.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.obj.as_mut().unwrap()
+    }
+
+    /// Write the output of the decoder thread into the underlying writer,
+    /// waiting for the end of the stream if `wait` is set.
+    fn dump(&mut self, wait: bool) -> std::io::Result<()> {
+        while !self.done {
+            let message = if wait {
+                self.output.recv().map_err(|_| worker_failure())?
+            } else {
+                match self.output.try_recv() {
+                    Ok(message) => message,
+                    Err(TryRecvError::Empty) => return Ok(()),
+                    Err(TryRecvError::Disconnected) => return Err(worker_failure()),
+                }
+            };
+            self.handle(message)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, message: Output) -> std::io::Result<()> {
+        match message {
+            Output::Data(data) => {
+                self.obj.as_mut().unwrap().write_all(&data)?;
+                self.total_out += data.len() as u64;
+            }
+            Output::NeedInput => {}
+            Output::Done(result) => {
+                self.done = true;
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pass `chunk` on to the decoder thread. While the input channel is full, the output of
+    /// the decoder thread is written, as it may wait for room in the output channel.
+    /// Returns false if the decoder thread stopped.
+    fn send(&mut self, mut chunk: Vec<u8>) -> std::io::Result<bool> {
+        while !self.done {
+            let Some(input) = self.input.as_ref() else {
+                return Ok(false);
+            };
+            match input.try_send(chunk) {
+                Ok(()) => return Ok(true),
+                Err(TrySendError::Full(rest)) => {
+                    chunk = rest;
+                    let message = self.output.recv().map_err(|_| worker_failure())?;
+                    self.handle(message)?;
+                }
+                Err(TrySendError::Disconnected(_)) => return Ok(false),
+            }
+        }
+        Ok(false)
+    }
+
+    /// Attempt to finish this output stream, writing out final chunks of data.
+    ///
+    /// Fails if the data written so far is not a complete bzip2 stream.
+    pub fn try_finish(&mut self) -> std::io::Result<()> {
+        self.input.take();
+        self.dump(true)
+    }
+
+    /// Unwrap the underlying writer, finishing the compression stream.
+    pub fn finish(&mut self) -> std::io::Result<W> {
+        self.try_finish()?;
+        Ok(self.obj.take().unwrap())
+    }
+
+    /// Returns the number of bytes produced by the decompressor
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Returns the number of bytes consumed by the decompressor
+    /// (e.g. the number of bytes written to this stream.)
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+}
+
+impl<W: Write> Write for BzDecoder<W> {
+    /// Pass `data` on to the decoder. Once the end of the stream was found no further data
+    /// is accepted, i.e. `Ok(0)` is returned.
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.dump(false)?;
+        if self.done || data.is_empty() {
+            return Ok(0);
+        }
+        if !self.send(data.to_vec())? {
+            // the decoder thread stopped, either at the end of the stream or due to an error
+            self.input.take();
+            self.dump(true)?;
+            return Ok(0);
+        }
+        self.total_in += data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.dump(false)?;
+        self.obj.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for BzDecoder<W> {
+    fn drop(&mut self) {
+        if self.obj.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::sample;
+
+    #[test]
+    pub fn round_trips_through_writers() {
+        let input = sample(150_000);
+        let mut encoder = BzEncoder::new(vec![], Compression::fast());
+        for piece in input.chunks(1_001) {
+            encoder.write_all(piece).unwrap();
+        }
+        assert_eq!(encoder.total_in(), input.len() as u64);
+        let encoded = encoder.finish().unwrap();
+
+        let mut decoder = BzDecoder::new(vec![]);
+        for piece in encoded.chunks(997) {
+            decoder.write_all(piece).unwrap();
+        }
+        let decoded = decoder.finish().unwrap();
+        assert_eq!(decoded, input);
+        assert_eq!(decoder.total_in(), encoded.len() as u64);
+        assert_eq!(decoder.total_out(), input.len() as u64);
+    }
+
+    #[test]
+    pub fn writes_output_while_waiting_for_room_for_input() {
+        let input = sample(150_000).repeat(6);
+        let mut encoder = BzEncoder::new(vec![], Compression::fast());
+        encoder.write_all(&input).unwrap();
+        let encoded = encoder.finish().unwrap();
+
+        // more blocks than buffered output messages, written in pieces filling the input
+        let mut decoder = BzDecoder::new(vec![]);
+        for piece in encoded.chunks(997) {
+            decoder.write_all(piece).unwrap();
+        }
+        assert_eq!(decoder.finish().unwrap(), input);
+    }
+
+    #[test]
+    pub fn accepts_nothing_after_end_of_stream() {
+        let encoded = BzEncoder::new(vec![], Compression::default())
+            .finish()
+            .unwrap();
+        let mut decoder = BzDecoder::new(vec![]);
+        decoder.write_all(&encoded).unwrap();
+        // wait until the decoder thread found the end of the stream
+        let accepted_after_end = (0..100)
+            .map(|_| {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                decoder.write(b"more").unwrap()
+            })
+            .take_while(|written| *written != 0)
+            .count();
+        assert!(accepted_after_end < 100);
+        assert!(decoder.finish().unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn reports_errors_when_finishing() {
+        let mut encoder = BzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&sample(150_000)).unwrap();
+        let encoded = encoder.finish().unwrap();
+
+        let mut decoder = BzDecoder::new(vec![]);
+        decoder.write_all(&encoded[..encoded.len() / 2]).unwrap();
+        assert!(decoder.try_finish().is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt;
+
+/// The reason why decoding a bzip2 stream failed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DecodeErrorKind {
+    /// The stream header, a block header or the stream footer does not start with the expected magic bytes,
+    /// or the stream header declares a block size other than 1 to 9.
+    BadMagic,
+    /// The input ended before the stream was complete.
+    TruncatedInput,
+    /// The checksum of a decoded block does not match the checksum stored in the block header.
+    BlockCrcMismatch { expected: u32, actual: u32 },
+    /// The combined checksum of all blocks does not match the checksum stored in the stream footer.
+    StreamCrcMismatch { expected: u32, actual: u32 },
+    /// The number of Huffman tables, the symbol map or the code lengths of a table are invalid.
+    InvalidHuffmanTable,
+    /// A selector refers to a Huffman table which does not exist.
+    InvalidSelector,
+    /// The original pointer of the Burrows-Wheeler transform points outside of the block.
+    OrigPtrOutOfRange,
+    /// A block is larger than the block size announced in the stream header permits.
+    BlockTooLarge,
+    /// A block does not match its entry in a [crate::stream::BlockIndex], which hence belongs
+    /// to another file.
+    IndexMismatch,
+    /// Decoding was stopped using an [crate::AbortHandle].
+    Cancelled,
+    /// Decoding was stopped because the input exceeds a limit set on the
+    /// [crate::stream::DecoderBuilder].
+    LimitExceeded(DecodeLimit),
+    /// The underlying reader or writer failed.
+    Io(std::io::Error),
+}
+
+impl fmt::Display for DecodeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeErrorKind::BadMagic => write!(f, "not a valid bzip2 stream (bad magic)"),
+            DecodeErrorKind::TruncatedInput => write!(f, "unexpected end of input"),
+            DecodeErrorKind::BlockCrcMismatch { expected, actual } => write!(
+                f,
+                "block checksum mismatch (expected {:#010x}, got {:#010x})",
+                expected, actual
+            ),
+            DecodeErrorKind::StreamCrcMismatch { expected, actual } => write!(
+                f,
+                "stream checksum mismatch (expected {:#010x}, got {:#010x})",
+                expected, actual
+            ),
+            DecodeErrorKind::InvalidHuffmanTable => write!(f, "invalid huffman table"),
+            DecodeErrorKind::InvalidSelector => write!(f, "invalid huffman table selector"),
+            DecodeErrorKind::OrigPtrOutOfRange => write!(f, "original pointer out of range"),
+            DecodeErrorKind::BlockTooLarge => {
+                write!(f, "block exceeds the block size of the stream header")
+            }
+            DecodeErrorKind::IndexMismatch => write!(f, "block does not match the block index"),
+            DecodeErrorKind::Cancelled => write!(f, "decoding was cancelled"),
+            DecodeErrorKind::LimitExceeded(limit) => {
+                write!(f, "decompression limit exceeded: {}", limit)
+            }
+            DecodeErrorKind::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// A limit guarding against decompression bombs, carrying the configured maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecodeLimit {
+    /// Total number of decoded bytes, see [crate::stream::DecoderBuilder::max_output].
+    Output(u64),
+    /// Decoded bytes per compressed byte, see [crate::stream::DecoderBuilder::max_ratio].
+    Ratio(u64),
+    /// Number of blocks, see [crate::stream::DecoderBuilder::max_blocks].
+    Blocks(usize),
+    /// Number of streams, see [crate::stream::DecoderBuilder::max_streams].
+    Streams(usize),
+    /// Memory of the blocks decoded concurrently, see [crate::stream::DecoderBuilder::max_memory].
+    Memory(usize),
+}
+
+impl fmt::Display for DecodeLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeLimit::Output(max) => write!(f, "more than {} bytes of output", max),
+            DecodeLimit::Ratio(max) => write!(f, "expands by more than a factor of {}", max),
+            DecodeLimit::Blocks(max) => write!(f, "more than {} blocks", max),
+            DecodeLimit::Streams(max) => write!(f, "more than {} streams", max),
+            DecodeLimit::Memory(max) => write!(f, "needs more than {} bytes of memory", max),
+        }
+    }
+}
+
+impl From<std::io::Error> for DecodeErrorKind {
+    fn from(err: std::io::Error) -> Self {
+        DecodeErrorKind::Io(err)
+    }
+}
+
+/// Error returned when decoding a bzip2 stream fails.
+///
+/// Besides the [DecodeErrorKind] it records where the problem was detected:
+/// the number of the block (starting at 1, 0 denotes the stream header) and
+/// the offset in bits from the start of the input.
+#[derive(Debug)]
+pub struct DecodeError {
+    kind: DecodeErrorKind,
+    block_number: usize,
+    bit_offset: u64,
+}
+
+impl DecodeError {
+    pub(crate) fn new(kind: DecodeErrorKind, block_number: usize, bit_offset: u64) -> Self {
+        DecodeError {
+            kind,
+            block_number,
+            bit_offset,
+        }
+    }
+
+    pub fn kind(&self) -> &DecodeErrorKind {
+        &self.kind
+    }
+
+    pub fn block_number(&self) -> usize {
+        self.block_number
+    }
+
+    pub fn bit_offset(&self) -> u64 {
+        self.bit_offset
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in block {} at bit offset {}",
+            self.kind, self.block_number, self.bit_offset
+        )
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            DecodeErrorKind::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for std::io::Error {
+    fn from(err: DecodeError) -> Self {
+        match err.kind {
+            DecodeErrorKind::Io(io_error) => io_error,
+            DecodeErrorKind::Cancelled => std::io::Error::other(err),
+            _ => std::io::Error::new(std::io::ErrorKind::InvalidData, err),
+        }
+    }
+}
@@ -5,7 +5,29 @@
 //!
 //!  * [stream::encode_stream]
 //!  * [stream::decode_stream]
+//!  * [stream::Bz2Encoder] for pushing data into an encoder using [std::io::Write]
+//!  * [stream::Bz2Decoder] for pulling decoded data from a decoder using [std::io::Read]
+//!  * [stream::EncoderBuilder] for configuring threads, encoding strategy and [stream::BlockSize]
+//!  * [stream::DecoderBuilder] for decoding with multiple threads or little memory and configuring how concatenated streams are decoded
+//!  * [stream::BlockIndex] for locating the blocks of a file without decoding them and storing their location in a sidecar file
+//!  * [stream::SeekableBz2Reader] for random access to the decompressed data of a file
+//!  * [stream::DecoderBuilder::max_output] and further limits guarding against decompression bombs
+//!  * [stream::CorruptionPolicy] for decoding past blocks failing their checksum
+//!  * [stream::recover] for extracting the intact blocks of damaged files
+//!  * [stream::Compress] and [stream::Decompress] for working on slices, e.g. with non-blocking I/O
+//!  * [stream::BlockProgress] reported to observers registered with the builders
+//!  * [stream::BlockStats] of every encoded block, returned in [stream::EncodeSummary]
+//!  * [AbortHandle] for cancelling encoders and decoders from another thread
+//!  * [compat] for migrating from the bzip2 crate
+//!  * `AsyncBz2Encoder` and `AsyncBz2Decoder` for use with tokio, enabled by the `tokio` feature
+mod abort;
 mod bitwise;
 mod block;
+pub mod compat;
+mod error;
 pub mod stream;
+#[cfg(test)]
+pub(crate) mod test_util;
+pub use abort::{AbortHandle, Cancelled};
 pub use block::symbol_statistics::EncodingStrategy;
+pub use error::{DecodeError, DecodeErrorKind, DecodeLimit};
@@ -0,0 +1,448 @@
+use std::future::Future;
+use std::io::{Read, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc::{self, error::SendError, OwnedPermit};
+
+use super::{worker_failure, DecodeSummary, DecoderBuilder, EncodeSummary, EncoderBuilder};
+
+/// Number of chunks buffered in each direction between the async side and the thread.
+const CHANNEL_CAPACITY: usize = 4;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Message sent from the thread running the synchronous encoder or decoder.
+enum Output<S> {
+    Data(Vec<u8>),
+    Done(std::io::Result<S>),
+}
+
+type Reserve = Pin<Box<dyn Future<Output = Result<OwnedPermit<Vec<u8>>, SendError<()>>> + Send>>;
+
+/// Feeds the synchronous encoder or decoder in its thread with chunks of input.
+struct ChannelReader {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let size = buf.len().min(self.chunk.len() - self.position);
+        buf[..size].copy_from_slice(&self.chunk[self.position..self.position + size]);
+        self.position += size;
+        Ok(size)
+    }
+}
+
+/// Hands the output of the synchronous encoder or decoder back to the async side.
+struct ChannelWriter<S> {
+    sender: mpsc::Sender<Output<S>>,
+}
+
+impl<S> Write for ChannelWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.sender
+            .blocking_send(Output::Data(buf.to_vec()))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A thread running `work` on the input sent by the async side, passing on its output.
+struct Bridge<S> {
+    input: Option<mpsc::Sender<Vec<u8>>>,
+    reserve: Option<Reserve>,
+    permit: Option<OwnedPermit<Vec<u8>>>,
+    output: mpsc::Receiver<Output<S>>,
+    pending: Vec<u8>,
+    pending_position: usize,
+    result: Option<S>,
+}
+
+impl<S: Send + 'static> Bridge<S> {
+    fn spawn(
+        name: &str,
+        work: impl FnOnce(ChannelReader, &mut ChannelWriter<S>) -> std::io::Result<S> + Send + 'static,
+    ) -> Self {
+        let (send_input, receive_input) = mpsc::channel(CHANNEL_CAPACITY);
+        let (send_output, receive_output) = mpsc::channel(CHANNEL_CAPACITY);
+        thread::Builder::new()
+            .name(name.into())
+            .spawn(move || {
+                let reader = ChannelReader {
+                    receiver: receive_input,
+                    chunk: vec![],
+                    position: 0,
+                };
+                let mut writer = ChannelWriter {
+                    sender: send_output,
+                };
+                let result = work(reader, &mut writer);
+                let _ = writer.sender.blocking_send(Output::Done(result));
+            })
+            .unwrap();
+        Bridge {
+            input: Some(send_input),
+            reserve: None,
+            permit: None,
+            output: receive_output,
+            pending: vec![],
+            pending_position: 0,
+            result: None,
+        }
+    }
+
+    /// Wait until a chunk of input can be sent to the thread.
+    /// Returns `false` if the thread does not accept any more input.
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<bool> {
+        if self.permit.is_some() {
+            return Poll::Ready(true);
+        }
+        let Some(input) = self.input.as_ref() else {
+            return Poll::Ready(false);
+        };
+        let reserve = self
+            .reserve
+            .get_or_insert_with(|| Box::pin(input.clone().reserve_owned()));
+        let result = std::task::ready!(reserve.as_mut().poll(cx));
+        self.reserve = None;
+        match result {
+            Ok(permit) => {
+                self.permit = Some(permit);
+                Poll::Ready(true)
+            }
+            Err(_) => {
+                self.input = None;
+                Poll::Ready(false)
+            }
+        }
+    }
+
+    /// Send a chunk of input, which requires [Bridge::poll_ready] to have returned `true`.
+    fn send(&mut self, chunk: Vec<u8>) {
+        self.permit.take().unwrap().send(chunk);
+    }
+
+    /// Signal the end of the input.
+    fn close(&mut self) {
+        self.input = None;
+        self.reserve = None;
+        self.permit = None;
+    }
+
+    /// Make output of the thread available in `pending`. Returns `false` if the thread finished.
+    fn poll_output(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<bool>> {
+        if self.pending_position < self.pending.len() {
+            return Poll::Ready(Ok(true));
+        }
+        if self.result.is_some() {
+            return Poll::Ready(Ok(false));
+        }
+        match self.output.poll_recv(cx) {
+            Poll::Ready(Some(Output::Data(data))) => {
+                self.pending = data;
+                self.pending_position = 0;
+                Poll::Ready(Ok(true))
+            }
+            Poll::Ready(Some(Output::Done(result))) => {
+                self.result = Some(result?);
+                Poll::Ready(Ok(false))
+            }
+            Poll::Ready(None) => Poll::Ready(Err(worker_failure())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn pending(&self) -> &[u8] {
+        &self.pending[self.pending_position..]
+    }
+
+    fn consume(&mut self, size: usize) {
+        self.pending_position += size;
+    }
+}
+
+/// An asynchronous bzip2 encoder compressing everything written to it into the wrapped writer.
+///
+/// The blocks are encoded by a [super::Bz2Encoder] running on a separate thread, which uses
+/// the worker threads configured by the [EncoderBuilder], so the executor is never blocked.
+/// The stream is terminated by shutting the encoder down, see [AsyncWrite::poll_shutdown].
+pub struct AsyncBz2Encoder<W: AsyncWrite + Unpin> {
+    writer: W,
+    bridge: Bridge<EncodeSummary>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncBz2Encoder<W> {
+    /// Create an encoder writing into `writer` with the default settings of [EncoderBuilder].
+    pub fn new(writer: W) -> Self {
+        EncoderBuilder::new().build_async(writer)
+    }
+
+    /// Acquire a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Summary of the stream, available once the encoder has been shut down.
+    pub fn summary(&self) -> Option<&EncodeSummary> {
+        self.bridge.result.as_ref()
+    }
+
+    /// Write all output of the encoder available so far into the underlying writer.
+    /// Returns `false` if the encoder finished.
+    fn poll_write_output(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<bool>> {
+        loop {
+            match self.bridge.poll_output(cx)? {
+                Poll::Ready(true) => {}
+                Poll::Ready(false) => return Poll::Ready(Ok(false)),
+                Poll::Pending => return Poll::Ready(Ok(true)),
+            }
+            let written = match Pin::new(&mut self.writer).poll_write(cx, self.bridge.pending()) {
+                Poll::Ready(written) => written?,
+                Poll::Pending => return Poll::Pending,
+            };
+            if written == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.bridge.consume(written);
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncBz2Encoder<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        // output must keep flowing, otherwise the encoder thread cannot take further input
+        if let Poll::Ready(false) = this.poll_write_output(cx)? {
+            return Poll::Ready(Err(std::io::Error::other(
+                "bzip2 stream has already been finished",
+            )));
+        }
+        match this.bridge.poll_ready(cx) {
+            Poll::Ready(true) => {
+                this.bridge.send(buf.to_vec());
+                Poll::Ready(Ok(buf.len()))
+            }
+            Poll::Ready(false) => Poll::Ready(Err(std::io::Error::other(
+                "bzip2 stream has already been finished",
+            ))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_write_output(cx)? {
+            Poll::Ready(_) => Pin::new(&mut this.writer).poll_flush(cx),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        this.bridge.close();
+        match this.poll_write_output(cx)? {
+            Poll::Ready(false) => Pin::new(&mut this.writer).poll_shutdown(cx),
+            // the encoder thread is still busy
+            Poll::Ready(true) | Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// An asynchronous bzip2 decoder reading compressed data from the wrapped reader.
+///
+/// The blocks are decoded on a separate thread as [DecoderBuilder::decode_stream] does
+/// (using multiple threads if configured), so the executor is never blocked.
+pub struct AsyncBz2Decoder<R: AsyncRead + Unpin> {
+    reader: R,
+    bridge: Bridge<DecodeSummary>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncBz2Decoder<R> {
+    /// Create a decoder reading from `reader` with the default settings of [DecoderBuilder].
+    pub fn new(reader: R) -> Self {
+        DecoderBuilder::new().build_async(reader)
+    }
+
+    /// Acquire a mutable reference to the underlying reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Summary of the decoded streams, available once all data has been read.
+    pub fn summary(&self) -> Option<&DecodeSummary> {
+        self.bridge.result.as_ref()
+    }
+
+    /// Pass input from the underlying reader on to the decoder thread, as long as both are ready.
+    fn feed(&mut self, cx: &mut Context<'_>) -> std::io::Result<()> {
+        // a finished decoder does not need the remaining input
+        while let Poll::Ready(true) = self.bridge.poll_ready(cx) {
+            let mut chunk = vec![0u8; READ_BUFFER_SIZE];
+            let mut read_buf = ReadBuf::new(&mut chunk);
+            if Pin::new(&mut self.reader)
+                .poll_read(cx, &mut read_buf)?
+                .is_pending()
+            {
+                break;
+            }
+            let size = read_buf.filled().len();
+            if size == 0 {
+                self.bridge.close();
+                break;
+            }
+            chunk.truncate(size);
+            self.bridge.send(chunk);
+        }
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncBz2Decoder<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        match this.bridge.poll_output(cx)? {
+            Poll::Ready(true) => {
+                let pending = this.bridge.pending();
+                let size = pending.len().min(buf.remaining());
+                buf.put_slice(&pending[..size]);
+                this.bridge.consume(size);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(false) => Poll::Ready(Ok(())),
+            // woken up by new output of the decoder thread, which needs input first
+            Poll::Pending => {
+                this.feed(cx)?;
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl EncoderBuilder {
+    /// Create an asynchronous encoder writing into `writer`.
+    pub fn build_async<W: AsyncWrite + Unpin>(&self, writer: W) -> AsyncBz2Encoder<W> {
+        let builder = self.clone();
+        let bridge = Bridge::spawn("Async encoder", move |mut reader, writer| {
+            let mut encoder = builder.build(writer);
+            if let Err(err) = std::io::copy(&mut reader, &mut encoder) {
+                encoder.abandon();
+                return Err(err);
+            }
+            encoder.try_finish()?;
+            Ok(encoder.summary())
+        });
+        AsyncBz2Encoder { writer, bridge }
+    }
+}
+
+impl DecoderBuilder {
+    /// Create an asynchronous decoder reading from `reader`.
+    pub fn build_async<R: AsyncRead + Unpin>(&self, reader: R) -> AsyncBz2Decoder<R> {
+        let builder = self.clone();
+        let bridge = Bridge::spawn("Async decoder", move |reader, writer| {
+            Ok(builder.decode_stream(reader, writer)?)
+        });
+        AsyncBz2Decoder { reader, bridge }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::{decode_stream, encode_stream, BlockSize};
+    use crate::test_util::sample;
+    use crate::EncodingStrategy;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    pub async fn round_trips_through_duplex_stream() {
+        let input = sample(250_000);
+        let (client, mut server) = tokio::io::duplex(1024);
+
+        let to_encode = input.clone();
+        let encode = tokio::spawn(async move {
+            let mut encoder = EncoderBuilder::new()
+                .num_threads(2)
+                .block_size(BlockSize::new(1).unwrap())
+                .build_async(client);
+            for piece in to_encode.chunks(7_777) {
+                encoder.write_all(piece).await.unwrap();
+            }
+            encoder.shutdown().await.unwrap();
+            encoder.summary().unwrap().num_blocks
+        });
+
+        let mut decoder = DecoderBuilder::new().build_async(&mut server);
+        let mut decoded = vec![];
+        decoder.read_to_end(&mut decoded).await.unwrap();
+        assert_eq!(decoder.summary().unwrap().num_blocks, 3);
+        assert_eq!(encode.await.unwrap(), 3);
+        assert_eq!(decoded, input);
+    }
+
+    #[tokio::test]
+    pub async fn matches_synchronous_encoder() {
+        let input = sample(20_000);
+        let mut expected = vec![];
+        encode_stream(&input[..], &mut expected, 1, EncodingStrategy::Single).unwrap();
+
+        let mut encoder = AsyncBz2Encoder::new(vec![]);
+        encoder.write_all(&input).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        let encoded = encoder.into_inner();
+        assert_eq!(encoded, expected);
+
+        let mut decoded = vec![];
+        decode_stream(&encoded[..], &mut decoded).unwrap();
+        assert_eq!(decoded, input);
+    }
+
+    #[tokio::test]
+    pub async fn reports_decode_errors() {
+        let mut encoded = vec![];
+        encode_stream(
+            &sample(20_000)[..],
+            &mut encoded,
+            1,
+            EncodingStrategy::Single,
+        )
+        .unwrap();
+        encoded[20] ^= 1;
+        let mut decoder = AsyncBz2Decoder::new(&encoded[..]);
+        let mut decoded = vec![];
+        assert!(decoder.read_to_end(&mut decoded).await.is_err());
+    }
+}
@@ -0,0 +1,52 @@
+/// Size of the blocks of a bzip2 stream given as level 1 to 9, i.e. in units of 100k.
+///
+/// The level is stored in the stream header (`BZh1` to `BZh9`) and tells the decoder how
+/// much memory a block may require. Smaller blocks need less memory but compress worse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockSize(u8);
+
+impl BlockSize {
+    /// Create the block size for a level between 1 and 9.
+    pub fn new(level: u8) -> Option<Self> {
+        if (1..=9).contains(&level) {
+            Some(BlockSize(level))
+        } else {
+            None
+        }
+    }
+
+    /// The level between 1 and 9.
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+
+    /// Maximum number of bytes a block may contain after the initial run length encoding.
+    pub fn max_block_length(&self) -> usize {
+        usize::from(self.0) * 100_000
+    }
+
+    /// The byte stored in the stream header, i.e. `b'1'` to `b'9'`.
+    pub(crate) fn header_byte(&self) -> u8 {
+        b'0' + self.0
+    }
+}
+
+impl Default for BlockSize {
+    fn default() -> Self {
+        BlockSize(9)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn accepts_levels_one_to_nine() {
+        assert!(BlockSize::new(0).is_none());
+        assert!(BlockSize::new(10).is_none());
+        assert_eq!(BlockSize::new(1).unwrap().max_block_length(), 100_000);
+        assert_eq!(BlockSize::new(9).unwrap().header_byte(), b'9');
+        assert_eq!(BlockSize::default(), BlockSize::new(9).unwrap());
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::DecodeErrorKind;
+
+/// How a decoder handles a block whose decoded data does not match its checksum.
+///
+/// Only the checksum is covered, blocks which cannot be decoded at all still fail. After a
+/// tolerated mismatch the checksum of the stream containing the block is not verified.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CorruptionPolicy {
+    /// Stop decoding with [DecodeErrorKind::BlockCrcMismatch].
+    #[default]
+    Fail,
+    /// Leave out the data of the block and continue with the next block.
+    Skip,
+    /// Replace the data of the block by as many zeros as the corrupt block decoded to.
+    ZeroFill,
+    /// Pass on the decoded data of the block although it is wrong.
+    EmitUnverified,
+    /// Replace the data of the block by the given marker.
+    Marker(Vec<u8>),
+}
+
+/// A block whose decoded data does not match its checksum, reported to the callback
+/// registered using [super::DecoderBuilder::on_corruption].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CorruptionIncident {
+    /// Number of the block, starting at 1 and counted across streams like in [crate::DecodeError].
+    pub block_number: usize,
+    /// Offset of the block magic in bits from the start of the input.
+    pub bit_offset: u64,
+    /// Offset of the data of the block in the output.
+    pub output_offset: u64,
+    /// Length of the decoded, unverified data of the block.
+    pub decoded_bytes: u64,
+    /// Checksum stored in the block header.
+    pub expected_crc: u32,
+    /// Checksum of the decoded data.
+    pub actual_crc: u32,
+}
+
+/// Callback registered using [super::DecoderBuilder::on_corruption].
+#[derive(Clone)]
+pub(crate) struct CorruptionObserver(Arc<dyn Fn(&CorruptionIncident) + Send + Sync>);
+
+impl CorruptionObserver {
+    pub(crate) fn new(observer: impl Fn(&CorruptionIncident) + Send + Sync + 'static) -> Self {
+        CorruptionObserver(Arc::new(observer))
+    }
+}
+
+impl fmt::Debug for CorruptionObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CorruptionObserver")
+    }
+}
+
+/// Applies the [CorruptionPolicy] of a decoder and reports incidents to its observer, if any.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CorruptionHandler {
+    pub(crate) policy: CorruptionPolicy,
+    pub(crate) observer: Option<CorruptionObserver>,
+}
+
+impl CorruptionHandler {
+    /// Whether the policy may replace the data of a block, which hence has to be decoded
+    /// completely before any of it is passed on.
+    pub(crate) fn replaces_data(&self) -> bool {
+        matches!(
+            self.policy,
+            CorruptionPolicy::Skip | CorruptionPolicy::ZeroFill | CorruptionPolicy::Marker(_)
+        )
+    }
+
+    /// Report `incident` and replace `data`, the decoded data of the block, as the policy says.
+    /// Fails unless the policy tolerates the mismatch.
+    pub(crate) fn handle(
+        &self,
+        incident: &CorruptionIncident,
+        data: &mut Vec<u8>,
+    ) -> Result<(), DecodeErrorKind> {
+        if let Some(observer) = &self.observer {
+            (observer.0)(incident);
+        }
+        match &self.policy {
+            CorruptionPolicy::Fail => {
+                return Err(DecodeErrorKind::BlockCrcMismatch {
+                    expected: incident.expected_crc,
+                    actual: incident.actual_crc,
+                })
+            }
+            CorruptionPolicy::Skip => data.clear(),
+            CorruptionPolicy::ZeroFill => data.fill(0),
+            CorruptionPolicy::EmitUnverified => {}
+            CorruptionPolicy::Marker(marker) => {
+                data.clear();
+                data.extend_from_slice(marker);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1119 @@
+use std::io::Read;
+use std::io::Write;
+use std::time::Instant;
+
+use crate::abort::AbortHandle;
+use crate::bitwise::bitreader::BitReader;
+use crate::bitwise::bitreader::BitReaderImpl;
+use crate::bitwise::bitwriter::convert_to_number;
+use crate::block::block_decoder::{parse_block, SmallBlock};
+use crate::error::{DecodeError, DecodeErrorKind};
+
+use super::corruption::{
+    CorruptionHandler, CorruptionIncident, CorruptionObserver, CorruptionPolicy,
+};
+use super::index::BlockInfo;
+use super::limits::DecodeLimits;
+use super::parallel_decoder::ParallelDecoder;
+use super::progress::{BlockProgress, ProgressObserver, ProgressReporter};
+use super::BlockSize;
+
+/// Size of the pieces a block is decoded in when the small mode holds it back until verified.
+const SMALL_READ_SIZE: usize = 1 << 16;
+
+pub(super) fn read_file_header(
+    mut bit_reader: impl BitReader,
+) -> Result<BlockSize, DecodeErrorKind> {
+    let res = bit_reader.read_bytes(4)?;
+    match &res[..] {
+        [b'B', b'Z', b'h', level @ b'1'..=b'9'] => Ok(BlockSize::new(level - b'0').unwrap()),
+        _ => Err(DecodeErrorKind::BadMagic),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum NextStream {
+    Header(BlockSize),
+    EndOfInput,
+    TrailingGarbage,
+}
+
+/// Look for the header of another stream following a stream footer. Like the reference
+/// implementation anything not starting with a valid header counts as trailing garbage,
+/// while a partial header at the end of the input is reported as truncated.
+pub(super) fn read_next_stream_header(
+    mut bit_reader: impl BitReader,
+) -> Result<NextStream, DecodeErrorKind> {
+    for (index, expected) in b"BZh".iter().enumerate() {
+        match bit_reader.read_bytes(1) {
+            Ok(byte) if byte[0] == *expected => {}
+            Ok(_) => return Ok(NextStream::TrailingGarbage),
+            Err(DecodeErrorKind::TruncatedInput) if index == 0 => {
+                return Ok(NextStream::EndOfInput)
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    let level = bit_reader.read_bytes(1)?[0];
+    match level {
+        b'1'..=b'9' => Ok(NextStream::Header(BlockSize::new(level - b'0').unwrap())),
+        _ => Ok(NextStream::TrailingGarbage),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum BlockType {
+    StreamFooter,
+    BlockHeader,
+}
+
+pub(super) fn what_next(mut bit_reader: impl BitReader) -> Result<BlockType, DecodeErrorKind> {
+    let res = bit_reader.read_bytes(6)?;
+    match &res[..] {
+        [0x31u8, 0x41u8, 0x59u8, 0x26u8, 0x53u8, 0x59u8] => Ok(BlockType::BlockHeader),
+        [0x17, 0x72, 0x45, 0x38, 0x50, 0x90] => Ok(BlockType::StreamFooter),
+        _ => Err(DecodeErrorKind::BadMagic),
+    }
+}
+
+/// Summary of a decoded input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DecodeSummary {
+    /// Number of compressed bytes consumed: the bytes of the streams plus, if there is trailing
+    /// garbage, the few bytes of it read to detect that it is no stream header.
+    pub input_bytes: u64,
+    /// Number of uncompressed bytes produced.
+    pub output_bytes: u64,
+    /// Number of streams in the input.
+    pub num_streams: usize,
+    /// Number of blocks in all streams.
+    pub num_blocks: usize,
+    /// Whether data which is not a bzip2 stream follows the last stream.
+    pub trailing_garbage: bool,
+}
+
+/// Configuration of a bzip2 decoder.
+///
+/// By default all concatenated streams of the input are decoded, as the reference
+/// implementation does.
+#[derive(Debug, Clone)]
+pub struct DecoderBuilder {
+    multi_stream: bool,
+    num_threads: usize,
+    small: bool,
+    observer: Option<ProgressObserver>,
+    corruption: CorruptionHandler,
+    limits: DecodeLimits,
+    abort: AbortHandle,
+}
+
+impl DecoderBuilder {
+    pub fn new() -> Self {
+        DecoderBuilder {
+            multi_stream: true,
+            num_threads: 1,
+            small: false,
+            observer: None,
+            corruption: CorruptionHandler::default(),
+            limits: DecodeLimits::default(),
+            abort: AbortHandle::new(),
+        }
+    }
+
+    /// Whether to continue with the next stream after a stream footer.
+    /// If disabled, decoding stops after the first stream and the remaining input is not read.
+    pub fn multi_stream(mut self, multi_stream: bool) -> Self {
+        self.multi_stream = multi_stream;
+        self
+    }
+
+    /// Number of threads decoding blocks in parallel in [DecoderBuilder::decode_stream] (at least 1).
+    /// The output is identical to decoding with a single thread. [Bz2Decoder] always decodes sequentially.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    /// Decode with less memory, like `bunzip2 -s`.
+    ///
+    /// Blocks are decoded sequentially, regardless of [DecoderBuilder::num_threads], and written
+    /// piecewise instead of decoding them completely first. Per 100k of block size, i.e. per
+    /// block size level, the tables of a block take 250 kB instead of about 2 MB plus the decoded
+    /// block, which may be larger than the block size. The symbols read from the input take up
+    /// to 400 kB more per level until the tables are built. Decoded blocks are verified once
+    /// they have been passed on completely, so data preceding a checksum mismatch is output,
+    /// unless the [CorruptionPolicy] replaces the data of corrupted blocks.
+    pub fn small(mut self, small: bool) -> Self {
+        self.small = small;
+        self
+    }
+
+    /// Call `observer` whenever a block has been decoded, see [BlockProgress].
+    pub fn on_progress(
+        mut self,
+        observer: impl Fn(&BlockProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.observer = Some(ProgressObserver::new(observer));
+        self
+    }
+
+    /// How blocks whose data does not match their checksum are handled, see [CorruptionPolicy].
+    /// In the mode set by [DecoderBuilder::small] the policies replacing the data of a block
+    /// hold back every decoded block until it is verified, which takes the memory of the block.
+    pub fn corruption_policy(mut self, policy: CorruptionPolicy) -> Self {
+        self.corruption.policy = policy;
+        self
+    }
+
+    /// Call `observer` for every block whose data does not match its checksum, regardless of
+    /// the [CorruptionPolicy], see [CorruptionIncident].
+    pub fn on_corruption(
+        mut self,
+        observer: impl Fn(&CorruptionIncident) + Send + Sync + 'static,
+    ) -> Self {
+        self.corruption.observer = Some(CorruptionObserver::new(observer));
+        self
+    }
+
+    /// Fail with [crate::DecodeLimit::Output] once more than `max_output` bytes are decoded.
+    /// A block exceeding the limit is not decoded completely, decoding stops as soon as it does.
+    pub fn max_output(mut self, max_output: u64) -> Self {
+        self.limits.max_output = Some(max_output);
+        self
+    }
+
+    /// Fail with [crate::DecodeLimit::Ratio] once the data decoded so far exceeds `max_ratio`
+    /// times the compressed input read so far, checked like [DecoderBuilder::max_output].
+    /// Hence a well compressible start of the input may exceed the ratio although the whole
+    /// input would not.
+    pub fn max_ratio(mut self, max_ratio: u64) -> Self {
+        self.limits.max_ratio = Some(max_ratio);
+        self
+    }
+
+    /// Fail with [crate::DecodeLimit::Blocks] once the input contains more than `max_blocks`
+    /// blocks, counted across all streams.
+    pub fn max_blocks(mut self, max_blocks: usize) -> Self {
+        self.limits.max_blocks = Some(max_blocks);
+        self
+    }
+
+    /// Fail with [crate::DecodeLimit::Streams] once the input contains more than `max_streams`
+    /// concatenated streams.
+    pub fn max_streams(mut self, max_streams: usize) -> Self {
+        self.limits.max_streams = Some(max_streams);
+        self
+    }
+
+    /// Limit the memory of the blocks being decoded to about `max_memory` bytes. A block is
+    /// estimated to take 20 bytes per byte of the block size while decoding besides its decoded
+    /// data. With multiple threads fewer blocks are decoded concurrently if needed, and the
+    /// compressed input held for them counts as well. Decoding fails with
+    /// [crate::DecodeLimit::Memory] if a single block does not fit, or decodes to more data than
+    /// fits. Not applied in the mode set by [DecoderBuilder::small].
+    pub fn max_memory(mut self, max_memory: usize) -> Self {
+        self.limits.max_memory = Some(max_memory);
+        self
+    }
+
+    /// Stop decoding once `abort` is triggered, see [AbortHandle].
+    pub fn abort_handle(mut self, abort: AbortHandle) -> Self {
+        self.abort = abort;
+        self
+    }
+
+    /// Create a decoder reading compressed data from `reader`.
+    pub fn build<R: Read>(&self, reader: R) -> Bz2Decoder<R> {
+        Bz2Decoder::with_config(reader, self)
+    }
+
+    /// Decode everything from `reader` into `writer`, see [crate::stream::decode_stream].
+    pub fn decode_stream(
+        &self,
+        reader: impl Read,
+        mut writer: impl Write,
+    ) -> Result<DecodeSummary, DecodeError> {
+        if self.small {
+            let mut decoder = self.build(reader);
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                let size = decoder.read_small(&mut buffer)?;
+                if size == 0 {
+                    return Ok(decoder.summary());
+                }
+                writer
+                    .write_all(&buffer[..size])
+                    .map_err(|err| decoder.error(DecodeErrorKind::Io(err)))?;
+            }
+        }
+        if self.num_threads > 1 {
+            return ParallelDecoder::new(
+                reader,
+                self.num_threads,
+                self.multi_stream,
+                self.observer.clone(),
+                self.corruption.clone(),
+                self.limits,
+                self.abort.clone(),
+            )
+            .run(writer);
+        }
+        let mut decoder = self.build(reader);
+        while decoder.decode_next_block()? {
+            writer
+                .write_all(decoder.decoded_block())
+                .map_err(|err| decoder.error(DecodeErrorKind::Io(err)))?;
+        }
+        Ok(decoder.summary())
+    }
+}
+
+impl Default for DecoderBuilder {
+    fn default() -> Self {
+        DecoderBuilder::new()
+    }
+}
+
+/// Block being read in small mode, see [DecoderBuilder::small].
+struct PendingSmallBlock {
+    block: SmallBlock,
+    /// Time the decoding of the block started.
+    started: Instant,
+    bit_offset: u64,
+    output_offset: u64,
+}
+
+/// A bzip2 decoder which decompresses the data read from the wrapped reader.
+///
+/// Blocks are decoded lazily one at a time when the caller reads from the decoder, hence
+/// memory consumption is bounded by the size of a single decoded block, or less in the mode
+/// set by [DecoderBuilder::small]. Blocks larger than the block size declared in the stream
+/// header are rejected.
+///
+/// Concatenated streams are decoded one after another unless configured otherwise using
+/// [DecoderBuilder::multi_stream]. Data following the last stream which does not start
+/// with a stream header is ignored, see [Bz2Decoder::trailing_garbage].
+pub struct Bz2Decoder<R: Read> {
+    bit_reader: BitReaderImpl<R>,
+    multi_stream: bool,
+    block_size: Option<BlockSize>,
+    stream_crc: u32,
+    /// Whether a block of the current stream did not match its checksum.
+    stream_corrupted: bool,
+    finished: bool,
+    trailing_garbage: bool,
+    num_streams: usize,
+    block_number: usize,
+    total_out: u64,
+    block: Vec<u8>,
+    position: usize,
+    small: bool,
+    small_block: Option<PendingSmallBlock>,
+    progress: ProgressReporter,
+    corruption: CorruptionHandler,
+    limits: DecodeLimits,
+    abort: AbortHandle,
+}
+
+impl<R: Read> Bz2Decoder<R> {
+    /// Create a decoder reading compressed data from `reader`,
+    /// use [DecoderBuilder] for further settings.
+    pub fn new(reader: R) -> Self {
+        DecoderBuilder::new().build(reader)
+    }
+
+    fn with_config(reader: R, config: &DecoderBuilder) -> Self {
+        Bz2Decoder {
+            bit_reader: BitReaderImpl::from_reader(reader),
+            multi_stream: config.multi_stream,
+            block_size: None,
+            stream_crc: 0,
+            stream_corrupted: false,
+            finished: false,
+            trailing_garbage: false,
+            num_streams: 0,
+            block_number: 0,
+            total_out: 0,
+            block: vec![],
+            position: 0,
+            small: config.small,
+            small_block: None,
+            progress: ProgressReporter::new(config.observer.clone()),
+            corruption: config.corruption.clone(),
+            limits: config.limits,
+            abort: config.abort.clone(),
+        }
+    }
+
+    /// Decode the next block of the input. Returns `false` once the end of the last stream is reached.
+    pub(crate) fn decode_next_block(&mut self) -> Result<bool, DecodeError> {
+        Ok(self.next_block(true)?.is_some())
+    }
+
+    /// Read the next block of the input and describe where it is located. The block is only
+    /// decoded into [Bz2Decoder::decoded_block] if `decode` is set, otherwise the checksum
+    /// stored in the block header is taken as is. Returns `None` once the end of the last stream is reached.
+    pub(crate) fn next_block(&mut self, decode: bool) -> Result<Option<BlockInfo>, DecodeError> {
+        self.block.clear();
+        self.position = 0;
+        if self.finished {
+            return Ok(None);
+        }
+        self.abort
+            .check()
+            .map_err(|cancelled| self.error(cancelled.into()))?;
+        if self.num_streams == 0 {
+            let block_size =
+                read_file_header(&mut self.bit_reader).map_err(|kind| self.error(kind))?;
+            self.start_stream(block_size)
+                .map_err(|kind| self.error(kind))?;
+        }
+        loop {
+            let block_size = self.block_size.unwrap();
+            match what_next(&mut self.bit_reader).map_err(|kind| self.error(kind))? {
+                BlockType::StreamFooter => {
+                    self.check_stream_crc().map_err(|kind| self.error(kind))?;
+                    if !self.next_stream().map_err(|kind| self.error(kind))? {
+                        self.finished = true;
+                        return Ok(None);
+                    }
+                }
+                BlockType::BlockHeader => {
+                    // the block magic has just been read
+                    let bit_offset = self.bit_reader.position() - 48;
+                    let started = Instant::now();
+                    self.block_number += 1;
+                    self.limits
+                        .check_blocks(self.block_number)
+                        .map_err(|kind| self.error(kind))?;
+                    let parsed_block =
+                        parse_block(&mut self.bit_reader).map_err(|kind| self.error(kind))?;
+                    let mut uncompressed = None;
+                    let block_crc = if decode && self.small {
+                        // the checksum is verified once the block has been read
+                        let block_crc = parsed_block.crc();
+                        let small_block = parsed_block
+                            .into_small(block_size.max_block_length())
+                            .map_err(|kind| self.error(kind))?;
+                        self.small_block = Some(PendingSmallBlock {
+                            block: small_block,
+                            started,
+                            bit_offset,
+                            output_offset: self.total_out,
+                        });
+                        block_crc
+                    } else if decode {
+                        let block_crc = parsed_block.crc();
+                        let (_, memory_share) = self
+                            .limits
+                            .blocks_in_memory(block_size.max_block_length(), 1, 0)
+                            .map_err(|kind| self.error(kind))?;
+                        let max_output = self
+                            .limits
+                            .remaining_output(self.total_in(), self.total_out)
+                            .min(memory_share);
+                        let (decoded, actual_crc) = parsed_block
+                            .reconstruct_unverified(block_size.max_block_length(), max_output)
+                            .map_err(|kind| self.error(kind))?;
+                        self.limits
+                            .check_output(self.total_in(), self.total_out + decoded.len() as u64)
+                            .and_then(|()| self.limits.check_memory(decoded.len(), memory_share))
+                            .map_err(|kind| self.error(kind))?;
+                        self.block = decoded;
+                        if actual_crc != block_crc {
+                            let incident = CorruptionIncident {
+                                block_number: self.block_number,
+                                bit_offset,
+                                output_offset: self.total_out,
+                                decoded_bytes: self.block.len() as u64,
+                                expected_crc: block_crc,
+                                actual_crc,
+                            };
+                            self.corruption
+                                .handle(&incident, &mut self.block)
+                                .map_err(|kind| self.error(kind))?;
+                            self.stream_corrupted = true;
+                        }
+                        uncompressed = Some((self.total_out, self.block.len() as u64));
+                        self.total_out += self.block.len() as u64;
+                        self.progress.report(
+                            self.block_number - 1,
+                            self.total_in(),
+                            self.total_out,
+                            started,
+                        );
+                        block_crc
+                    } else {
+                        parsed_block.crc()
+                    };
+                    self.stream_crc = block_crc ^ self.stream_crc.rotate_left(1);
+                    return Ok(Some(BlockInfo {
+                        stream_index: self.num_streams - 1,
+                        block_size,
+                        bit_offset,
+                        bit_length: self.bit_reader.position() - bit_offset,
+                        crc: block_crc,
+                        uncompressed_offset: uncompressed.map(|(offset, _)| offset),
+                        uncompressed_length: uncompressed.map(|(_, length)| length),
+                    }));
+                }
+            }
+        }
+    }
+
+    fn start_stream(&mut self, block_size: BlockSize) -> Result<(), DecodeErrorKind> {
+        self.limits.check_streams(self.num_streams + 1)?;
+        self.block.reserve(block_size.max_block_length());
+        self.block_size = Some(block_size);
+        self.stream_crc = 0;
+        self.stream_corrupted = false;
+        self.num_streams += 1;
+        Ok(())
+    }
+
+    /// Continue with the stream following the current one, if any.
+    fn next_stream(&mut self) -> Result<bool, DecodeErrorKind> {
+        if !self.multi_stream {
+            return Ok(false);
+        }
+        // streams are padded to whole bytes
+        self.bit_reader.align_to_byte();
+        match read_next_stream_header(&mut self.bit_reader)? {
+            NextStream::Header(block_size) => {
+                self.start_stream(block_size)?;
+                Ok(true)
+            }
+            NextStream::EndOfInput => Ok(false),
+            NextStream::TrailingGarbage => {
+                self.trailing_garbage = true;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Compare the checksum of the stream footer with the checksums of all decoded blocks,
+    /// unless a block of the stream did not match its checksum.
+    fn check_stream_crc(&mut self) -> Result<(), DecodeErrorKind> {
+        let expected = convert_to_number(&self.bit_reader.read_bits(32)?) as u32;
+        if expected != self.stream_crc && !self.stream_corrupted {
+            return Err(DecodeErrorKind::StreamCrcMismatch {
+                expected,
+                actual: self.stream_crc,
+            });
+        }
+        Ok(())
+    }
+
+    /// Acquire a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        self.bit_reader.get_ref()
+    }
+
+    /// Acquire a mutable reference to the underlying reader.
+    /// Note that the decoder reads ahead, so the reader is usually beyond the data decoded so far.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.bit_reader.get_mut()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.bit_reader.into_inner()
+    }
+
+    /// The block size declared in the header of the current stream, once the header has been read.
+    pub fn block_size(&self) -> Option<BlockSize> {
+        self.block_size
+    }
+
+    /// Whether data which is not a bzip2 stream was found (and ignored) after the last stream.
+    pub fn trailing_garbage(&self) -> bool {
+        self.trailing_garbage
+    }
+
+    /// Number of compressed bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.bit_reader.position().div_ceil(8)
+    }
+
+    /// Number of uncompressed bytes produced so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Summary of the input decoded so far.
+    pub fn summary(&self) -> DecodeSummary {
+        DecodeSummary {
+            input_bytes: self.total_in(),
+            output_bytes: self.total_out(),
+            num_streams: self.num_streams,
+            num_blocks: self.block_number,
+            trailing_garbage: self.trailing_garbage,
+        }
+    }
+
+    /// Attach the current position in the stream to an error.
+    pub(crate) fn error(&self, kind: DecodeErrorKind) -> DecodeError {
+        DecodeError::new(kind, self.block_number, self.bit_reader.position())
+    }
+
+    /// Decode the next part of the input into `buf` in small mode, see [DecoderBuilder::small].
+    /// Returns 0 once the end of the last stream is reached.
+    fn read_small(&mut self, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        loop {
+            if self.position < self.block.len() {
+                // a block held back by buffer_small_block
+                let size = buf.len().min(self.block.len() - self.position);
+                buf[..size].copy_from_slice(&self.block[self.position..self.position + size]);
+                self.position += size;
+                return Ok(size);
+            }
+            if self.small_block.is_some() && self.corruption.replaces_data() {
+                self.buffer_small_block()?;
+                continue;
+            }
+            let input_bytes = self.total_in();
+            let remaining = self.limits.remaining_output(input_bytes, self.total_out);
+            if let Some(pending) = self.small_block.as_mut() {
+                // read one byte beyond the limit to detect exceeding it
+                let length = buf.len().min(remaining.saturating_add(1));
+                let size = pending.block.read(&mut buf[..length]);
+                self.limits
+                    .check_output(input_bytes, self.total_out + size as u64)
+                    .map_err(|kind| self.error(kind))?;
+                self.total_out += size as u64;
+                if size > 0 || buf.is_empty() {
+                    return Ok(size);
+                }
+                let pending = self.small_block.take().unwrap();
+                let decoded_bytes = self.total_out - pending.output_offset;
+                // the data has been passed on already
+                self.finish_small_block(pending, decoded_bytes, &mut vec![])?;
+            }
+            if buf.is_empty() || self.next_block(true)?.is_none() {
+                return Ok(0);
+            }
+        }
+    }
+
+    /// Decode the pending block of the small mode completely, so that the corruption policy can
+    /// replace its data before it is passed on.
+    fn buffer_small_block(&mut self) -> Result<(), DecodeError> {
+        let mut pending = self.small_block.take().unwrap();
+        let input_bytes = self.total_in();
+        let mut data = std::mem::take(&mut self.block);
+        data.clear();
+        loop {
+            let length = data.len();
+            data.resize(length + SMALL_READ_SIZE, 0);
+            let size = pending.block.read(&mut data[length..]);
+            data.truncate(length + size);
+            self.limits
+                .check_output(input_bytes, self.total_out + data.len() as u64)
+                .map_err(|kind| self.error(kind))?;
+            if size == 0 {
+                break;
+            }
+        }
+        let decoded_bytes = data.len() as u64;
+        self.finish_small_block(pending, decoded_bytes, &mut data)?;
+        self.total_out += data.len() as u64;
+        self.block = data;
+        self.position = 0;
+        Ok(())
+    }
+
+    /// Verify a block of the small mode which decoded to `decoded_bytes` bytes, applying the
+    /// corruption policy to `data`.
+    fn finish_small_block(
+        &mut self,
+        pending: PendingSmallBlock,
+        decoded_bytes: u64,
+        data: &mut Vec<u8>,
+    ) -> Result<(), DecodeError> {
+        match pending.block.finish() {
+            Ok(_) => {}
+            Err(DecodeErrorKind::BlockCrcMismatch { expected, actual }) => {
+                let incident = CorruptionIncident {
+                    block_number: self.block_number,
+                    bit_offset: pending.bit_offset,
+                    output_offset: pending.output_offset,
+                    decoded_bytes,
+                    expected_crc: expected,
+                    actual_crc: actual,
+                };
+                self.corruption
+                    .handle(&incident, data)
+                    .map_err(|kind| self.error(kind))?;
+                self.stream_corrupted = true;
+            }
+            Err(kind) => return Err(self.error(kind)),
+        }
+        self.progress.report(
+            self.block_number - 1,
+            self.total_in(),
+            self.total_out + data.len() as u64,
+            pending.started,
+        );
+        Ok(())
+    }
+
+    /// The block most recently decoded by [Bz2Decoder::decode_next_block].
+    pub(crate) fn decoded_block(&self) -> &[u8] {
+        &self.block
+    }
+}
+
+impl<R: Read> Read for Bz2Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.small {
+            return Ok(self.read_small(buf)?);
+        }
+        while self.position >= self.block.len() {
+            if buf.is_empty() || !self.decode_next_block()? {
+                return Ok(0);
+            }
+        }
+        let size = buf.len().min(self.block.len() - self.position);
+        buf[..size].copy_from_slice(&self.block[self.position..self.position + size]);
+        self.position += size;
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::stream::EncoderBuilder;
+    use crate::test_util::{encode, sample};
+    use crate::DecodeLimit;
+    use std::io::{BufRead, BufReader, Cursor};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    pub fn accepts_correct_header() {
+        let input = b"BZh9";
+        let mut cursor = Cursor::new(input);
+        let mut bit_reader = BitReaderImpl::from_reader(&mut cursor);
+        let read = read_file_header(&mut bit_reader);
+        assert_eq!(read.unwrap().level(), 9);
+    }
+
+    #[test]
+    pub fn rejects_invalid_level() {
+        for input in [b"BZh0", b"BZhX"] {
+            let mut bit_reader = BitReaderImpl::from_reader(Cursor::new(input));
+            let read = read_file_header(&mut bit_reader);
+            assert!(matches!(read, Err(DecodeErrorKind::BadMagic)));
+        }
+    }
+
+    #[test]
+    pub fn detects_block_header() {
+        let data = vec![0x31u8, 0x41u8, 0x59u8, 0x26u8, 0x53u8, 0x59u8];
+        let mut cursor = Cursor::new(data);
+        let mut bit_reader = BitReaderImpl::from_reader(&mut cursor);
+
+        assert_eq!(BlockType::BlockHeader, what_next(&mut bit_reader).unwrap());
+    }
+
+    #[test]
+    pub fn detects_stream_footer() {
+        let data = vec![0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+        let mut cursor = Cursor::new(data);
+        let mut bit_reader = BitReaderImpl::from_reader(&mut cursor);
+
+        assert_eq!(BlockType::StreamFooter, what_next(&mut bit_reader).unwrap());
+    }
+
+    #[test]
+    pub fn detects_error() {
+        let data = vec![0, 1, 2, 3, 4, 5];
+        let mut cursor = Cursor::new(data);
+        let mut bit_reader = BitReaderImpl::from_reader(&mut cursor);
+
+        assert!(what_next(&mut bit_reader).is_err());
+    }
+
+    #[test]
+    pub fn reads_in_small_pieces() {
+        let input = b"If Peter Piper picked a peck of pickled peppers, where's the peck of pickled peppers Peter Piper picked?????".repeat(100);
+        let mut decoder = Bz2Decoder::new(Cursor::new(encode(&input, 9)));
+        let mut decoded = vec![];
+        let mut buf = [0u8; 7];
+        loop {
+            let size = decoder.read(&mut buf).unwrap();
+            if size == 0 {
+                break;
+            }
+            decoded.extend_from_slice(&buf[..size]);
+        }
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    pub fn reads_lines() {
+        let input = (0..1000)
+            .map(|x| format!("line {}\n", x))
+            .collect::<String>();
+        let decoder = Bz2Decoder::new(Cursor::new(encode(input.as_bytes(), 9)));
+        let lines = BufReader::new(decoder)
+            .lines()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(lines.len(), 1000);
+        assert_eq!(lines[999], "line 999");
+    }
+
+    #[test]
+    pub fn reports_invalid_data() {
+        let mut decoder = Bz2Decoder::new(Cursor::new(b"no bzip2 data".to_vec()));
+        let err = decoder.read(&mut [0u8; 16]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    pub fn reports_position_of_crc_mismatch() {
+        let input = b"If Peter Piper picked a peck of pickled peppers".repeat(10);
+        let mut encoded = encode(&input, 9);
+        // flip a bit of the block checksum following the block magic
+        encoded[10] ^= 1;
+        let mut decoder = Bz2Decoder::new(Cursor::new(encoded));
+        let err = decoder.decode_next_block().unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            DecodeErrorKind::BlockCrcMismatch { .. }
+        ));
+        assert_eq!(err.block_number(), 1);
+        assert!(err.bit_offset() > 80);
+    }
+
+    #[test]
+    pub fn reports_truncated_input() {
+        let input = b"If Peter Piper picked a peck of pickled peppers".repeat(10);
+        let encoded = encode(&input, 9);
+        let mut decoder = Bz2Decoder::new(Cursor::new(encoded[..encoded.len() / 2].to_vec()));
+        let err = decoder.decode_next_block().unwrap_err();
+        assert!(matches!(err.kind(), DecodeErrorKind::TruncatedInput));
+    }
+
+    #[test]
+    pub fn rejects_blocks_exceeding_block_size() {
+        let input = (0..150_000u32)
+            .map(|x| (x.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect::<Vec<_>>();
+        let mut encoded = encode(&input, 9);
+        let mut decoder = Bz2Decoder::new(Cursor::new(encoded.clone()));
+        assert!(decoder.decode_next_block().unwrap());
+        assert_eq!(decoder.block_size().unwrap().level(), 9);
+
+        encoded[3] = b'1';
+        let mut decoder = Bz2Decoder::new(Cursor::new(encoded));
+        let err = decoder.decode_next_block().unwrap_err();
+        assert!(matches!(err.kind(), DecodeErrorKind::BlockTooLarge));
+        assert_eq!(err.block_number(), 1);
+    }
+
+    #[test]
+    pub fn reports_stream_crc_mismatch() {
+        let input = b"If Peter Piper picked a peck of pickled peppers".repeat(10);
+        let mut encoded = encode(&input, 9);
+        // the combined checksum occupies the last bits of the stream
+        let last = encoded.len() - 2;
+        encoded[last] ^= 1;
+        let mut decoder = Bz2Decoder::new(Cursor::new(encoded));
+        assert!(decoder.decode_next_block().unwrap());
+        let err = decoder.decode_next_block().unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            DecodeErrorKind::StreamCrcMismatch { .. }
+        ));
+    }
+
+    #[test]
+    pub fn decodes_concatenated_streams() {
+        let mut encoded = encode(b"first stream, ", 9);
+        encoded.extend(encode(b"second stream", 9));
+        let mut decoded = vec![];
+        let summary = DecoderBuilder::new()
+            .decode_stream(&encoded[..], &mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"first stream, second stream");
+        assert_eq!(summary.num_streams, 2);
+        assert_eq!(summary.num_blocks, 2);
+        assert_eq!(summary.input_bytes, encoded.len() as u64);
+        assert!(!summary.trailing_garbage);
+    }
+
+    #[test]
+    pub fn stops_after_first_stream() {
+        let mut encoded = encode(b"first stream, ", 9);
+        encoded.extend(encode(b"second stream", 9));
+        let mut decoded = vec![];
+        let summary = DecoderBuilder::new()
+            .multi_stream(false)
+            .decode_stream(&encoded[..], &mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"first stream, ");
+        assert_eq!(summary.num_streams, 1);
+    }
+
+    #[test]
+    pub fn ignores_trailing_garbage() {
+        let mut encoded = encode(b"some data", 9);
+        encoded.extend_from_slice(b"garbage");
+        let mut decoded = vec![];
+        let summary = DecoderBuilder::new()
+            .decode_stream(&encoded[..], &mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"some data");
+        assert!(summary.trailing_garbage);
+    }
+
+    #[test]
+    pub fn reports_partial_stream_header_after_stream() {
+        let mut encoded = encode(b"some data", 9);
+        encoded.extend_from_slice(b"BZ");
+        let err = DecoderBuilder::new()
+            .decode_stream(&encoded[..], std::io::sink())
+            .unwrap_err();
+        assert!(matches!(err.kind(), DecodeErrorKind::TruncatedInput));
+    }
+
+    #[test]
+    pub fn reports_progress_with_any_number_of_threads() {
+        let input = sample(250_000);
+        let encoded = encode(&input, 1);
+
+        let reports = (1..=3)
+            .map(|num_threads| {
+                let reports = Arc::new(Mutex::new(vec![]));
+                let observed = reports.clone();
+                DecoderBuilder::new()
+                    .num_threads(num_threads)
+                    .on_progress(move |progress| {
+                        observed.lock().unwrap().push((
+                            progress.block_index,
+                            progress.input_bytes,
+                            progress.output_bytes,
+                        ))
+                    })
+                    .decode_stream(&encoded[..], std::io::sink())
+                    .unwrap();
+                let reports = reports.lock().unwrap().clone();
+                reports
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(reports[0].len(), 3);
+        assert_eq!(reports[0][2].2, input.len() as u64);
+        assert!(reports[0][2].1 < encoded.len() as u64);
+        assert_eq!(reports[1], reports[0]);
+        assert_eq!(reports[2], reports[0]);
+    }
+
+    #[test]
+    pub fn stops_when_aborted() {
+        let input = sample(250_000);
+        let encoded = encode(&input, 1);
+
+        for num_threads in [1, 3] {
+            let abort = AbortHandle::new();
+            let observer_abort = abort.clone();
+            let mut decoded = vec![];
+            let err = DecoderBuilder::new()
+                .num_threads(num_threads)
+                .abort_handle(abort)
+                .on_progress(move |_| observer_abort.abort())
+                .decode_stream(&encoded[..], &mut decoded)
+                .unwrap_err();
+            assert!(matches!(err.kind(), DecodeErrorKind::Cancelled));
+            assert_eq!(err.block_number(), 1);
+            // only the first block was decoded
+            assert!(decoded.len() < input.len() / 2);
+            assert_eq!(decoded, input[..decoded.len()]);
+        }
+    }
+
+    #[test]
+    pub fn decodes_in_small_mode() {
+        // runs of up to 300 bytes exercise the initial run length encoding
+        let input: Vec<u8> = (0..250_000u32)
+            .map(|x| b"aaaaabcd"[(x / 37 % 8) as usize] + (x % 300 / 299) as u8)
+            .collect();
+        let mut encoded = encode(&input, 1);
+        encoded.extend_from_slice(&encode(b"second stream", 9));
+        let mut expected = input.clone();
+        expected.extend_from_slice(b"second stream");
+
+        let mut decoded = vec![];
+        let summary = DecoderBuilder::new()
+            .small(true)
+            .num_threads(3)
+            .decode_stream(&encoded[..], &mut decoded)
+            .unwrap();
+        assert_eq!(decoded, expected);
+        let expected_summary = DecoderBuilder::new()
+            .decode_stream(&encoded[..], std::io::sink())
+            .unwrap();
+        assert_eq!(summary, expected_summary);
+
+        let mut decoder = DecoderBuilder::new()
+            .small(true)
+            .build(Cursor::new(&encoded));
+        let mut decoded = vec![];
+        let mut buf = [0u8; 7];
+        loop {
+            let size = decoder.read(&mut buf).unwrap();
+            if size == 0 {
+                break;
+            }
+            decoded.extend_from_slice(&buf[..size]);
+        }
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    pub fn reports_crc_mismatch_in_small_mode() {
+        let input = b"If Peter Piper picked a peck of pickled peppers".repeat(10);
+        let mut encoded = encode(&input, 9);
+        encoded[10] ^= 1;
+        let mut decoded = vec![];
+        let err = DecoderBuilder::new()
+            .small(true)
+            .decode_stream(&encoded[..], &mut decoded)
+            .unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            DecodeErrorKind::BlockCrcMismatch { .. }
+        ));
+        assert_eq!(err.block_number(), 1);
+        // the block is passed on before its checksum is verified
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    pub fn applies_corruption_policy() {
+        let input = sample(250_000);
+        let mut encoded = encode(&input, 1);
+        let index = crate::stream::BlockIndex::build_decoded(&encoded[..]).unwrap();
+        let damaged = index.blocks()[1].clone();
+        let (start, end) = (
+            damaged.uncompressed_offset.unwrap() as usize,
+            damaged.uncompressed_end().unwrap() as usize,
+        );
+        // flip a bit of the checksum in the block header, which follows the block magic
+        let bit = damaged.bit_offset + 48;
+        encoded[(bit / 8) as usize] ^= 0x80 >> (bit % 8);
+        let expected_incident = CorruptionIncident {
+            block_number: 2,
+            bit_offset: damaged.bit_offset,
+            output_offset: start as u64,
+            decoded_bytes: (end - start) as u64,
+            expected_crc: damaged.crc ^ 0x8000_0000,
+            actual_crc: damaged.crc,
+        };
+
+        let zeros = vec![0; end - start];
+        for (policy, replacement) in [
+            (CorruptionPolicy::Skip, &b""[..]),
+            (CorruptionPolicy::ZeroFill, &zeros[..]),
+            (CorruptionPolicy::EmitUnverified, &input[start..end]),
+            (CorruptionPolicy::Marker(b"<lost>".to_vec()), &b"<lost>"[..]),
+            (CorruptionPolicy::Fail, &b""[..]),
+        ] {
+            for (num_threads, small) in [(1, false), (3, false), (1, true)] {
+                let incidents = Arc::new(Mutex::new(vec![]));
+                let reported = incidents.clone();
+                let mut decoded = vec![];
+                let result = DecoderBuilder::new()
+                    .num_threads(num_threads)
+                    .small(small)
+                    .corruption_policy(policy.clone())
+                    .on_corruption(move |incident| reported.lock().unwrap().push(incident.clone()))
+                    .decode_stream(&encoded[..], &mut decoded);
+                assert_eq!(*incidents.lock().unwrap(), vec![expected_incident.clone()]);
+                if policy == CorruptionPolicy::Fail {
+                    let err = result.unwrap_err();
+                    assert!(matches!(
+                        err.kind(),
+                        DecodeErrorKind::BlockCrcMismatch { .. }
+                    ));
+                    assert_eq!(err.block_number(), 2);
+                    assert_eq!(decoded, input[..decoded.len()]);
+                } else {
+                    result.unwrap();
+                    assert_eq!(
+                        decoded,
+                        [&input[..start], replacement, &input[end..]].concat()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn enforces_limits() {
+        let mut state = 1u32;
+        let input: Vec<u8> = (0..150_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                b"abcdefgh"[(state >> 28) as usize & 7]
+            })
+            .collect();
+        let mut stream = vec![];
+        EncoderBuilder::new()
+            .block_size(BlockSize::new(1).unwrap())
+            .encode_stream(&input[..], &mut stream)
+            .unwrap();
+        // two streams of two blocks each
+        let encoded = [&stream[..], &stream[..]].concat();
+        let mut zeros = vec![];
+        EncoderBuilder::new()
+            .block_size(BlockSize::new(1).unwrap())
+            .encode_stream(&vec![0u8; 2_000_000][..], &mut zeros)
+            .unwrap();
+        let length = 2 * input.len() as u64;
+
+        for (num_threads, small) in [(1, false), (3, false), (1, true)] {
+            let builder = DecoderBuilder::new().num_threads(num_threads).small(small);
+            let decode = |builder: DecoderBuilder, input: &[u8]| {
+                let mut decoded = vec![];
+                let result = builder.decode_stream(input, &mut decoded);
+                (result.map_err(|err| err.kind().to_string()), decoded)
+            };
+            let exceeded = |limit| Err(DecodeErrorKind::LimitExceeded(limit).to_string());
+
+            let (result, decoded) = decode(builder.clone().max_output(length - 10), &encoded);
+            assert_eq!(result, exceeded(DecodeLimit::Output(length - 10)));
+            assert!(decoded.len() as u64 <= length - 10);
+            assert!(decode(builder.clone().max_output(length), &encoded)
+                .0
+                .is_ok());
+
+            let (result, _) = decode(builder.clone().max_ratio(100), &zeros);
+            assert_eq!(result, exceeded(DecodeLimit::Ratio(100)));
+            assert!(decode(builder.clone().max_ratio(100), &encoded).0.is_ok());
+
+            let (result, decoded) = decode(builder.clone().max_blocks(3), &encoded);
+            assert_eq!(result, exceeded(DecodeLimit::Blocks(3)));
+            assert!(decoded.len() < input.len() * 2);
+            assert!(decode(builder.clone().max_blocks(4), &encoded).0.is_ok());
+
+            let (result, decoded) = decode(builder.clone().max_streams(1), &encoded);
+            assert_eq!(result, exceeded(DecodeLimit::Streams(1)));
+            assert_eq!(decoded, input);
+            assert!(decode(builder.clone().max_streams(2), &encoded).0.is_ok());
+
+            if !small {
+                // a block takes about 2 MB besides its decoded data at this block size
+                let (result, _) = decode(builder.clone().max_memory(1_000_000), &encoded);
+                assert_eq!(result, exceeded(DecodeLimit::Memory(1_000_000)));
+                let (result, _) = decode(builder.clone().max_memory(3_000_000), &zeros);
+                assert_eq!(result, exceeded(DecodeLimit::Memory(3_000_000)));
+                assert!(decode(builder.clone().max_memory(3_000_000), &encoded)
+                    .0
+                    .is_ok());
+            }
+        }
+    }
+}
@@ -0,0 +1,867 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::abort::{AbortHandle, Cancelled};
+use crate::bitwise::bitwriter::convert_to_code_pad_to_byte;
+use crate::bitwise::bitwriter::BitWriter;
+use crate::bitwise::bitwriter::BitWriterImpl;
+use crate::bitwise::Bit;
+use crate::block::block_encoder::crc_as_bytes;
+use crate::block::block_encoder::generate_block_data;
+use crate::block::block_encoder::BlockStats;
+use crate::block::crc32::crc32;
+use crate::block::rle::rle;
+use crate::block::rle::rle_augment;
+use crate::block::rle::rle_total_size;
+use crate::block::symbol_statistics::EncodingStrategy;
+
+use super::progress::{BlockProgress, ProgressObserver, ProgressReporter};
+use super::{worker_failure, BlockIndex, BlockInfo, BlockSize};
+
+pub(super) fn stream_footer(crc: u32) -> Vec<Bit> {
+    let mut out = vec![];
+
+    out.append(
+        &mut [0x17, 0x72, 0x45, 0x38, 0x50, 0x90]
+            .iter()
+            .flat_map(|x| convert_to_code_pad_to_byte(*x as u8))
+            .collect::<Vec<_>>(),
+    );
+    let mut crc_as_bits = crc_as_bytes(crc)
+        .iter()
+        .flat_map(|x| convert_to_code_pad_to_byte(*x))
+        .collect::<Vec<_>>();
+    out.append(&mut crc_as_bits);
+    out
+}
+
+pub(super) fn file_header(block_size: BlockSize) -> Vec<Bit> {
+    let mut out = vec![];
+    out.append(&mut convert_to_code_pad_to_byte(b'B'));
+    out.append(&mut convert_to_code_pad_to_byte(b'Z'));
+    out.append(&mut convert_to_code_pad_to_byte(b'h'));
+    out.append(&mut convert_to_code_pad_to_byte(block_size.header_byte()));
+    out
+}
+
+type Work = (u32, Vec<u8>);
+type ComputationResult = Result<(Vec<Bit>, BlockStats), Cancelled>;
+
+/// Summary of an encoded stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EncodeSummary {
+    /// Number of uncompressed bytes consumed.
+    pub input_bytes: u64,
+    /// Number of compressed bytes written.
+    pub output_bytes: u64,
+    /// Number of blocks in the stream.
+    pub num_blocks: usize,
+    /// Location of the blocks, if requested using [EncoderBuilder::build_index].
+    pub index: Option<BlockIndex>,
+    /// Statistics of the blocks written, in the order of the stream.
+    pub blocks: Vec<BlockStats>,
+}
+
+impl EncodeSummary {
+    /// Total time the worker threads spent on the Burrows-Wheeler transform.
+    pub fn bwt_time(&self) -> Duration {
+        self.blocks.iter().map(|block| block.bwt_time).sum()
+    }
+
+    /// Total time the worker threads spent on MTF, RLE2 and Huffman coding.
+    pub fn entropy_time(&self) -> Duration {
+        self.blocks.iter().map(|block| block.entropy_time).sum()
+    }
+}
+
+struct WorkerThread {
+    send_work: Option<Sender<Work>>,
+    receive_result: Receiver<ComputationResult>,
+    handle: Option<JoinHandle<()>>,
+    pending: bool,
+}
+
+impl WorkerThread {
+    fn spawn(name: &str, encoding_strategy: EncodingStrategy, abort: AbortHandle) -> Self {
+        let (send_work, receive_work) = channel::<Work>();
+        let (send_result, receive_result) = channel::<ComputationResult>();
+        let builder = thread::Builder::new().name(name.into());
+
+        let handle = builder
+            .spawn(move || {
+                while let Ok(work) = receive_work.recv() {
+                    let (computed_crc, rle_data) = work;
+                    let result =
+                        generate_block_data(computed_crc, &rle_data, encoding_strategy, &abort);
+                    if send_result.send(result).is_err() {
+                        break;
+                    }
+                }
+            })
+            .unwrap();
+        WorkerThread {
+            send_work: Some(send_work),
+            receive_result,
+            handle: Some(handle),
+            pending: false,
+        }
+    }
+
+    fn flush_work_buffer(&mut self, mut bit_writer: impl BitWriter) -> std::io::Result<BlockStats> {
+        self.pending = false;
+        let (bits, stats) = self.receive_result.recv().map_err(|_| worker_failure())??;
+
+        bit_writer.write_bits(&bits)?;
+        Ok(stats)
+    }
+
+    fn send_work(&mut self, work_to_send: Work) -> std::io::Result<()> {
+        self.pending = true;
+        self.send_work
+            .as_ref()
+            .unwrap()
+            .send(work_to_send)
+            .map_err(|_| worker_failure())
+    }
+}
+
+impl Drop for WorkerThread {
+    fn drop(&mut self) {
+        // closing the channel makes the thread leave its loop
+        self.send_work.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Configuration of a bzip2 encoder.
+///
+/// Defaults to a single thread, [EncodingStrategy::Single] and blocks of 900k.
+#[derive(Debug, Clone)]
+pub struct EncoderBuilder {
+    num_threads: usize,
+    encoding_strategy: EncodingStrategy,
+    block_size: BlockSize,
+    build_index: bool,
+    observer: Option<ProgressObserver>,
+    abort: AbortHandle,
+}
+
+impl EncoderBuilder {
+    pub fn new() -> Self {
+        EncoderBuilder {
+            num_threads: 1,
+            encoding_strategy: EncodingStrategy::Single,
+            block_size: BlockSize::default(),
+            build_index: false,
+            observer: None,
+            abort: AbortHandle::new(),
+        }
+    }
+
+    /// Number of worker threads encoding blocks in parallel (at least 1).
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    pub fn encoding_strategy(mut self, encoding_strategy: EncodingStrategy) -> Self {
+        self.encoding_strategy = encoding_strategy;
+        self
+    }
+
+    /// Size of the blocks the input is cut into, stored in the stream header.
+    pub fn block_size(mut self, block_size: BlockSize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Record the location of every block while encoding, see [EncodeSummary::index].
+    /// Bit offsets are counted from the start of the stream.
+    pub fn build_index(mut self, build_index: bool) -> Self {
+        self.build_index = build_index;
+        self
+    }
+
+    /// Call `observer` whenever a block has been written, see [BlockProgress].
+    pub fn on_progress(
+        mut self,
+        observer: impl Fn(&BlockProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.observer = Some(ProgressObserver::new(observer));
+        self
+    }
+
+    /// Stop encoding once `abort` is triggered, see [AbortHandle].
+    pub fn abort_handle(mut self, abort: AbortHandle) -> Self {
+        self.abort = abort;
+        self
+    }
+
+    /// Create an encoder writing into `writer`.
+    pub fn build<W: Write>(&self, writer: W) -> Bz2Encoder<W> {
+        Bz2Encoder::with_config(writer, self)
+    }
+
+    /// Encode everything from `read` into `writer`, see [crate::stream::encode_stream].
+    pub fn encode_stream(
+        &self,
+        mut read: impl std::io::Read,
+        writer: impl Write,
+    ) -> std::io::Result<EncodeSummary> {
+        let mut encoder = self.build(writer);
+        if let Err(err) = std::io::copy(&mut read, &mut encoder) {
+            encoder.abandon();
+            return Err(err);
+        }
+        encoder.try_finish()?;
+        Ok(encoder.summary())
+    }
+}
+
+impl Default for EncoderBuilder {
+    fn default() -> Self {
+        EncoderBuilder::new()
+    }
+}
+
+/// A bzip2 encoder which compresses everything written to it into the wrapped writer.
+///
+/// Input is cut into blocks of the configured [BlockSize] exactly as [crate::stream::encode_stream] does, hence
+/// both produce the same output for the same input and settings. Full blocks are handed
+/// to the worker threads as soon as they are complete. The stream is terminated by calling
+/// [Bz2Encoder::finish]; dropping the encoder finishes the stream as well but ignores errors.
+///
+/// Once writing to the underlying writer failed or encoding was cancelled using an
+/// [AbortHandle], the encoder refuses any further input and will not write a stream footer,
+/// so that a broken stream is never terminated like a valid one. Decoders report what has
+/// been written so far as truncated input.
+pub struct Bz2Encoder<W: Write> {
+    bit_writer: Option<BitWriterImpl<W>>,
+    worker_threads: Vec<WorkerThread>,
+    next_worker: usize,
+    block_size: BlockSize,
+    total_crc: u32,
+    chunk: Vec<u8>,
+    block_data: Vec<u8>,
+    rle_data: Vec<u8>,
+    rle_total_count: usize,
+    rle_count: usize,
+    rle_last_char: Option<u8>,
+    total_in: u64,
+    num_blocks: usize,
+    /// Checksum, uncompressed length and start of encoding of the blocks handed to the worker threads.
+    pending_blocks: VecDeque<(u32, u64, Instant)>,
+    blocks_written: usize,
+    /// Number of uncompressed bytes in the blocks written so far.
+    written_in: u64,
+    block_stats: Vec<BlockStats>,
+    progress: ProgressReporter,
+    abort: AbortHandle,
+    index: Option<Vec<BlockInfo>>,
+    header_written: bool,
+    finished: bool,
+    failed: bool,
+}
+
+impl<W: Write> Bz2Encoder<W> {
+    /// Create an encoder writing into `writer`.
+    /// The number of threads and the encoding strategy can be specified,
+    /// use [EncoderBuilder] for further settings.
+    pub fn new(writer: W, num_threads: usize, encoding_strategy: EncodingStrategy) -> Self {
+        EncoderBuilder::new()
+            .num_threads(num_threads)
+            .encoding_strategy(encoding_strategy)
+            .build(writer)
+    }
+
+    fn with_config(writer: W, config: &EncoderBuilder) -> Self {
+        let worker_threads = (0..config.num_threads)
+            .map(|num| {
+                WorkerThread::spawn(
+                    &format!("Thread {}", num),
+                    config.encoding_strategy,
+                    config.abort.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        Bz2Encoder {
+            bit_writer: Some(BitWriterImpl::from_writer(writer)),
+            worker_threads,
+            next_worker: 0,
+            block_size: config.block_size,
+            total_crc: 0,
+            chunk: vec![],
+            block_data: vec![],
+            rle_data: vec![],
+            rle_total_count: 0,
+            rle_count: 0,
+            rle_last_char: None,
+            total_in: 0,
+            num_blocks: 0,
+            pending_blocks: VecDeque::new(),
+            blocks_written: 0,
+            written_in: 0,
+            block_stats: vec![],
+            progress: ProgressReporter::new(config.observer.clone()),
+            abort: config.abort.clone(),
+            index: config.build_index.then(Vec::new),
+            header_written: false,
+            finished: false,
+            failed: false,
+        }
+    }
+
+    /// Acquire a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.bit_writer.as_ref().unwrap().get_ref()
+    }
+
+    /// Acquire a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.bit_writer().get_mut()
+    }
+
+    /// Number of uncompressed bytes written into the encoder so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Number of compressed bytes written to the underlying writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.bit_writer.as_ref().unwrap().bytes_written()
+    }
+
+    /// Summary of the stream encoded so far.
+    pub fn summary(&self) -> EncodeSummary {
+        EncodeSummary {
+            input_bytes: self.total_in(),
+            output_bytes: self.total_out(),
+            num_blocks: self.num_blocks,
+            index: self
+                .index
+                .clone()
+                .map(|blocks| BlockIndex::new(blocks, self.finished.then(|| self.total_out()))),
+            blocks: self.block_stats.clone(),
+        }
+    }
+
+    /// Write all pending blocks and the stream footer and flush the underlying writer.
+    /// Calling this method more than once has no further effect.
+    pub fn try_finish(&mut self) -> std::io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.check_failed()?;
+        let result = self.write_footer();
+        self.failed = result.is_err();
+        result
+    }
+
+    /// End the current block even if it is not full and write all pending blocks to the
+    /// underlying writer. As blocks are not aligned to bytes, up to seven bits of the last
+    /// block are held back until the next block or the footer follows, like in libbzip2.
+    /// Frequent use worsens the compression ratio.
+    pub fn flush_block(&mut self) -> std::io::Result<()> {
+        if self.finished {
+            return Err(std::io::Error::other(
+                "bzip2 stream has already been finished",
+            ));
+        }
+        self.check_failed()?;
+        let result = self.end_pending_blocks();
+        self.failed = result.is_err();
+        result
+    }
+
+    /// Finish the stream (see [Bz2Encoder::try_finish]) and return the underlying writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.try_finish()?;
+        Ok(self.bit_writer.take().unwrap().into_inner())
+    }
+
+    /// Give up on the stream: no footer is written, neither now nor on drop.
+    pub(crate) fn abandon(&mut self) {
+        self.failed = true;
+    }
+
+    fn check_failed(&self) -> std::io::Result<()> {
+        if self.failed {
+            Err(std::io::Error::other(
+                "bzip2 encoder is unusable after a previous error",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn end_pending_blocks(&mut self) -> std::io::Result<()> {
+        self.abort.check()?;
+        self.write_header()?;
+        if !self.chunk.is_empty() {
+            self.process_chunk();
+        }
+        if !self.block_data.is_empty() {
+            self.end_block()?;
+        }
+        self.flush_worker_threads()
+    }
+
+    fn write_footer(&mut self) -> std::io::Result<()> {
+        self.end_pending_blocks()?;
+
+        let total_crc = self.total_crc;
+        let bit_writer = self.bit_writer();
+        bit_writer.write_bits(&stream_footer(total_crc))?;
+        bit_writer.finalize()?;
+        bit_writer.get_mut().flush()?;
+        self.finished = true;
+        Ok(())
+    }
+
+    fn bit_writer(&mut self) -> &mut BitWriterImpl<W> {
+        self.bit_writer.as_mut().unwrap()
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        if !self.header_written {
+            let header = file_header(self.block_size);
+            self.bit_writer().write_bits(&header)?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Amount of input the next chunk should contain.
+    /// RLE can blow up 4chars to 5, hence we keep a safety margin.
+    /// The pending run may grow by a few bytes as well, which the extra 5 bytes account for.
+    fn chunk_size(&self) -> usize {
+        (self.block_size.max_block_length() - self.rle_total_count).saturating_sub(5) * 4 / 5
+    }
+
+    fn process_chunk(&mut self) {
+        let chunk = std::mem::take(&mut self.chunk);
+        let rle_result = rle(&chunk, self.rle_count, self.rle_last_char);
+        let mut rle_next = rle_result.data;
+
+        let next_data_len = self.rle_data.len() + rle_next.len();
+        self.rle_total_count =
+            rle_total_size(next_data_len, rle_result.counter, rle_result.last_byte);
+
+        self.rle_data.append(&mut rle_next);
+        self.block_data.extend_from_slice(&chunk);
+        self.rle_count = rle_result.counter;
+        self.rle_last_char = rle_result.last_byte;
+    }
+
+    fn end_block(&mut self) -> std::io::Result<()> {
+        let rle_total = rle_augment(&self.rle_data, self.rle_count, self.rle_last_char);
+        debug_assert!(rle_total.len() <= self.block_size.max_block_length());
+        let computed_crc = crc32(&self.block_data);
+        self.worker_threads[self.next_worker].send_work((computed_crc, rle_total))?;
+        self.num_blocks += 1;
+        self.pending_blocks
+            .push_back((computed_crc, self.block_data.len() as u64, Instant::now()));
+
+        self.block_data.clear();
+        self.rle_data.clear();
+        self.rle_total_count = 0;
+        self.rle_count = 0;
+        self.rle_last_char = None;
+
+        self.next_worker += 1;
+        if self.next_worker == self.worker_threads.len() {
+            self.flush_worker_threads()?;
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.abort.check()?;
+        let to_take = buf.len().min(self.chunk_size() - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..to_take]);
+        self.total_in += to_take as u64;
+        if self.chunk.len() == self.chunk_size() {
+            self.process_chunk();
+            if self.chunk_size() == 0 {
+                self.end_block()?;
+            }
+        }
+        Ok(to_take)
+    }
+
+    fn flush_worker_threads(&mut self) -> std::io::Result<()> {
+        let bit_writer = self.bit_writer.as_mut().unwrap();
+        for worker_thread in self.worker_threads.iter_mut() {
+            if worker_thread.pending {
+                let bit_offset = bit_writer.bits_written();
+                let mut stats = worker_thread.flush_work_buffer(&mut *bit_writer)?;
+                let (crc, uncompressed_length, started) = self.pending_blocks.pop_front().unwrap();
+                self.total_crc = crc ^ self.total_crc.rotate_left(1);
+                self.written_in += uncompressed_length;
+                stats.uncompressed_bytes = uncompressed_length;
+                self.block_stats.push(stats);
+                self.progress.report(
+                    self.blocks_written,
+                    self.written_in,
+                    bit_writer.bytes_written(),
+                    started,
+                );
+                self.blocks_written += 1;
+                if let Some(blocks) = self.index.as_mut() {
+                    let uncompressed_offset = blocks
+                        .last()
+                        .map_or(0, |block| block.uncompressed_end().unwrap());
+                    blocks.push(BlockInfo {
+                        stream_index: 0,
+                        block_size: self.block_size,
+                        bit_offset,
+                        bit_length: bit_writer.bits_written() - bit_offset,
+                        crc,
+                        uncompressed_offset: Some(uncompressed_offset),
+                        uncompressed_length: Some(uncompressed_length),
+                    });
+                }
+            }
+        }
+        self.next_worker = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Bz2Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.finished {
+            return Err(std::io::Error::other(
+                "bzip2 stream has already been finished",
+            ));
+        }
+        self.check_failed()?;
+        let result = self.write_header().and_then(|_| self.write_chunk(buf));
+        self.failed = result.is_err();
+        result
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.bit_writer().get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for Bz2Encoder<W> {
+    fn drop(&mut self) {
+        if self.bit_writer.is_some() && !self.failed {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::stream::{decode_stream, encode_stream};
+    use crate::test_util;
+    use crate::DecodeErrorKind;
+
+    fn sample() -> Vec<u8> {
+        b"If Peter Piper picked a peck of pickled peppers, where's the peck of pickled peppers Peter Piper picked?????"
+            .iter()
+            .cycle()
+            .take(20_000)
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    pub fn matches_encode_stream() {
+        let input = sample();
+        let mut expected = vec![];
+        encode_stream(&input[..], &mut expected, 2, EncodingStrategy::Single).unwrap();
+
+        let mut encoder = Bz2Encoder::new(vec![], 2, EncodingStrategy::Single);
+        for piece in input.chunks(333) {
+            encoder.write_all(piece).unwrap();
+        }
+        let encoded = encoder.finish().unwrap();
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    pub fn matches_encode_stream_for_multiple_blocks() {
+        let input = (0..2_000_000u32)
+            .map(|x| (x.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect::<Vec<_>>();
+        let mut expected = vec![];
+        encode_stream(&input[..], &mut expected, 3, EncodingStrategy::Single).unwrap();
+
+        let mut encoder = Bz2Encoder::new(vec![], 3, EncodingStrategy::Single);
+        for piece in input.chunks(100_003) {
+            encoder.write_all(piece).unwrap();
+        }
+        let encoded = encoder.finish().unwrap();
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    pub fn finishes_on_drop() {
+        let input = sample();
+        let mut encoded = vec![];
+        {
+            let mut encoder = Bz2Encoder::new(&mut encoded, 1, EncodingStrategy::Single);
+            encoder.write_all(&input).unwrap();
+        }
+        let mut decoded = vec![];
+        decode_stream(&encoded[..], &mut decoded).unwrap();
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    pub fn encodes_empty_input() {
+        let encoded = Bz2Encoder::new(vec![], 1, EncodingStrategy::Single)
+            .finish()
+            .unwrap();
+        let mut decoded = vec![];
+        decode_stream(&encoded[..], &mut decoded).unwrap();
+        assert!(decoded.is_empty());
+    }
+
+    struct FailingReader {
+        remaining: usize,
+    }
+
+    impl std::io::Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.remaining == 0 {
+                return Err(std::io::Error::other("read failed"));
+            }
+            let size = buf.len().min(self.remaining);
+            buf[..size].fill(b'a');
+            self.remaining -= size;
+            Ok(size)
+        }
+    }
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("write failed"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn propagates_read_errors() {
+        let mut encoded = vec![];
+        let result = encode_stream(
+            FailingReader { remaining: 10_000 },
+            &mut encoded,
+            1,
+            EncodingStrategy::Single,
+        );
+        assert_eq!(result.unwrap_err().to_string(), "read failed");
+
+        let mut decoded = vec![];
+        assert!(decode_stream(&encoded[..], &mut decoded).is_err());
+    }
+
+    #[test]
+    pub fn propagates_write_errors() {
+        let result = encode_stream(&sample()[..], FailingWriter, 1, EncodingStrategy::Single);
+        assert_eq!(result.unwrap_err().to_string(), "write failed");
+    }
+
+    #[test]
+    pub fn writes_block_size_into_header() {
+        for level in 1..=9 {
+            let encoded = EncoderBuilder::new()
+                .block_size(BlockSize::new(level).unwrap())
+                .build(vec![])
+                .finish()
+                .unwrap();
+            assert_eq!(encoded[..4], [b'B', b'Z', b'h', b'0' + level]);
+        }
+    }
+
+    #[test]
+    pub fn cuts_blocks_at_block_size() {
+        // runs of varying length make the RLE output grow unevenly
+        let input = (0..700_000u32)
+            .flat_map(|x| {
+                let byte = (x.wrapping_mul(2_654_435_761) >> 24) as u8;
+                std::iter::repeat_n(byte, (x % 7) as usize + 1)
+            })
+            .take(700_000)
+            .collect::<Vec<_>>();
+        let mut encoded = vec![];
+        let summary = EncoderBuilder::new()
+            .num_threads(2)
+            .block_size(BlockSize::new(1).unwrap())
+            .encode_stream(&input[..], &mut encoded)
+            .unwrap();
+        assert!(summary.num_blocks >= 7);
+
+        let mut decoded = vec![];
+        decode_stream(&encoded[..], &mut decoded).unwrap();
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    pub fn summarizes_stream() {
+        let input = sample();
+        let mut encoded = vec![];
+        let summary = encode_stream(&input[..], &mut encoded, 1, EncodingStrategy::Single).unwrap();
+        assert_eq!(summary.input_bytes, input.len() as u64);
+        assert_eq!(summary.output_bytes, encoded.len() as u64);
+        assert_eq!(summary.num_blocks, 1);
+    }
+
+    #[test]
+    pub fn summarizes_blocks() {
+        let input = test_util::sample(250_000);
+        let summary = EncoderBuilder::new()
+            .num_threads(2)
+            .block_size(BlockSize::new(1).unwrap())
+            .encoding_strategy(EncodingStrategy::BlockWise {
+                num_clusters: 4,
+                num_iterations: 2,
+            })
+            .encode_stream(&input[..], vec![])
+            .unwrap();
+        assert_eq!(summary.blocks.len(), summary.num_blocks);
+        let mut start = 0;
+        for block in &summary.blocks {
+            let end = start + block.uncompressed_bytes as usize;
+            let rle_result = rle(&input[start..end], 0, None);
+            let rle_bytes = rle_total_size(
+                rle_result.data.len(),
+                rle_result.counter,
+                rle_result.last_byte,
+            );
+            assert_eq!(block.rle_bytes, rle_bytes);
+            assert_eq!(block.num_selectors, (block.symbols + 1).div_ceil(50));
+            assert!((1..=4).contains(&block.tables_used));
+            start = end;
+        }
+        assert_eq!(start, input.len());
+        // stream header, blocks including their headers, stream footer and padding to a byte
+        let bits: u64 = summary
+            .blocks
+            .iter()
+            .map(|block| block.compressed_bits)
+            .sum();
+        let padding = summary.output_bytes * 8 - (32 + bits + 80);
+        assert!(padding < 8);
+    }
+
+    #[test]
+    pub fn counts_symbols_after_zero_run_length_encoding() {
+        // up to three equal bytes are a run of as many zeros after the move-to-front transform,
+        // written as the bits of the run length plus one after its leading one, while "ab"
+        // transforms to "ba" and then to two ones, each of which is a symbol of its own
+        for (input, symbols) in [(&b"a"[..], 1), (b"aa", 1), (b"aaa", 2), (b"ab", 2)] {
+            let summary = EncoderBuilder::new().encode_stream(input, vec![]).unwrap();
+            assert_eq!(summary.blocks[0].symbols, symbols);
+        }
+    }
+
+    #[test]
+    pub fn builds_index_while_encoding() {
+        let input = test_util::sample(350_000);
+        let mut encoded = vec![];
+        let summary = EncoderBuilder::new()
+            .num_threads(3)
+            .block_size(BlockSize::new(1).unwrap())
+            .build_index(true)
+            .encode_stream(&input[..], &mut encoded)
+            .unwrap();
+        let index = summary.index.unwrap();
+        assert_eq!(index.blocks().len(), summary.num_blocks);
+        assert_eq!(index, BlockIndex::build_decoded(&encoded[..]).unwrap());
+
+        let summary = encode_stream(&input[..], vec![], 1, EncodingStrategy::Single).unwrap();
+        assert!(summary.index.is_none());
+    }
+
+    #[test]
+    pub fn flushes_current_block() {
+        let mut encoder = Bz2Encoder::new(vec![], 2, EncodingStrategy::Single);
+        encoder.write_all(b"first").unwrap();
+        encoder.flush_block().unwrap();
+        let flushed = encoder.total_out();
+        assert!(flushed > 4);
+        // there is nothing to flush, hence no empty block is written
+        encoder.flush_block().unwrap();
+        assert_eq!(encoder.total_out(), flushed);
+        encoder.write_all(b" second").unwrap();
+        assert_eq!(encoder.summary().num_blocks, 1);
+        let encoded = encoder.finish().unwrap();
+
+        let mut decoded = vec![];
+        let summary = decode_stream(&encoded[..], &mut decoded).unwrap();
+        assert_eq!(decoded, b"first second");
+        assert_eq!(summary.num_blocks, 2);
+    }
+
+    #[test]
+    pub fn reports_progress_in_block_order() {
+        let input = test_util::sample(250_000);
+        let reports = Arc::new(Mutex::new(vec![]));
+        let observed = reports.clone();
+        let mut encoded = vec![];
+        let summary = EncoderBuilder::new()
+            .num_threads(2)
+            .block_size(BlockSize::new(1).unwrap())
+            .on_progress(move |progress| observed.lock().unwrap().push(progress.clone()))
+            .encode_stream(&input[..], &mut encoded)
+            .unwrap();
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), summary.num_blocks);
+        assert!(reports.len() > 2);
+        for (index, progress) in reports.iter().enumerate() {
+            assert_eq!(progress.block_index, index);
+            assert!(progress.block_time <= progress.elapsed);
+        }
+        assert!(reports.windows(2).all(|pair| {
+            pair[0].input_bytes < pair[1].input_bytes && pair[0].output_bytes < pair[1].output_bytes
+        }));
+        assert_eq!(reports.last().unwrap().input_bytes, input.len() as u64);
+        assert!(reports.last().unwrap().output_bytes < encoded.len() as u64);
+    }
+
+    #[test]
+    pub fn stops_when_aborted() {
+        let input = test_util::sample(350_000);
+        let abort = AbortHandle::new();
+        let observer_abort = abort.clone();
+        let mut encoded = vec![];
+        let err = EncoderBuilder::new()
+            .num_threads(2)
+            .block_size(BlockSize::new(1).unwrap())
+            .abort_handle(abort)
+            .on_progress(move |_| observer_abort.abort())
+            .encode_stream(&input[..], &mut encoded)
+            .unwrap_err();
+        assert!(Cancelled::is_cause_of(&err));
+
+        // no footer is written
+        let mut decoded = vec![];
+        let err = decode_stream(&encoded[..], &mut decoded).unwrap_err();
+        assert!(matches!(err.kind(), DecodeErrorKind::TruncatedInput));
+        assert_eq!(decoded, input[..decoded.len()]);
+    }
+}
@@ -0,0 +1,414 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::bitwise::bitreader::{BitReader, BitReaderImpl};
+use crate::block::block_decoder::decode_block;
+use crate::block::crc32::crc32;
+use crate::error::{DecodeError, DecodeErrorKind};
+
+use super::{BlockSize, DecoderBuilder};
+
+/// Location of a block in a bzip2 file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BlockInfo {
+    /// Index of the stream containing the block, starting at 0.
+    pub stream_index: usize,
+    /// Block size declared in the header of the stream containing the block.
+    pub block_size: BlockSize,
+    /// Offset of the block magic in bits from the start of the input.
+    pub bit_offset: u64,
+    /// Length of the block in bits, including the block magic.
+    pub bit_length: u64,
+    /// Checksum of the uncompressed data as stored in the block header.
+    pub crc: u32,
+    /// Offset of the uncompressed data of the block in the output, if the block was decoded.
+    pub uncompressed_offset: Option<u64>,
+    /// Length of the uncompressed data of the block, if the block was decoded.
+    pub uncompressed_length: Option<u64>,
+}
+
+impl BlockInfo {
+    /// Offset of the uncompressed data following the block, if the block was decoded.
+    pub fn uncompressed_end(&self) -> Option<u64> {
+        Some(self.uncompressed_offset? + self.uncompressed_length?)
+    }
+}
+
+const SIDECAR_MAGIC: &[u8; 6] = b"BZ2IDX";
+const SIDECAR_VERSION: u16 = 2;
+const SIDECAR_HEADER_LENGTH: usize = 24;
+const SIDECAR_ENTRY_LENGTH: usize = 42;
+
+fn invalid_sidecar(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn take<const N: usize>(data: &mut &[u8]) -> [u8; N] {
+    let (bytes, rest) = data.split_at(N);
+    *data = rest;
+    bytes.try_into().unwrap()
+}
+
+/// The blocks of all streams of a bzip2 file in the order they appear.
+///
+/// An index can be stored next to the file it describes (usually with the extension
+/// `.bz2.idx`) using [BlockIndex::write_sidecar], so that it is available without
+/// scanning the file again. The encoder creates it on the fly, see [super::EncoderBuilder::build_index].
+///
+/// The sidecar starts with the magic `BZ2IDX`, a version, the number of blocks and the length
+/// of the compressed file (0 if unknown), followed by an entry of 42 bytes for every block and a
+/// checksum of everything before. All numbers are little endian.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockIndex {
+    blocks: Vec<BlockInfo>,
+    compressed_length: Option<u64>,
+}
+
+impl BlockIndex {
+    pub(crate) fn new(blocks: Vec<BlockInfo>, compressed_length: Option<u64>) -> Self {
+        BlockIndex {
+            blocks,
+            compressed_length,
+        }
+    }
+
+    /// Locate all blocks of `reader` without decoding them.
+    ///
+    /// The Huffman coded data of every block is read to find its end, but the
+    /// Burrows-Wheeler transform and the other stages are skipped. Hence the checksums of the
+    /// blocks are not verified, though the checksums of the streams are checked against them.
+    pub fn build(reader: impl Read) -> Result<Self, DecodeError> {
+        BlockIndex::collect(reader, false)
+    }
+
+    /// Like [BlockIndex::build], but decode every block to verify it and to determine
+    /// where its data is located in the uncompressed output.
+    pub fn build_decoded(reader: impl Read) -> Result<Self, DecodeError> {
+        BlockIndex::collect(reader, true)
+    }
+
+    fn collect(reader: impl Read, decode: bool) -> Result<Self, DecodeError> {
+        let mut decoder = DecoderBuilder::new().build(reader);
+        let mut blocks = vec![];
+        while let Some(block) = decoder.next_block(decode)? {
+            blocks.push(block);
+        }
+        // the length of trailing garbage is not known
+        let compressed_length = (!decoder.trailing_garbage()).then(|| decoder.total_in());
+        Ok(BlockIndex::new(blocks, compressed_length))
+    }
+
+    pub fn blocks(&self) -> &[BlockInfo] {
+        &self.blocks
+    }
+
+    /// Length of the compressed file, if known.
+    pub fn compressed_length(&self) -> Option<u64> {
+        self.compressed_length
+    }
+
+    /// Fail if the compressed file `reader` has another length than the one the index was
+    /// built for, e.g. because a sidecar is stale. Moves the position of `reader` to its end.
+    pub fn check_compressed_length(&self, mut reader: impl Seek) -> std::io::Result<()> {
+        if let Some(compressed_length) = self.compressed_length {
+            if reader.seek(SeekFrom::End(0))? != compressed_length {
+                return Err(invalid_sidecar(
+                    "bzip2 block index belongs to a file of another length",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Length of the uncompressed data, if all blocks were decoded.
+    pub fn uncompressed_length(&self) -> Option<u64> {
+        self.blocks
+            .last()
+            .map_or(Some(0), BlockInfo::uncompressed_end)
+    }
+
+    /// Decode the block with the given number (starting at 0) of the file `reader` into `writer`
+    /// and return its checksum, without touching any other block. Fails with
+    /// [DecodeErrorKind::IndexMismatch] if the checksum or the uncompressed length of the block
+    /// differ from the index, and with an [std::io::ErrorKind::InvalidInput] error if the index
+    /// has no block with that number.
+    pub fn decode_block(
+        &self,
+        block_number: usize,
+        mut reader: impl Read + Seek,
+        mut writer: impl Write,
+    ) -> Result<u32, DecodeError> {
+        let block = self.blocks.get(block_number).ok_or_else(|| {
+            let err = std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "block number out of range",
+            );
+            DecodeError::new(err.into(), block_number + 1, 0)
+        })?;
+        let first_byte = block.bit_offset / 8;
+        reader
+            .seek(SeekFrom::Start(first_byte))
+            .map_err(|err| DecodeError::new(err.into(), block_number + 1, block.bit_offset))?;
+        let mut bit_reader = BitReaderImpl::from_reader(reader);
+        let mut data = vec![];
+        // skip to the block magic and the magic itself
+        bit_reader
+            .read_bits((block.bit_offset % 8) as usize + 48)
+            .and_then(|_| {
+                decode_block(
+                    &mut bit_reader,
+                    &mut data,
+                    block.block_size.max_block_length(),
+                )
+            })
+            .and_then(|crc| {
+                let length_matches = block
+                    .uncompressed_length
+                    .is_none_or(|length| length == data.len() as u64);
+                if crc != block.crc || !length_matches {
+                    return Err(DecodeErrorKind::IndexMismatch);
+                }
+                writer.write_all(&data)?;
+                Ok(crc)
+            })
+            .map_err(|kind| {
+                DecodeError::new(
+                    kind,
+                    block_number + 1,
+                    first_byte * 8 + bit_reader.position(),
+                )
+            })
+    }
+
+    /// Split the blocks into at most `num_parts` consecutive ranges of about the same compressed
+    /// size, e.g. to decode them independently using [BlockIndex::decode_block].
+    pub fn split(&self, num_parts: usize) -> Vec<Range<usize>> {
+        let num_parts = num_parts.max(1) as u64;
+        let total_bits = self
+            .blocks
+            .iter()
+            .map(|block| block.bit_length)
+            .sum::<u64>();
+        let mut parts = vec![];
+        let mut start = 0;
+        let mut bits = 0;
+        for (number, block) in self.blocks.iter().enumerate() {
+            bits += block.bit_length;
+            if bits * num_parts >= total_bits * (parts.len() as u64 + 1) {
+                parts.push(start..number + 1);
+                start = number + 1;
+            }
+        }
+        if start < self.blocks.len() {
+            parts.push(start..self.blocks.len());
+        }
+        parts
+    }
+
+    /// Store the index in the sidecar format described at [BlockIndex].
+    pub fn write_sidecar(&self, mut writer: impl Write) -> std::io::Result<()> {
+        let mut out = Vec::with_capacity(
+            SIDECAR_HEADER_LENGTH + self.blocks.len() * SIDECAR_ENTRY_LENGTH + 4,
+        );
+        out.extend_from_slice(SIDECAR_MAGIC);
+        out.extend_from_slice(&SIDECAR_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.blocks.len() as u64).to_le_bytes());
+        out.extend_from_slice(&self.compressed_length.unwrap_or(0).to_le_bytes());
+        for block in self.blocks.iter() {
+            let decoded =
+                block.uncompressed_offset.is_some() && block.uncompressed_length.is_some();
+            out.extend_from_slice(&(block.stream_index as u32).to_le_bytes());
+            out.push(block.block_size.level());
+            out.push(decoded as u8);
+            out.extend_from_slice(&block.bit_offset.to_le_bytes());
+            out.extend_from_slice(&block.bit_length.to_le_bytes());
+            out.extend_from_slice(&block.crc.to_le_bytes());
+            out.extend_from_slice(&block.uncompressed_offset.unwrap_or(0).to_le_bytes());
+            out.extend_from_slice(&block.uncompressed_length.unwrap_or(0).to_le_bytes());
+        }
+        out.extend_from_slice(&crc32(&out).to_le_bytes());
+        writer.write_all(&out)
+    }
+
+    /// Load an index stored by [BlockIndex::write_sidecar].
+    pub fn read_sidecar(mut reader: impl Read) -> std::io::Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() < SIDECAR_HEADER_LENGTH + 4 || &bytes[..6] != SIDECAR_MAGIC {
+            return Err(invalid_sidecar("not a bzip2 block index"));
+        }
+        let (mut data, checksum) = bytes.split_at(bytes.len() - 4);
+        if crc32(data) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+            return Err(invalid_sidecar("checksum mismatch in bzip2 block index"));
+        }
+        take::<6>(&mut data);
+        if u16::from_le_bytes(take(&mut data)) != SIDECAR_VERSION {
+            return Err(invalid_sidecar("unsupported version of bzip2 block index"));
+        }
+        let num_blocks = u64::from_le_bytes(take(&mut data));
+        let compressed_length = u64::from_le_bytes(take(&mut data));
+        if data.len() as u64 != num_blocks.saturating_mul(SIDECAR_ENTRY_LENGTH as u64) {
+            return Err(invalid_sidecar("invalid length of bzip2 block index"));
+        }
+        let mut blocks = vec![];
+        while !data.is_empty() {
+            let stream_index = u32::from_le_bytes(take(&mut data)) as usize;
+            let [level, decoded] = take(&mut data);
+            let block_size = BlockSize::new(level)
+                .ok_or_else(|| invalid_sidecar("invalid block size in bzip2 block index"))?;
+            let bit_offset = u64::from_le_bytes(take(&mut data));
+            let bit_length = u64::from_le_bytes(take(&mut data));
+            let crc = u32::from_le_bytes(take(&mut data));
+            let uncompressed_offset = u64::from_le_bytes(take(&mut data));
+            let uncompressed_length = u64::from_le_bytes(take(&mut data));
+            blocks.push(BlockInfo {
+                stream_index,
+                block_size,
+                bit_offset,
+                bit_length,
+                crc,
+                uncompressed_offset: (decoded != 0).then_some(uncompressed_offset),
+                uncompressed_length: (decoded != 0).then_some(uncompressed_length),
+            });
+        }
+        Ok(BlockIndex::new(
+            blocks,
+            (compressed_length != 0).then_some(compressed_length),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{encode, sample};
+
+    #[test]
+    pub fn locates_blocks_of_all_streams() {
+        let first = encode(&sample(250_000), 1);
+        let mut encoded = first.clone();
+        encoded.extend(encode(b"second stream", 9));
+
+        let index = BlockIndex::build(&encoded[..]).unwrap();
+        let blocks = index.blocks();
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[0].bit_offset, 32);
+        for pair in blocks[..3].windows(2) {
+            assert_eq!(pair[0].bit_offset + pair[0].bit_length, pair[1].bit_offset);
+        }
+        assert_eq!(blocks[3].stream_index, 1);
+        assert_eq!(blocks[3].block_size.level(), 9);
+        assert_eq!(blocks[3].bit_offset, first.len() as u64 * 8 + 32);
+        assert!(blocks
+            .iter()
+            .all(|block| block.uncompressed_offset.is_none()));
+    }
+
+    #[test]
+    pub fn determines_uncompressed_ranges_by_decoding() {
+        let input = sample(250_000);
+        let encoded = encode(&input, 1);
+        let index = BlockIndex::build(&encoded[..]).unwrap();
+        let decoded_index = BlockIndex::build_decoded(&encoded[..]).unwrap();
+
+        let mut offset = 0;
+        for (block, decoded_block) in index.blocks().iter().zip(decoded_index.blocks()) {
+            assert_eq!(block.bit_offset, decoded_block.bit_offset);
+            assert_eq!(block.crc, decoded_block.crc);
+            assert_eq!(decoded_block.uncompressed_offset, Some(offset));
+            offset += decoded_block.uncompressed_length.unwrap();
+        }
+        assert_eq!(offset, input.len() as u64);
+    }
+
+    #[test]
+    pub fn verifies_stream_checksum() {
+        let mut encoded = encode(b"some data", 9);
+        let last = encoded.len() - 2;
+        encoded[last] ^= 1;
+        let err = BlockIndex::build(&encoded[..]).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            DecodeErrorKind::StreamCrcMismatch { .. }
+        ));
+    }
+
+    #[test]
+    pub fn stores_index_in_sidecar() {
+        let encoded = encode(&sample(250_000), 1);
+        for index in [
+            BlockIndex::build(&encoded[..]).unwrap(),
+            BlockIndex::build_decoded(&encoded[..]).unwrap(),
+        ] {
+            assert_eq!(index.compressed_length(), Some(encoded.len() as u64));
+            let mut sidecar = vec![];
+            index.write_sidecar(&mut sidecar).unwrap();
+            assert_eq!(sidecar.len(), 24 + 3 * 42 + 4);
+            assert_eq!(BlockIndex::read_sidecar(&sidecar[..]).unwrap(), index);
+
+            for position in [3, 7, 30, sidecar.len() - 1] {
+                let mut corrupted = sidecar.clone();
+                corrupted[position] ^= 1;
+                assert!(BlockIndex::read_sidecar(&corrupted[..]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    pub fn rejects_blocks_not_matching_index() {
+        let encoded = encode(&sample(150_000), 1);
+        let index = BlockIndex::build_decoded(&encoded[..]).unwrap();
+        let mut other_crc = index.blocks().to_vec();
+        other_crc[1].crc ^= 1;
+        let mut other_length = index.blocks().to_vec();
+        *other_length[1].uncompressed_length.as_mut().unwrap() -= 1;
+
+        for blocks in [other_crc, other_length] {
+            let stale = BlockIndex::new(blocks, index.compressed_length());
+            let mut decoded = vec![];
+            let err = stale
+                .decode_block(1, std::io::Cursor::new(&encoded), &mut decoded)
+                .unwrap_err();
+            assert!(matches!(err.kind(), DecodeErrorKind::IndexMismatch));
+            assert!(decoded.is_empty());
+        }
+        assert!(index
+            .check_compressed_length(std::io::Cursor::new(&encoded[1..]))
+            .is_err());
+    }
+
+    #[test]
+    pub fn rejects_block_numbers_out_of_range() {
+        let encoded = encode(b"a single block", 1);
+        let index = BlockIndex::build_decoded(&encoded[..]).unwrap();
+        let err = index
+            .decode_block(1, std::io::Cursor::new(&encoded), vec![])
+            .unwrap_err();
+        match err.kind() {
+            DecodeErrorKind::Io(err) => assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    pub fn splits_into_parts_of_similar_size() {
+        let input = sample(950_000);
+        let encoded = encode(&input, 1);
+        let index = BlockIndex::build_decoded(&encoded[..]).unwrap();
+        assert_eq!(index.split(1), vec![0..10]);
+        assert_eq!(index.split(20).len(), 10);
+        let parts = index.split(3);
+        assert_eq!(parts, vec![0..4, 4..7, 7..10]);
+
+        let mut decoded = vec![];
+        for part in parts {
+            for number in part {
+                index
+                    .decode_block(number, std::io::Cursor::new(&encoded), &mut decoded)
+                    .unwrap();
+            }
+        }
+        assert_eq!(decoded, input);
+    }
+}
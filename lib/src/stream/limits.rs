@@ -0,0 +1,110 @@
+use crate::error::{DecodeErrorKind, DecodeLimit};
+
+/// Estimated memory needed to decode a block per byte of its block size, besides the
+/// decoded data itself.
+const DECODE_MEMORY_PER_BYTE: usize = 20;
+
+/// Limits set on [super::DecoderBuilder], none of them is set by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct DecodeLimits {
+    pub(crate) max_output: Option<u64>,
+    pub(crate) max_ratio: Option<u64>,
+    pub(crate) max_blocks: Option<usize>,
+    pub(crate) max_streams: Option<usize>,
+    pub(crate) max_memory: Option<usize>,
+}
+
+impl DecodeLimits {
+    /// Fail if `num_blocks` blocks, counted across streams, exceed the limit.
+    pub(crate) fn check_blocks(&self, num_blocks: usize) -> Result<(), DecodeErrorKind> {
+        match self.max_blocks {
+            Some(max) if num_blocks > max => Err(exceeded(DecodeLimit::Blocks(max))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if `num_streams` streams exceed the limit.
+    pub(crate) fn check_streams(&self, num_streams: usize) -> Result<(), DecodeErrorKind> {
+        match self.max_streams {
+            Some(max) if num_streams > max => Err(exceeded(DecodeLimit::Streams(max))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if decoding `output_bytes` from the first `input_bytes` of the input exceeds the
+    /// limit of the output or of the expansion ratio.
+    pub(crate) fn check_output(
+        &self,
+        input_bytes: u64,
+        output_bytes: u64,
+    ) -> Result<(), DecodeErrorKind> {
+        match self.max_output {
+            Some(max) if output_bytes > max => return Err(exceeded(DecodeLimit::Output(max))),
+            _ => {}
+        }
+        match self.max_ratio {
+            Some(ratio) if output_bytes > ratio.saturating_mul(input_bytes) => {
+                Err(exceeded(DecodeLimit::Ratio(ratio)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of bytes which may be decoded in addition to `output_bytes` once the first
+    /// `input_bytes` of the input are read, see [DecodeLimits::check_output].
+    pub(crate) fn remaining_output(&self, input_bytes: u64, output_bytes: u64) -> usize {
+        let max_output = self.max_output.unwrap_or(u64::MAX);
+        let max_ratio = self
+            .max_ratio
+            .map_or(u64::MAX, |ratio| ratio.saturating_mul(input_bytes));
+        let remaining = max_output.min(max_ratio).saturating_sub(output_bytes);
+        usize::try_from(remaining).unwrap_or(usize::MAX)
+    }
+
+    /// How many of `wanted` blocks of up to `max_block_length` bytes can be decoded at once
+    /// within the memory limit, besides `reserved` bytes used otherwise (e.g. for the compressed
+    /// input), and how many bytes the decoded data of each of them may take. Fails if not even
+    /// a single block fits.
+    pub(crate) fn blocks_in_memory(
+        &self,
+        max_block_length: usize,
+        wanted: usize,
+        reserved: usize,
+    ) -> Result<(usize, usize), DecodeErrorKind> {
+        let Some(max_memory) = self.max_memory else {
+            return Ok((wanted, usize::MAX));
+        };
+        let available = max_memory.saturating_sub(reserved);
+        let working_memory = DECODE_MEMORY_PER_BYTE * max_block_length;
+        let blocks = (available / (working_memory + max_block_length)).min(wanted);
+        if blocks == 0 {
+            return Err(exceeded(DecodeLimit::Memory(max_memory)));
+        }
+        Ok((blocks, available / blocks - working_memory))
+    }
+
+    /// Fail if holding `input_bytes` of the compressed input exceeds the memory limit.
+    pub(crate) fn check_input(&self, input_bytes: usize) -> Result<(), DecodeErrorKind> {
+        match self.max_memory {
+            Some(max) if input_bytes > max => Err(exceeded(DecodeLimit::Memory(max))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if a block decoded to more than the `share` of memory given by
+    /// [DecodeLimits::blocks_in_memory].
+    pub(crate) fn check_memory(
+        &self,
+        decoded_bytes: usize,
+        share: usize,
+    ) -> Result<(), DecodeErrorKind> {
+        match self.max_memory {
+            Some(max) if decoded_bytes > share => Err(exceeded(DecodeLimit::Memory(max))),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn exceeded(limit: DecodeLimit) -> DecodeErrorKind {
+    DecodeErrorKind::LimitExceeded(limit)
+}
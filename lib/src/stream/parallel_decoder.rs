@@ -0,0 +1,999 @@
+use std::collections::VecDeque;
+use std::io::Read;
+use std::io::Write;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use crate::abort::AbortHandle;
+use crate::bitwise::bitreader::BitReader;
+use crate::bitwise::bitreader::BitReaderImpl;
+use crate::bitwise::bitreader::SliceBitReader;
+use crate::bitwise::bitwriter::convert_to_number;
+use crate::block::block_decoder::parse_block;
+use crate::error::{DecodeError, DecodeErrorKind};
+
+use super::corruption::{CorruptionHandler, CorruptionIncident};
+use super::decoder::{
+    read_file_header, read_next_stream_header, what_next, BlockType, DecodeSummary, NextStream,
+};
+use super::limits::DecodeLimits;
+use super::progress::{ProgressObserver, ProgressReporter};
+use super::{worker_failure, BlockSize};
+
+pub(super) const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+pub(super) const FOOTER_MAGIC: u64 = 0x1772_4538_5090;
+pub(super) const MAGIC_BITS: u64 = 48;
+const MAGIC_MASK: u64 = (1 << MAGIC_BITS) - 1;
+const READ_SIZE: usize = 1 << 20;
+/// Upper bound of the elements scanned ahead of the output, which keeps an input of many empty
+/// streams from being queued at once.
+const MAX_PENDING: usize = 1 << 10;
+/// Upper bound of the length of a block in bits: at most 900k symbols (and the end of block
+/// symbol) of up to 20 bits each, plus the tables and selectors.
+pub(super) const MAX_BLOCK_BITS: u64 = 20_000_000;
+
+/// The compressed input from the oldest block which is not decoded yet onwards.
+pub(super) struct InputWindow<R: Read> {
+    reader: R,
+    bytes: Vec<u8>,
+    start: u64,
+    end_of_input: bool,
+}
+
+impl<R: Read> InputWindow<R> {
+    pub(super) fn new(reader: R) -> Self {
+        InputWindow {
+            reader,
+            bytes: vec![],
+            start: 0,
+            end_of_input: false,
+        }
+    }
+
+    /// Position of the end of the input read so far, in bits.
+    pub(super) fn end_bit(&self) -> u64 {
+        (self.start + self.bytes.len() as u64) * 8
+    }
+
+    /// Read from the input until the bits before `bit` are available or the input ends.
+    pub(super) fn fill_to(&mut self, bit: u64) -> Result<(), DecodeErrorKind> {
+        while !self.end_of_input && self.end_bit() < bit {
+            let len = self.bytes.len();
+            self.bytes.resize(len + READ_SIZE, 0);
+            let size = loop {
+                match self.reader.read(&mut self.bytes[len..]) {
+                    Ok(size) => break size,
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        self.bytes.truncate(len);
+                        return Err(DecodeErrorKind::Io(err));
+                    }
+                }
+            };
+            self.bytes.truncate(len + size);
+            self.end_of_input = size == 0;
+        }
+        Ok(())
+    }
+
+    /// Forget the bytes before the one containing `bit`. They are only dropped once they amount
+    /// to a read, so that calling this often stays cheap.
+    pub(super) fn discard_before(&mut self, bit: u64) {
+        let byte = bit / 8;
+        if byte >= self.start + READ_SIZE as u64 {
+            self.bytes.drain(..(byte - self.start) as usize);
+            self.start = byte;
+        }
+    }
+
+    /// Copy of the bits from `from` to `to` (or the end of the input read so far).
+    pub(super) fn segment(&self, from: u64, to: u64) -> Segment {
+        let first = (from / 8 - self.start) as usize;
+        let last = ((to.div_ceil(8) - self.start) as usize).min(self.bytes.len());
+        Segment {
+            bytes: self.bytes[first..last.max(first)].to_vec(),
+            start_bit: from,
+        }
+    }
+
+    /// Position of the first block or stream footer magic at or after `from`,
+    /// or the end of the input if there is none.
+    pub(super) fn find_magic(&mut self, from: u64) -> Result<u64, DecodeErrorKind> {
+        self.find_magic_before(from, u64::MAX)
+    }
+
+    /// Like [InputWindow::find_magic], but only the input before `limit` is searched. Returns
+    /// `limit` if no magic ends before it.
+    pub(super) fn find_magic_before(
+        &mut self,
+        from: u64,
+        limit: u64,
+    ) -> Result<u64, DecodeErrorKind> {
+        let mut register = 0u64;
+        let mut bits_seen = 0;
+        let mut byte_index = from / 8;
+        loop {
+            if byte_index * 8 >= limit {
+                return Ok(limit);
+            }
+            self.fill_to((byte_index + 1) * 8)?;
+            if byte_index * 8 >= self.end_bit() {
+                return Ok(self.end_bit().min(limit));
+            }
+            let first = (byte_index - self.start) as usize;
+            let last = (limit.div_ceil(8) - self.start).min(self.bytes.len() as u64) as usize;
+            for &byte in self.bytes[first..last].iter() {
+                for shift in (0..8).rev() {
+                    let bit = byte_index * 8 + 7 - shift;
+                    if bit < from || bit >= limit {
+                        continue;
+                    }
+                    register = (register << 1) | u64::from((byte >> shift) & 1);
+                    bits_seen += 1;
+                    if bits_seen >= MAGIC_BITS {
+                        let candidate = register & MAGIC_MASK;
+                        if candidate == BLOCK_MAGIC || candidate == FOOTER_MAGIC {
+                            return Ok(bit + 1 - MAGIC_BITS);
+                        }
+                    }
+                }
+                byte_index += 1;
+            }
+        }
+    }
+}
+
+/// Part of the compressed input starting at an arbitrary bit.
+pub(super) struct Segment {
+    pub(super) bytes: Vec<u8>,
+    pub(super) start_bit: u64,
+}
+
+impl Segment {
+    pub(super) fn reader(&self) -> BitReaderImpl<&[u8]> {
+        let mut reader = BitReaderImpl::from_reader(&self.bytes[..]);
+        // the first byte contains the start bit, hence skipping cannot fail
+        let _ = reader.read_bits((self.start_bit % 8) as usize);
+        reader
+    }
+
+    /// Position of a reader created by [Segment::reader] in the whole input.
+    fn position(&self, reader: &BitReaderImpl<&[u8]>) -> u64 {
+        (self.start_bit / 8) * 8 + reader.position()
+    }
+}
+
+pub(super) struct DecodedBlock {
+    pub(super) data: Vec<u8>,
+    /// Checksum stored in the block header.
+    pub(super) crc: u32,
+    /// Checksum of `data`, which differs from `crc` if the block is corrupted.
+    pub(super) actual_crc: u32,
+    pub(super) end_bit: u64,
+}
+
+pub(super) type BlockResult = Result<DecodedBlock, (DecodeErrorKind, u64)>;
+
+/// Decode the block whose data (following the block magic) starts the segment, without
+/// verifying its checksum. Decoding stops early once the data exceeds `max_output` bytes, see
+/// [crate::block::block_decoder::ParsedBlock::reconstruct_unverified].
+pub(super) fn decode_segment(
+    segment: &Segment,
+    max_block_length: usize,
+    max_output: usize,
+) -> BlockResult {
+    let mut reader = segment.reader();
+    let decoded = parse_block(&mut reader).and_then(|parsed_block| {
+        let crc = parsed_block.crc();
+        let (data, actual_crc) =
+            parsed_block.reconstruct_unverified(max_block_length, max_output)?;
+        Ok((data, crc, actual_crc))
+    });
+    match decoded {
+        Ok((data, crc, actual_crc)) => Ok(DecodedBlock {
+            data,
+            crc,
+            actual_crc,
+            end_bit: segment.position(&reader),
+        }),
+        Err(kind) => Err((kind, segment.position(&reader))),
+    }
+}
+
+/// A segment to decode, the maximum block length and the maximum output, see [decode_segment].
+type Work = (Segment, usize, usize);
+
+struct WorkerThread {
+    send_work: Option<Sender<Work>>,
+    receive_result: Receiver<BlockResult>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl WorkerThread {
+    fn spawn(name: &str, abort: AbortHandle) -> Self {
+        let (send_work, receive_work) = channel::<Work>();
+        let (send_result, receive_result) = channel::<BlockResult>();
+        let builder = thread::Builder::new().name(name.into());
+
+        let handle = builder
+            .spawn(move || {
+                while let Ok((segment, max_block_length, max_output)) = receive_work.recv() {
+                    // once cancelled, the remaining work is skipped
+                    let result = match abort.check() {
+                        Ok(()) => decode_segment(&segment, max_block_length, max_output),
+                        Err(cancelled) => Err((cancelled.into(), segment.start_bit)),
+                    };
+                    if send_result.send(result).is_err() {
+                        break;
+                    }
+                }
+            })
+            .unwrap();
+        WorkerThread {
+            send_work: Some(send_work),
+            receive_result,
+            handle: Some(handle),
+        }
+    }
+
+    fn send_work(&self, work: Work) -> Result<(), DecodeErrorKind> {
+        self.send_work
+            .as_ref()
+            .unwrap()
+            .send(work)
+            .map_err(|_| DecodeErrorKind::Io(worker_failure()))
+    }
+
+    fn receive_result(&self) -> Result<BlockResult, DecodeErrorKind> {
+        self.receive_result
+            .recv()
+            .map_err(|_| DecodeErrorKind::Io(worker_failure()))
+    }
+}
+
+impl Drop for WorkerThread {
+    fn drop(&mut self) {
+        // closing the channel makes the thread leave its loop
+        self.send_work.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// What the scanner found in the input, processed in order once all blocks before are decoded.
+enum Pending {
+    StreamHeader,
+    Block {
+        worker: usize,
+        magic_bit: u64,
+        planned_end: u64,
+        block_size: BlockSize,
+        /// Size the decoded data may take within the memory limit.
+        memory_share: usize,
+        dispatched: Instant,
+    },
+    StreamFooter {
+        crc: u32,
+        bit: u64,
+    },
+    End {
+        trailing_garbage: bool,
+        bit: u64,
+    },
+    Error(DecodeError),
+}
+
+enum ScanState {
+    FirstStreamHeader,
+    NextStreamHeader,
+    InStream(BlockSize),
+    Done,
+}
+
+/// Decodes the blocks of the input concurrently.
+///
+/// The scanner runs ahead of the decoded output: it assumes every block ends where the next
+/// block or stream footer magic is found and hands the bits in between to a worker thread.
+/// As the magic may also occur by chance inside of a block, results are validated in order:
+/// a block which runs past its assumed end is decoded again up to the next candidate, and
+/// whenever a block ends elsewhere everything scanned after it is discarded and scanning
+/// restarts at its actual end. Hence the output and errors match the sequential decoder.
+pub(super) struct ParallelDecoder<R: Read> {
+    window: InputWindow<R>,
+    worker_threads: Vec<WorkerThread>,
+    next_worker: usize,
+    pending: VecDeque<Pending>,
+    blocks_in_flight: usize,
+    scan_position: u64,
+    scan_state: ScanState,
+    multi_stream: bool,
+    stream_crc: u32,
+    /// Whether a block of the current stream did not match its checksum.
+    stream_corrupted: bool,
+    num_streams: usize,
+    block_number: usize,
+    /// Number of streams and blocks found by the scanner, including those not decoded yet.
+    scanned_streams: usize,
+    scanned_blocks: usize,
+    total_out: u64,
+    progress: ProgressReporter,
+    corruption: CorruptionHandler,
+    limits: DecodeLimits,
+    abort: AbortHandle,
+}
+
+impl<R: Read> ParallelDecoder<R> {
+    /// Create a decoder of all streams of `reader` using `num_threads` threads.
+    pub(super) fn new(
+        reader: R,
+        num_threads: usize,
+        multi_stream: bool,
+        observer: Option<ProgressObserver>,
+        corruption: CorruptionHandler,
+        limits: DecodeLimits,
+        abort: AbortHandle,
+    ) -> Self {
+        ParallelDecoder {
+            window: InputWindow::new(reader),
+            worker_threads: (0..num_threads.max(1))
+                .map(|num| WorkerThread::spawn(&format!("Decoder {}", num), abort.clone()))
+                .collect(),
+            next_worker: 0,
+            pending: VecDeque::new(),
+            blocks_in_flight: 0,
+            scan_position: 0,
+            scan_state: ScanState::FirstStreamHeader,
+            multi_stream,
+            stream_crc: 0,
+            stream_corrupted: false,
+            num_streams: 0,
+            block_number: 0,
+            scanned_streams: 0,
+            scanned_blocks: 0,
+            total_out: 0,
+            progress: ProgressReporter::new(observer),
+            corruption,
+            limits,
+            abort,
+        }
+    }
+
+    /// Decode everything into `writer`.
+    pub(super) fn run(mut self, mut writer: impl Write) -> Result<DecodeSummary, DecodeError> {
+        loop {
+            self.scan_ahead();
+            if let Err(cancelled) = self.abort.check() {
+                let bit = self.oldest_needed();
+                return Err(self.error(cancelled.into(), bit));
+            }
+            match self.pending.pop_front().unwrap() {
+                Pending::StreamHeader => {
+                    self.num_streams += 1;
+                    self.stream_crc = 0;
+                    self.stream_corrupted = false;
+                    self.discard_decoded();
+                }
+                Pending::Block {
+                    worker,
+                    magic_bit,
+                    planned_end,
+                    block_size,
+                    memory_share,
+                    dispatched,
+                } => {
+                    self.blocks_in_flight -= 1;
+                    self.block_number += 1;
+                    let result = self.worker_threads[worker]
+                        .receive_result()
+                        .map_err(|kind| self.error(kind, magic_bit))?;
+                    let mut block =
+                        self.validate(result, magic_bit, planned_end, block_size, memory_share)?;
+                    let input_bytes = block.end_bit.div_ceil(8);
+                    self.limits
+                        .check_output(input_bytes, self.total_out + block.data.len() as u64)
+                        .and_then(|()| self.limits.check_memory(block.data.len(), memory_share))
+                        .map_err(|kind| self.error(kind, block.end_bit))?;
+                    if block.actual_crc != block.crc {
+                        let incident = CorruptionIncident {
+                            block_number: self.block_number,
+                            bit_offset: magic_bit,
+                            output_offset: self.total_out,
+                            decoded_bytes: block.data.len() as u64,
+                            expected_crc: block.crc,
+                            actual_crc: block.actual_crc,
+                        };
+                        self.corruption
+                            .handle(&incident, &mut block.data)
+                            .map_err(|kind| self.error(kind, block.end_bit))?;
+                        self.stream_corrupted = true;
+                    }
+                    writer
+                        .write_all(&block.data)
+                        .map_err(|err| self.error(DecodeErrorKind::Io(err), block.end_bit))?;
+                    self.stream_crc = block.crc ^ self.stream_crc.rotate_left(1);
+                    self.total_out += block.data.len() as u64;
+                    self.progress.report(
+                        self.block_number - 1,
+                        block.end_bit.div_ceil(8),
+                        self.total_out,
+                        dispatched,
+                    );
+                    if block.end_bit != planned_end {
+                        self.restart_scan(block.end_bit, block_size)
+                            .map_err(|kind| self.error(kind, block.end_bit))?;
+                    }
+                    self.discard_decoded();
+                }
+                Pending::StreamFooter { crc, bit } => {
+                    if crc != self.stream_crc && !self.stream_corrupted {
+                        let kind = DecodeErrorKind::StreamCrcMismatch {
+                            expected: crc,
+                            actual: self.stream_crc,
+                        };
+                        return Err(self.error(kind, bit));
+                    }
+                    self.discard_decoded();
+                }
+                Pending::End {
+                    trailing_garbage,
+                    bit,
+                } => {
+                    return Ok(DecodeSummary {
+                        input_bytes: bit.div_ceil(8),
+                        output_bytes: self.total_out,
+                        num_streams: self.num_streams,
+                        num_blocks: self.block_number,
+                        trailing_garbage,
+                    })
+                }
+                Pending::Error(err) => return Err(err),
+            }
+        }
+    }
+
+    fn error(&self, kind: DecodeErrorKind, bit: u64) -> DecodeError {
+        DecodeError::new(kind, self.block_number, bit)
+    }
+
+    /// Scan ahead as far as the blocks in flight and the memory limit permit.
+    fn scan_ahead(&mut self) {
+        while self.may_scan() && self.scan_next() {}
+    }
+
+    /// Whether to scan the next element: up to two blocks per thread are decoded concurrently
+    /// unless the memory limit requires fewer, and at most [MAX_PENDING] elements are queued.
+    fn may_scan(&self) -> bool {
+        if matches!(self.scan_state, ScanState::Done) || self.pending.len() >= MAX_PENDING {
+            return false;
+        }
+        let wanted = 2 * self.worker_threads.len();
+        let ScanState::InStream(block_size) = self.scan_state else {
+            return self.blocks_in_flight < wanted;
+        };
+        if self.blocks_in_flight == 0 {
+            // a block which does not fit on its own fails in dispatch_block
+            return true;
+        }
+        let reserved = held_bytes(self.oldest_needed(), self.scan_position);
+        self.limits
+            .blocks_in_memory(block_size.max_block_length(), wanted, reserved)
+            .is_ok_and(|(blocks, _)| self.blocks_in_flight < blocks)
+    }
+
+    /// Scan the next element of the input and dispatch it if it is a block. Returns false if a
+    /// block has to wait for the blocks in flight to free memory.
+    fn scan_next(&mut self) -> bool {
+        let position = self.scan_position;
+        if let Err(kind) = self.window.fill_to(position + 80).and_then(|()| {
+            self.limits
+                .check_input(held_bytes(self.oldest_needed(), position))
+        }) {
+            self.fail_scan(kind, position);
+            return true;
+        }
+        // a stream header or footer is short, hence it is read without a buffered reader
+        let segment = self.window.segment(position, position + 80);
+        let mut reader = SliceBitReader::new(&segment.bytes, &[], position % 8);
+        let read_to = |reader: &SliceBitReader| position / 8 * 8 + reader.position();
+        match self.scan_state {
+            ScanState::FirstStreamHeader => match read_file_header(&mut reader) {
+                Ok(block_size) => self.start_stream(block_size, position),
+                Err(kind) => self.fail_scan(kind, read_to(&reader)),
+            },
+            ScanState::NextStreamHeader => match read_next_stream_header(&mut reader) {
+                Ok(NextStream::Header(block_size)) => self.start_stream(block_size, position),
+                Ok(NextStream::EndOfInput) => self.end_scan(false, position),
+                Ok(NextStream::TrailingGarbage) => self.end_scan(true, read_to(&reader)),
+                Err(kind) => self.fail_scan(kind, read_to(&reader)),
+            },
+            ScanState::InStream(block_size) => match what_next(&mut reader) {
+                Ok(BlockType::BlockHeader) => match self.dispatch_block(block_size) {
+                    Ok(dispatched) => return dispatched,
+                    Err(kind) => self.fail_scan(kind, position),
+                },
+                Ok(BlockType::StreamFooter) => match reader.read_bits(32) {
+                    Ok(crc) => {
+                        let bit = read_to(&reader);
+                        self.pending.push_back(Pending::StreamFooter {
+                            crc: convert_to_number(&crc) as u32,
+                            bit,
+                        });
+                        if self.multi_stream {
+                            // streams are padded to whole bytes
+                            self.scan_position = bit.div_ceil(8) * 8;
+                            self.scan_state = ScanState::NextStreamHeader;
+                        } else {
+                            self.end_scan(false, bit);
+                        }
+                    }
+                    Err(kind) => self.fail_scan(kind, read_to(&reader)),
+                },
+                Err(kind) => self.fail_scan(kind, read_to(&reader)),
+            },
+            ScanState::Done => {}
+        }
+        true
+    }
+
+    fn start_stream(&mut self, block_size: BlockSize, position: u64) {
+        self.scanned_streams += 1;
+        if let Err(kind) = self.limits.check_streams(self.scanned_streams) {
+            self.fail_scan(kind, position);
+            return;
+        }
+        self.pending.push_back(Pending::StreamHeader);
+        self.scan_position += 32;
+        self.scan_state = ScanState::InStream(block_size);
+    }
+
+    fn end_scan(&mut self, trailing_garbage: bool, bit: u64) {
+        self.pending.push_back(Pending::End {
+            trailing_garbage,
+            bit,
+        });
+        self.scan_state = ScanState::Done;
+    }
+
+    /// Stop scanning, failing once everything scanned before is decoded.
+    fn fail_scan(&mut self, kind: DecodeErrorKind, bit: u64) {
+        let err = DecodeError::new(kind, self.scanned_blocks, bit);
+        self.pending.push_back(Pending::Error(err));
+        self.scan_state = ScanState::Done;
+    }
+
+    /// Hand the block at the scan position to the next worker, assuming it ends at the next magic.
+    /// Returns false if the block does not fit into the memory limit beside the blocks in flight.
+    fn dispatch_block(&mut self, block_size: BlockSize) -> Result<bool, DecodeErrorKind> {
+        self.scanned_blocks += 1;
+        self.limits.check_blocks(self.scanned_blocks)?;
+        let magic_bit = self.scan_position;
+        let planned_end = self
+            .window
+            .find_magic_before(magic_bit + MAGIC_BITS, block_limit(magic_bit))?;
+        // the input of the blocks in flight and of this one counts against the memory limit
+        let memory_share = match self.limits.blocks_in_memory(
+            block_size.max_block_length(),
+            2 * self.worker_threads.len(),
+            held_bytes(self.oldest_needed(), planned_end),
+        ) {
+            Ok((blocks, share)) if blocks > self.blocks_in_flight => share,
+            Err(kind) if self.blocks_in_flight == 0 => return Err(kind),
+            _ => {
+                self.scanned_blocks -= 1;
+                return Ok(false);
+            }
+        };
+        let segment = self.window.segment(magic_bit + MAGIC_BITS, planned_end);
+        // the output of the blocks before is not known yet, so this is an upper bound
+        let max_output = self
+            .limits
+            .remaining_output(planned_end.div_ceil(8), self.total_out)
+            .min(memory_share);
+        let worker = self.next_worker;
+        self.worker_threads[worker].send_work((
+            segment,
+            block_size.max_block_length(),
+            max_output,
+        ))?;
+        self.next_worker = (worker + 1) % self.worker_threads.len();
+        self.blocks_in_flight += 1;
+        self.pending.push_back(Pending::Block {
+            worker,
+            magic_bit,
+            planned_end,
+            block_size,
+            memory_share,
+            dispatched: Instant::now(),
+        });
+        self.scan_position = planned_end;
+        Ok(true)
+    }
+
+    /// Turn the result of a worker into the decoded block. If the block was cut short because
+    /// the block magic occurred inside of it, it is decoded again up to the next candidate.
+    fn validate(
+        &mut self,
+        mut result: BlockResult,
+        magic_bit: u64,
+        planned_end: u64,
+        block_size: BlockSize,
+        memory_share: usize,
+    ) -> Result<DecodedBlock, DecodeError> {
+        let mut end = planned_end;
+        loop {
+            match result {
+                Ok(block) => return Ok(block),
+                Err((DecodeErrorKind::TruncatedInput, _)) if end >= block_limit(magic_bit) => {
+                    let kind = DecodeErrorKind::BlockTooLarge;
+                    return Err(self.error(kind, block_limit(magic_bit)));
+                }
+                Err((DecodeErrorKind::TruncatedInput, bit)) => {
+                    self.window
+                        .fill_to(end + 1)
+                        .map_err(|kind| self.error(kind, bit))?;
+                    if end >= self.window.end_bit() {
+                        return Err(self.error(DecodeErrorKind::TruncatedInput, bit));
+                    }
+                    end = self
+                        .window
+                        .find_magic_before(end + 1, block_limit(magic_bit))
+                        .map_err(|kind| self.error(kind, bit))?;
+                    self.limits
+                        .blocks_in_memory(
+                            block_size.max_block_length(),
+                            1,
+                            held_bytes(magic_bit, end.max(self.scan_position)),
+                        )
+                        .map_err(|kind| self.error(kind, bit))?;
+                    let segment = self.window.segment(magic_bit + MAGIC_BITS, end);
+                    let max_output = self
+                        .limits
+                        .remaining_output(end.div_ceil(8), self.total_out)
+                        .min(memory_share);
+                    result = decode_segment(&segment, block_size.max_block_length(), max_output);
+                }
+                Err((kind, bit)) => return Err(self.error(kind, bit)),
+            }
+        }
+    }
+
+    /// Drop everything scanned ahead and continue scanning at `position`.
+    fn restart_scan(
+        &mut self,
+        position: u64,
+        block_size: BlockSize,
+    ) -> Result<(), DecodeErrorKind> {
+        for pending in self.pending.drain(..) {
+            if let Pending::Block { worker, .. } = pending {
+                // the result of a block scanned ahead is meaningless now
+                let _ = self.worker_threads[worker].receive_result()?;
+            }
+        }
+        self.blocks_in_flight = 0;
+        self.scanned_streams = self.num_streams;
+        self.scanned_blocks = self.block_number;
+        self.scan_position = position;
+        self.scan_state = ScanState::InStream(block_size);
+        Ok(())
+    }
+
+    /// Position of the oldest block which is not decoded yet, or the scan position if there is
+    /// none. The input before is not needed anymore.
+    fn oldest_needed(&self) -> u64 {
+        if self.blocks_in_flight == 0 {
+            return self.scan_position;
+        }
+        self.pending
+            .iter()
+            .find_map(|pending| match pending {
+                Pending::Block { magic_bit, .. } => Some(*magic_bit),
+                _ => None,
+            })
+            .unwrap_or(self.scan_position)
+            .min(self.scan_position)
+    }
+
+    /// Forget the input before the oldest block which is not decoded yet.
+    fn discard_decoded(&mut self) {
+        self.window.discard_before(self.oldest_needed());
+    }
+}
+
+/// Number of bytes of the input holding the bits from `from` to `to`.
+fn held_bytes(from: u64, to: u64) -> usize {
+    (to.div_ceil(8) - from / 8) as usize
+}
+
+/// End of the search for the magic following the block magic at `magic_bit`, as no block
+/// can be longer.
+fn block_limit(magic_bit: u64) -> u64 {
+    magic_bit + MAGIC_BITS + MAX_BLOCK_BITS
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::DecodeLimit;
+    use crate::stream::DecoderBuilder;
+    use crate::test_util::{encode, sample};
+
+    fn decode_sequential(encoded: &[u8]) -> (Vec<u8>, DecodeSummary) {
+        let mut decoded = vec![];
+        let summary = DecoderBuilder::new()
+            .decode_stream(encoded, &mut decoded)
+            .unwrap();
+        (decoded, summary)
+    }
+
+    fn decode_parallel(encoded: &[u8]) -> Result<(Vec<u8>, DecodeSummary), DecodeError> {
+        let mut decoded = vec![];
+        let summary = ParallelDecoder::new(
+            encoded,
+            3,
+            true,
+            None,
+            CorruptionHandler::default(),
+            DecodeLimits::default(),
+            AbortHandle::new(),
+        )
+        .run(&mut decoded)?;
+        Ok((decoded, summary))
+    }
+
+    #[test]
+    pub fn finds_magic_at_any_bit() {
+        for shift in 0..8 {
+            let mut bits = (0x0123u64 << 48) | BLOCK_MAGIC;
+            bits <<= shift;
+            let bytes = bits.to_be_bytes();
+            let mut window = InputWindow::new(&bytes[..]);
+            assert_eq!(window.find_magic(0).unwrap(), 16 - shift as u64);
+            assert_eq!(window.find_magic(17 - shift as u64).unwrap(), 64);
+        }
+    }
+
+    #[test]
+    pub fn matches_sequential_decoder() {
+        let input = sample(450_000);
+        let mut encoded = encode(&input, 1);
+        encoded.extend(encode(b"second stream", 9));
+        encoded.extend_from_slice(b"garbage");
+
+        let (expected, expected_summary) = decode_sequential(&encoded);
+        let (decoded, summary) = decode_parallel(&encoded).unwrap();
+        assert_eq!(decoded, expected);
+        assert_eq!(summary, expected_summary);
+        assert_eq!(summary.num_streams, 2);
+        assert!(summary.num_blocks > 5);
+    }
+
+    #[test]
+    pub fn decodes_empty_stream() {
+        let encoded = encode(b"", 9);
+        let (decoded, summary) = decode_parallel(&encoded).unwrap();
+        assert!(decoded.is_empty());
+        assert_eq!(summary.num_blocks, 0);
+    }
+
+    #[test]
+    pub fn reports_same_errors_as_sequential_decoder() {
+        let encoded = encode(&sample(250_000), 1);
+        let mut corrupted = encoded.clone();
+        corrupted[encoded.len() / 2] ^= 0x10;
+        let truncated = &encoded[..encoded.len() - 20];
+
+        for input in [&corrupted[..], truncated, b"BZh9"] {
+            let expected = DecoderBuilder::new()
+                .decode_stream(input, std::io::sink())
+                .unwrap_err();
+            let err = decode_parallel(input).unwrap_err();
+            assert_eq!(err.to_string(), expected.to_string());
+        }
+    }
+
+    #[test]
+    pub fn decodes_again_if_magic_occurs_inside_of_block() {
+        let input = sample(250_000);
+        let encoded = encode(&input, 1);
+        let mut decoder = ParallelDecoder::new(
+            &encoded[..],
+            1,
+            true,
+            None,
+            CorruptionHandler::default(),
+            DecodeLimits::default(),
+            AbortHandle::new(),
+        );
+        let magic_bit = decoder.window.find_magic(0).unwrap();
+        let end = decoder.window.find_magic(magic_bit + MAGIC_BITS).unwrap();
+        // pretend the block ends in the middle
+        let segment = decoder
+            .window
+            .segment(magic_bit + MAGIC_BITS, (magic_bit + end) / 2);
+        let block_size = BlockSize::new(1).unwrap();
+        let result = decode_segment(&segment, block_size.max_block_length(), usize::MAX);
+        assert!(matches!(result, Err((DecodeErrorKind::TruncatedInput, _))));
+
+        let block = decoder
+            .validate(
+                result,
+                magic_bit,
+                (magic_bit + end) / 2,
+                block_size,
+                usize::MAX,
+            )
+            .unwrap();
+        assert_eq!(block.end_bit, end);
+        assert_eq!(block.data, input[..block.data.len()]);
+    }
+
+    #[test]
+    pub fn stops_scanning_at_block_and_stream_limits() {
+        let stream = encode(&sample(250_000), 1);
+        let encoded = [&stream[..], &stream[..]].concat();
+        let at_most = |max_blocks, max_streams| DecodeLimits {
+            max_blocks,
+            max_streams,
+            ..Default::default()
+        };
+        for (limits, sequential, expected_blocks, expected_limit) in [
+            (
+                at_most(Some(4), None),
+                DecoderBuilder::new().max_blocks(4),
+                4,
+                DecodeLimit::Blocks(4),
+            ),
+            (
+                at_most(None, Some(1)),
+                DecoderBuilder::new().max_streams(1),
+                3,
+                DecodeLimit::Streams(1),
+            ),
+        ] {
+            let expected = sequential
+                .decode_stream(&encoded[..], std::io::sink())
+                .unwrap_err();
+
+            let mut decoder = ParallelDecoder::new(
+                &encoded[..],
+                3,
+                true,
+                None,
+                CorruptionHandler::default(),
+                limits,
+                AbortHandle::new(),
+            );
+            while !matches!(decoder.scan_state, ScanState::Done) {
+                decoder.scan_next();
+            }
+            let blocks = decoder
+                .pending
+                .iter()
+                .filter(|pending| matches!(pending, Pending::Block { .. }))
+                .count();
+            assert_eq!(blocks, expected_blocks);
+            match decoder.pending.back() {
+                Some(Pending::Error(err)) => {
+                    assert!(matches!(
+                        err.kind(),
+                        DecodeErrorKind::LimitExceeded(limit) if *limit == expected_limit
+                    ));
+                    assert_eq!(err.block_number(), expected.block_number());
+                }
+                _ => panic!("scanning did not stop at the limit"),
+            }
+        }
+    }
+
+    #[test]
+    pub fn counts_input_of_blocks_in_flight_against_memory_limit() {
+        let mut state = 1u32;
+        let input: Vec<u8> = (0..1_100_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 24) as u8
+            })
+            .collect();
+        let encoded = encode(&input, 1);
+        // the read ahead must not count against the limit
+        assert!(encoded.len() > READ_SIZE);
+        let decoder = |max_memory| {
+            let limits = DecodeLimits {
+                max_memory: Some(max_memory),
+                ..Default::default()
+            };
+            ParallelDecoder::new(
+                &encoded[..],
+                3,
+                true,
+                None,
+                CorruptionHandler::default(),
+                limits,
+                AbortHandle::new(),
+            )
+        };
+        // a block takes 2.1 MB besides its incompressible input of about 0.1 MB
+        let err = decoder(2_150_000).run(std::io::sink()).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            DecodeErrorKind::LimitExceeded(DecodeLimit::Memory(2_150_000))
+        ));
+        // the next block waits if it does not fit beside those in flight
+        for (max_memory, blocks_in_flight) in [(2_300_000, 1), (4_350_000, 1), (4_500_000, 2)] {
+            let mut decoder = decoder(max_memory);
+            decoder.scan_ahead();
+            assert_eq!(decoder.blocks_in_flight, blocks_in_flight);
+        }
+        let mut decoded = vec![];
+        decoder(2_300_000).run(&mut decoded).unwrap();
+        assert!(decoded == input);
+    }
+
+    #[test]
+    pub fn bounds_input_held_for_empty_streams() {
+        let encoded = encode(b"", 9).repeat(160_000);
+        let mut decoder = ParallelDecoder::new(
+            &encoded[..],
+            3,
+            true,
+            None,
+            CorruptionHandler::default(),
+            DecodeLimits {
+                max_memory: Some(1_000_000),
+                ..Default::default()
+            },
+            AbortHandle::new(),
+        );
+        let t = Instant::now();
+        while !matches!(decoder.scan_state, ScanState::Done) {
+            eprintln!("loop {:?}", t.elapsed());
+            decoder.scan_ahead();
+            assert!(decoder.pending.len() <= MAX_PENDING);
+            assert!(decoder.window.bytes.len() <= 2 * READ_SIZE);
+            decoder.pending.clear();
+            decoder.discard_decoded();
+        }
+
+        eprintln!("scanned {:?}", t.elapsed());
+        let summary = decode_parallel(&encoded).unwrap().1;
+        eprintln!("run {:?}", t.elapsed());
+        let _ = decode_sequential(&encoded);
+        eprintln!("seq {:?}", t.elapsed());
+        assert_eq!(summary.num_streams, 160_000);
+        assert_eq!(summary.input_bytes, encoded.len() as u64);
+    }
+
+    #[test]
+    pub fn stops_searching_for_magic_beyond_largest_block() {
+        let mut header = b"BZh9".to_vec();
+        header.extend_from_slice(&BLOCK_MAGIC.to_be_bytes()[2..]);
+        let input_length = 1 << 28;
+        let expected = DecoderBuilder::new()
+            .decode_stream(
+                header.chain(std::io::repeat(0).take(input_length)),
+                std::io::sink(),
+            )
+            .unwrap_err();
+
+        let mut input = header.chain(std::io::repeat(0).take(input_length));
+        let err = ParallelDecoder::new(
+            &mut input,
+            3,
+            true,
+            None,
+            CorruptionHandler::default(),
+            DecodeLimits::default(),
+            AbortHandle::new(),
+        )
+        .run(std::io::sink())
+        .unwrap_err();
+        assert_eq!(err.to_string(), expected.to_string());
+        let read = input_length - input.get_ref().1.limit();
+        assert!(read < MAX_BLOCK_BITS / 8 + 4 * READ_SIZE as u64);
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Progress of an encoder or decoder, reported after every completed block.
+///
+/// Blocks are reported in the order of the stream, also when they are processed by several
+/// threads. Decoders count blocks across all concatenated streams.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BlockProgress {
+    /// Index of the completed block, starting at 0.
+    pub block_index: usize,
+    /// Number of bytes consumed up to the end of the block, uncompressed when encoding and
+    /// compressed when decoding.
+    pub input_bytes: u64,
+    /// Number of bytes produced up to the end of the block.
+    pub output_bytes: u64,
+    /// Time since the encoder or decoder was created.
+    pub elapsed: Duration,
+    /// Time the block took from being handed to a thread until it was written.
+    pub block_time: Duration,
+}
+
+/// Callback registered using [super::EncoderBuilder::on_progress] or
+/// [super::DecoderBuilder::on_progress].
+#[derive(Clone)]
+pub(crate) struct ProgressObserver(Arc<dyn Fn(&BlockProgress) + Send + Sync>);
+
+impl ProgressObserver {
+    pub(crate) fn new(observer: impl Fn(&BlockProgress) + Send + Sync + 'static) -> Self {
+        ProgressObserver(Arc::new(observer))
+    }
+}
+
+impl fmt::Debug for ProgressObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressObserver")
+    }
+}
+
+/// Reports the blocks of a single encoder or decoder to its observer, if any.
+pub(crate) struct ProgressReporter {
+    observer: Option<ProgressObserver>,
+    started: Instant,
+}
+
+impl ProgressReporter {
+    pub(crate) fn new(observer: Option<ProgressObserver>) -> Self {
+        ProgressReporter {
+            observer,
+            started: Instant::now(),
+        }
+    }
+
+    /// Report that the block `block_index`, whose processing started at `block_started`, is complete.
+    pub(crate) fn report(
+        &self,
+        block_index: usize,
+        input_bytes: u64,
+        output_bytes: u64,
+        block_started: Instant,
+    ) {
+        if let Some(observer) = &self.observer {
+            (observer.0)(&BlockProgress {
+                block_index,
+                input_bytes,
+                output_bytes,
+                elapsed: self.started.elapsed(),
+                block_time: block_started.elapsed(),
+            });
+        }
+    }
+}
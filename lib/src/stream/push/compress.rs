@@ -0,0 +1,177 @@
+use std::io::Write;
+
+use super::{Action, Progress, Status};
+use crate::stream::{Bz2Encoder, EncoderBuilder};
+
+/// Compresses a stream passed in slices into slices, see [Compress::compress].
+///
+/// Blocks are encoded by worker threads like in [Bz2Encoder], encoded data is kept until the
+/// caller provides space for it.
+pub struct Compress {
+    encoder: Bz2Encoder<Vec<u8>>,
+    /// Position of the first byte in the output of the encoder not passed to the caller yet.
+    output_position: usize,
+    total_out: u64,
+}
+
+impl Compress {
+    /// Create a compressor with the default settings of [EncoderBuilder].
+    pub fn new() -> Self {
+        EncoderBuilder::new().build_push()
+    }
+
+    /// Consume `input` and write compressed data into `output` as far as possible. Once the
+    /// input is consumed, `action` determines whether the current block is ended.
+    ///
+    /// A call returns [Status::OutputFull] while compressed data is waiting for more space,
+    /// [Status::NeedsInput] once the input is consumed (and, for [Action::Flush], the block
+    /// was passed on completely) and [Status::StreamEnd] once the stream was finished.
+    pub fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        action: Action,
+    ) -> std::io::Result<Progress> {
+        let mut consumed = 0;
+        let mut produced = 0;
+        let status = loop {
+            let pending = &mut self.encoder.get_mut()[self.output_position..];
+            let size = pending.len().min(output.len() - produced);
+            output[produced..produced + size].copy_from_slice(&pending[..size]);
+            produced += size;
+            self.output_position += size;
+            if size < pending.len() {
+                break Status::OutputFull;
+            }
+            self.encoder.get_mut().clear();
+            self.output_position = 0;
+            if consumed < input.len() {
+                consumed += self.encoder.write(&input[consumed..])?;
+                continue;
+            }
+            match action {
+                Action::Run => break Status::NeedsInput,
+                Action::Flush => {
+                    self.encoder.flush_block()?;
+                    if self.encoder.get_ref().is_empty() {
+                        break Status::NeedsInput;
+                    }
+                }
+                Action::Finish => {
+                    self.encoder.try_finish()?;
+                    if self.encoder.get_ref().is_empty() {
+                        break Status::StreamEnd;
+                    }
+                }
+            }
+        };
+        self.total_out += produced as u64;
+        Ok(Progress {
+            status,
+            consumed,
+            produced,
+        })
+    }
+
+    /// Number of uncompressed bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.encoder.total_in()
+    }
+
+    /// Number of compressed bytes produced so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+}
+
+impl Default for Compress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EncoderBuilder {
+    /// Create a compressor working on slices, see [Compress].
+    pub fn build_push(&self) -> Compress {
+        Compress {
+            encoder: self.build(vec![]),
+            output_position: 0,
+            total_out: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use super::*;
+    use crate::stream::{decode_stream, BlockSize, Bz2Decoder};
+    use crate::test_util::sample;
+
+    #[test]
+    pub fn matches_encoder_with_tiny_slices() {
+        let input = sample(150_000);
+        let builder = EncoderBuilder::new().block_size(BlockSize::new(1).unwrap());
+        let mut expected = vec![];
+        builder.encode_stream(&input[..], &mut expected).unwrap();
+
+        let mut compress = builder.build_push();
+        let mut encoded = vec![];
+        let mut output = [0u8; 1];
+        for byte in input.chunks(1) {
+            let mut progress = compress.compress(byte, &mut output, Action::Run).unwrap();
+            encoded.extend_from_slice(&output[..progress.produced]);
+            while progress.consumed == 0 {
+                progress = compress.compress(byte, &mut output, Action::Run).unwrap();
+                encoded.extend_from_slice(&output[..progress.produced]);
+            }
+        }
+        loop {
+            let progress = compress.compress(&[], &mut output, Action::Finish).unwrap();
+            encoded.extend_from_slice(&output[..progress.produced]);
+            if progress.status == Status::StreamEnd {
+                break;
+            }
+            assert_eq!(progress.status, Status::OutputFull);
+        }
+        assert_eq!(encoded, expected);
+        assert_eq!(compress.total_in(), input.len() as u64);
+        assert_eq!(compress.total_out(), encoded.len() as u64);
+    }
+
+    #[test]
+    pub fn flushes_blocks() {
+        let input = sample(150_000);
+        let mut compress = Compress::new();
+        let mut encoded = vec![0u8; 200_000];
+        let mut length = 0;
+        let mut flushed = 0;
+        for piece in input.chunks(50_000) {
+            let progress = compress
+                .compress(piece, &mut encoded[length..], Action::Flush)
+                .unwrap();
+            assert_eq!(progress.status, Status::NeedsInput);
+            assert_eq!(progress.consumed, piece.len());
+            length += progress.produced;
+            // the blocks flushed before can be decoded, the last bits of this one may be held back
+            let mut decoded = vec![];
+            assert!(Bz2Decoder::new(&encoded[..length])
+                .read_to_end(&mut decoded)
+                .is_err());
+            assert!(decoded.len() >= flushed);
+            assert_eq!(decoded, input[..decoded.len()]);
+            flushed += piece.len();
+        }
+        let progress = compress
+            .compress(&[], &mut encoded[length..], Action::Finish)
+            .unwrap();
+        assert_eq!(progress.status, Status::StreamEnd);
+        length += progress.produced;
+        let mut decoded = vec![];
+        let summary = decode_stream(&encoded[..length], &mut decoded).unwrap();
+        assert_eq!(decoded, input);
+        assert_eq!(summary.num_blocks, 3);
+        assert!(compress.compress(b"x", &mut encoded, Action::Run).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use super::{Progress, Status};
+use crate::bitwise::bitreader::{BitReader, SliceBitReader};
+use crate::bitwise::bitwriter::convert_to_number;
+use crate::block::block_decoder::BlockParser;
+use crate::error::{DecodeError, DecodeErrorKind};
+use crate::stream::decoder::{read_file_header, what_next, BlockType};
+use crate::stream::BlockSize;
+
+/// Part of the stream read by the next [Decoding::step].
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    StreamHeader,
+    BlockMagic,
+    Block,
+    StreamFooter,
+    Done,
+    Failed,
+}
+
+/// Where decoding stands, apart from the input and output kept between calls.
+struct Decoding {
+    stage: Stage,
+    block_parser: BlockParser,
+    block_size: Option<BlockSize>,
+    stream_crc: u32,
+    block_number: usize,
+}
+
+impl Decoding {
+    /// Read the next part of the stream, decoded blocks are appended to `output`. A part is
+    /// either read completely or the decoding is left unchanged.
+    fn step(
+        &mut self,
+        reader: &mut SliceBitReader,
+        output: &mut Vec<u8>,
+    ) -> Result<(), DecodeErrorKind> {
+        match self.stage {
+            Stage::StreamHeader => {
+                self.block_size = Some(read_file_header(reader)?);
+                self.stage = Stage::BlockMagic;
+            }
+            Stage::BlockMagic => match what_next(reader)? {
+                BlockType::BlockHeader => {
+                    self.block_number += 1;
+                    self.stage = Stage::Block;
+                }
+                BlockType::StreamFooter => self.stage = Stage::StreamFooter,
+            },
+            Stage::Block => {
+                if let Some(parsed_block) = self.block_parser.step(reader)? {
+                    let max_block_length = self.block_size.unwrap().max_block_length();
+                    let block_crc = parsed_block.reconstruct(&mut *output, max_block_length)?;
+                    self.stream_crc = block_crc ^ self.stream_crc.rotate_left(1);
+                    self.stage = Stage::BlockMagic;
+                }
+            }
+            Stage::StreamFooter => {
+                let expected = convert_to_number(&reader.read_bits(32)?) as u32;
+                if expected != self.stream_crc {
+                    return Err(DecodeErrorKind::StreamCrcMismatch {
+                        expected,
+                        actual: self.stream_crc,
+                    });
+                }
+                self.stage = Stage::Done;
+            }
+            Stage::Done | Stage::Failed => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+/// Decompresses a single stream passed in slices into slices, see [Decompress::decompress].
+///
+/// Decoding can stop at any bit of the input and resumes with the next call. Like libbzip2,
+/// a single stream is decoded, input following it is left unconsumed.
+pub struct Decompress {
+    decoding: Decoding,
+    /// Input of previous calls not read completely yet.
+    kept: Vec<u8>,
+    /// Number of bits of `kept` already read.
+    kept_position: u64,
+    /// Number of bits read before the start of `kept`.
+    kept_offset: u64,
+    /// Decoded data, passed to the caller from `output_position` on.
+    output: Vec<u8>,
+    output_position: usize,
+    total_in: u64,
+    total_out: u64,
+}
+
+impl Decompress {
+    pub fn new() -> Self {
+        Decompress {
+            decoding: Decoding {
+                stage: Stage::StreamHeader,
+                block_parser: BlockParser::default(),
+                block_size: None,
+                stream_crc: 0,
+                block_number: 0,
+            },
+            kept: vec![],
+            kept_position: 0,
+            kept_offset: 0,
+            output: vec![],
+            output_position: 0,
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+
+    /// Consume `input` and write decompressed data into `output` as far as possible.
+    ///
+    /// Input which is needed but incomplete is consumed and kept until the next call, whereas
+    /// input following the end of the stream is not consumed. Decoded data is kept until the
+    /// caller provides space for it. Once an error is returned, the decompressor is unusable.
+    pub fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<Progress, DecodeError> {
+        let mut reader = SliceBitReader::new(&self.kept, input, self.kept_position);
+        let mut produced = 0;
+        let result = loop {
+            let pending = &self.output[self.output_position..];
+            let size = pending.len().min(output.len() - produced);
+            output[produced..produced + size].copy_from_slice(&pending[..size]);
+            produced += size;
+            self.output_position += size;
+            if size < pending.len() {
+                break Ok(Status::OutputFull);
+            }
+            self.output.clear();
+            self.output_position = 0;
+            match self.decoding.stage {
+                Stage::Done => break Ok(Status::StreamEnd),
+                Stage::Failed => {
+                    break Err(DecodeErrorKind::Io(std::io::Error::other(
+                        "decompressor is unusable after a previous error",
+                    )))
+                }
+                _ => {}
+            }
+            let position = reader.position();
+            match self.decoding.step(&mut reader, &mut self.output) {
+                Ok(()) => {}
+                Err(DecodeErrorKind::TruncatedInput) => {
+                    reader.set_position(position);
+                    break Ok(Status::NeedsInput);
+                }
+                Err(kind) => {
+                    self.decoding.stage = Stage::Failed;
+                    break Err(kind);
+                }
+            }
+        };
+        self.total_out += produced as u64;
+
+        // keep the bytes which are not read completely, up to the end of the input if more
+        // input is needed, otherwise up to the last byte touched
+        let position = reader.position();
+        let end = match result {
+            Ok(Status::NeedsInput) => self.kept.len() + input.len(),
+            _ => position.div_ceil(8) as usize,
+        };
+        let consumed = end.saturating_sub(self.kept.len());
+        let start = (position / 8) as usize;
+        let mut kept = Vec::with_capacity(end - start);
+        if start < self.kept.len() {
+            kept.extend_from_slice(&self.kept[start..end.min(self.kept.len())]);
+        }
+        kept.extend_from_slice(&input[start.saturating_sub(self.kept.len())..consumed]);
+        self.kept = kept;
+        self.kept_position = position % 8;
+        self.kept_offset += start as u64 * 8;
+        self.total_in += consumed as u64;
+
+        match result {
+            Ok(status) => Ok(Progress {
+                status,
+                consumed,
+                produced,
+            }),
+            Err(kind) => Err(DecodeError::new(
+                kind,
+                self.decoding.block_number,
+                self.kept_offset + self.kept_position,
+            )),
+        }
+    }
+
+    /// The block size declared in the stream header, once the header has been read.
+    pub fn block_size(&self) -> Option<BlockSize> {
+        self.decoding.block_size
+    }
+
+    /// Number of compressed bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Number of uncompressed bytes produced so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+}
+
+impl Default for Decompress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{encode, sample};
+
+    fn encoded_sample() -> (Vec<u8>, Vec<u8>) {
+        let input = sample(150_000);
+        let encoded = encode(&input, 1);
+        (input, encoded)
+    }
+
+    #[test]
+    pub fn decodes_with_tiny_slices() {
+        let (input, mut encoded) = encoded_sample();
+        let stream_length = encoded.len();
+        encoded.extend_from_slice(b"trailing data");
+
+        let mut decompress = Decompress::new();
+        let mut decoded = vec![];
+        let mut output = [0u8; 1];
+        let mut consumed = 0;
+        loop {
+            let end = (consumed + 1).min(encoded.len());
+            let progress = decompress
+                .decompress(&encoded[consumed..end], &mut output)
+                .unwrap();
+            consumed += progress.consumed;
+            decoded.extend_from_slice(&output[..progress.produced]);
+            if progress.status == Status::StreamEnd {
+                break;
+            }
+        }
+        assert_eq!(decoded, input);
+        assert_eq!(consumed, stream_length);
+        assert_eq!(decompress.total_in(), stream_length as u64);
+        assert_eq!(decompress.total_out(), input.len() as u64);
+        assert_eq!(decompress.block_size(), BlockSize::new(1));
+
+        let progress = decompress
+            .decompress(&encoded[consumed..], &mut output)
+            .unwrap();
+        assert_eq!(progress.status, Status::StreamEnd);
+        assert_eq!(progress.consumed, 0);
+    }
+
+    #[test]
+    pub fn decodes_in_one_call() {
+        let (input, encoded) = encoded_sample();
+        let mut decompress = Decompress::new();
+        let mut output = vec![0u8; input.len() + 1];
+        let progress = decompress.decompress(&encoded, &mut output).unwrap();
+        assert_eq!(progress.status, Status::StreamEnd);
+        assert_eq!(progress.consumed, encoded.len());
+        assert_eq!(output[..progress.produced], input);
+    }
+
+    #[test]
+    pub fn asks_for_more_input() {
+        let (_, encoded) = encoded_sample();
+        let mut decompress = Decompress::new();
+        let mut output = vec![0u8; 200_000];
+        let progress = decompress
+            .decompress(&encoded[..encoded.len() - 1], &mut output)
+            .unwrap();
+        assert_eq!(progress.status, Status::NeedsInput);
+        assert_eq!(progress.consumed, encoded.len() - 1);
+    }
+
+    #[test]
+    pub fn reports_errors_with_position() {
+        let (_, mut encoded) = encoded_sample();
+        let mut decompress = Decompress::new();
+        let mut output = vec![0u8; 1000];
+        let err = decompress.decompress(b"BZh0", &mut output).err().unwrap();
+        assert!(matches!(err.kind(), DecodeErrorKind::BadMagic));
+        assert!(decompress.decompress(b"BZh9", &mut output).is_err());
+
+        // corrupt the checksum of the first block
+        encoded[10] ^= 1;
+        let mut decompress = Decompress::new();
+        let mut output = vec![0u8; 200_000];
+        let err = decompress.decompress(&encoded, &mut output).err().unwrap();
+        assert!(matches!(
+            err.kind(),
+            DecodeErrorKind::BlockCrcMismatch { .. }
+        ));
+        assert_eq!(err.block_number(), 1);
+    }
+}
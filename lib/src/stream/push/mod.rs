@@ -0,0 +1,43 @@
+//! Low-level compression and decompression working on slices.
+//!
+//! [Compress] and [Decompress] take a slice of input and a slice of output per call, consume
+//! and produce as much as possible and return how far they got. They never block on I/O,
+//! thus they fit non-blocking I/O and foreign interfaces where the caller owns the buffers.
+
+mod compress;
+mod decompress;
+
+pub use compress::Compress;
+pub use decompress::Decompress;
+
+/// Why a call of [Compress::compress] or [Decompress::decompress] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// All input was consumed, the call should be repeated with more input.
+    NeedsInput,
+    /// The output is full, the call should be repeated with more space for output.
+    OutputFull,
+    /// The end of the stream was written or read completely.
+    StreamEnd,
+}
+
+/// What a call of [Compress::compress] or [Decompress::decompress] achieved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub status: Status,
+    /// Number of bytes consumed from the start of the input.
+    pub consumed: usize,
+    /// Number of bytes written to the start of the output.
+    pub produced: usize,
+}
+
+/// What [Compress::compress] should do once the input is consumed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Keep collecting input, blocks are only written when they are full.
+    Run,
+    /// End the current block, so that the output contains all input passed so far.
+    Flush,
+    /// End the current block and write the stream footer.
+    Finish,
+}
@@ -0,0 +1,264 @@
+use std::io::{Read, Write};
+use std::ops::Range;
+
+use crate::bitwise::bitreader::BitReader;
+use crate::bitwise::bitwriter::{convert_to_number, BitWriter, BitWriterImpl};
+use crate::error::{DecodeError, DecodeErrorKind};
+
+use super::decoder::read_file_header;
+use super::encoder::{file_header, stream_footer};
+use super::parallel_decoder::{
+    decode_segment, InputWindow, Segment, BLOCK_MAGIC, FOOTER_MAGIC, MAGIC_BITS, MAX_BLOCK_BITS,
+};
+use super::BlockSize;
+
+/// An intact block found by [recover].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RecoveredBlock {
+    /// Offset of the block magic in bits from the start of the input.
+    pub bit_offset: u64,
+    /// Length of the block in bits, including the block magic.
+    pub bit_length: u64,
+    /// Checksum of the block, which matches its decoded data.
+    pub crc: u32,
+    decoded: Vec<u8>,
+    bits: Vec<u8>,
+}
+
+impl RecoveredBlock {
+    /// The decoded data of the block.
+    pub fn decoded(&self) -> &[u8] {
+        &self.decoded
+    }
+
+    /// Write the block as a complete bzip2 stream of its own, with the largest block size.
+    pub fn write_stream(&self, writer: impl Write) -> std::io::Result<()> {
+        let segment = Segment {
+            bytes: self.bits.clone(),
+            start_bit: self.bit_offset,
+        };
+        let block = segment
+            .reader()
+            .read_bits(self.bit_length as usize)
+            .map_err(|kind| std::io::Error::other(kind.to_string()))?;
+        let mut bit_writer = BitWriterImpl::from_writer(writer);
+        bit_writer.write_bits(&file_header(BlockSize::default()))?;
+        bit_writer.write_bits(&block)?;
+        // the checksum of a stream of a single block equals the checksum of the block
+        bit_writer.write_bits(&stream_footer(self.crc))?;
+        bit_writer.finalize()?;
+        bit_writer.get_mut().flush()
+    }
+}
+
+/// Summary of a damaged input scanned by [recover].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RecoverySummary {
+    /// Number of compressed bytes read.
+    pub input_bytes: u64,
+    /// Number of intact blocks found.
+    pub num_blocks: usize,
+    /// Ranges of the input in bits which belong neither to an intact block nor to a stream
+    /// header or footer, i.e. the data which could not be recovered.
+    pub lost: Vec<Range<u64>>,
+}
+
+/// Find all intact blocks of a damaged input, like `bzip2recover`.
+///
+/// The input is scanned for block and stream footer magics at every bit. The bits from a
+/// block magic to the next magic are decoded and kept if the checksum matches, and
+/// `on_block` is called with every intact block in the order of the input, e.g. to decode
+/// it or to store it using [RecoveredBlock::write_stream]. Only errors reading the input or
+/// returned by `on_block` stop the scan.
+pub fn recover(
+    reader: impl Read,
+    mut on_block: impl FnMut(&RecoveredBlock) -> std::io::Result<()>,
+) -> Result<RecoverySummary, DecodeError> {
+    let mut scan = Scan {
+        window: InputWindow::new(reader),
+        covered: 0,
+        block_number: 0,
+        summary: RecoverySummary::default(),
+    };
+    scan.skip_stream_header(0)
+        .map_err(|kind| scan.error(kind, 0))?;
+    let mut magic = scan
+        .window
+        .find_magic(0)
+        .map_err(|kind| scan.error(kind, 0))?;
+    while magic < scan.window.end_bit() {
+        scan.window.discard_before(magic);
+        let kind = convert_to_number(
+            &scan
+                .window
+                .segment(magic, magic + MAGIC_BITS)
+                .reader()
+                .read_bits(MAGIC_BITS as usize)
+                .map_err(|kind| scan.error(kind, magic))?,
+        ) as u64;
+        magic = match kind {
+            FOOTER_MAGIC => scan
+                .skip_stream_footer(magic)
+                .map_err(|kind| scan.error(kind, magic))?,
+            BLOCK_MAGIC => {
+                scan.block_number += 1;
+                match scan
+                    .find_block(magic)
+                    .map_err(|kind| scan.error(kind, magic))?
+                {
+                    (Some(block), _) => {
+                        on_block(&block)
+                            .map_err(|err| scan.error(DecodeErrorKind::Io(err), magic))?;
+                        scan.cover(magic..block.bit_offset + block.bit_length);
+                        scan.summary.num_blocks += 1;
+                        scan.window
+                            .find_magic(block.bit_offset + block.bit_length)
+                            .map_err(|kind| scan.error(kind, magic))?
+                    }
+                    (None, next) => next,
+                }
+            }
+            _ => unreachable!(),
+        };
+    }
+    let end = scan.window.end_bit();
+    scan.cover(end..end);
+    scan.summary.input_bytes = end / 8;
+    Ok(scan.summary)
+}
+
+/// State of [recover].
+struct Scan<R: Read> {
+    window: InputWindow<R>,
+    /// End of the last part of the input which was recovered or belongs to a stream.
+    covered: u64,
+    block_number: usize,
+    summary: RecoverySummary,
+}
+
+impl<R: Read> Scan<R> {
+    /// Account for the part `range` of the input, anything before it which is not covered yet is lost.
+    fn cover(&mut self, range: Range<u64>) {
+        if range.start > self.covered {
+            self.summary.lost.push(self.covered..range.start);
+        }
+        self.covered = self.covered.max(range.end);
+    }
+
+    /// Cover the stream header at `bit`, if there is one.
+    fn skip_stream_header(&mut self, bit: u64) -> Result<(), DecodeErrorKind> {
+        self.window.fill_to(bit + 32)?;
+        let segment = self.window.segment(bit, bit + 32);
+        if read_file_header(segment.reader()).is_ok() {
+            self.cover(bit..bit + 32);
+        }
+        Ok(())
+    }
+
+    /// Cover the stream footer at `magic` and a following stream header, returns the
+    /// position of the next magic.
+    fn skip_stream_footer(&mut self, magic: u64) -> Result<u64, DecodeErrorKind> {
+        // streams are padded to whole bytes
+        let end = ((magic + MAGIC_BITS + 32).div_ceil(8) * 8).min(self.window.end_bit());
+        self.cover(magic..end);
+        self.skip_stream_header(end)?;
+        self.window.find_magic(magic + MAGIC_BITS)
+    }
+
+    /// Decode the block starting at `magic`. A block magic may also occur by chance inside of
+    /// a block, hence blocks ending beyond the next magic are decoded again up to the magic
+    /// following it. Returns the block, if it is intact, and the position of the next magic.
+    fn find_block(&mut self, magic: u64) -> Result<(Option<RecoveredBlock>, u64), DecodeErrorKind> {
+        let next = self.window.find_magic(magic + MAGIC_BITS)?;
+        let mut end = next;
+        loop {
+            let segment = self.window.segment(magic + MAGIC_BITS, end);
+            match decode_segment(
+                &segment,
+                BlockSize::default().max_block_length(),
+                usize::MAX,
+            ) {
+                Ok(block) if block.actual_crc != block.crc => return Ok((None, next)),
+                Ok(block) => {
+                    let bits = self.window.segment(magic, block.end_bit);
+                    let block = RecoveredBlock {
+                        bit_offset: magic,
+                        bit_length: block.end_bit - magic,
+                        crc: block.crc,
+                        decoded: block.data,
+                        bits: bits.bytes,
+                    };
+                    return Ok((Some(block), next));
+                }
+                Err((DecodeErrorKind::TruncatedInput, _))
+                    if end < self.window.end_bit() && end - magic < MAX_BLOCK_BITS =>
+                {
+                    end = self.window.find_magic(end + MAGIC_BITS)?;
+                }
+                Err(_) => return Ok((None, next)),
+            }
+        }
+    }
+
+    fn error(&self, kind: DecodeErrorKind, bit: u64) -> DecodeError {
+        DecodeError::new(kind, self.block_number, bit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::{decode_stream, BlockIndex};
+    use crate::test_util::{encode, sample};
+
+    #[test]
+    pub fn recovers_all_blocks_of_intact_input() {
+        let input = sample(250_000);
+        let mut encoded = encode(&input, 1);
+        encoded.extend_from_slice(&encode(b"second stream", 1));
+
+        let mut decoded = vec![];
+        let summary = recover(&encoded[..], |block| {
+            let mut stream = vec![];
+            block.write_stream(&mut stream)?;
+            let mut stream_decoded = vec![];
+            decode_stream(&stream[..], &mut stream_decoded)?;
+            assert_eq!(stream_decoded, block.decoded());
+            decoded.extend_from_slice(block.decoded());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(decoded, [&input[..], b"second stream"].concat());
+        assert_eq!(summary.num_blocks, 4);
+        assert_eq!(summary.input_bytes, encoded.len() as u64);
+        assert!(summary.lost.is_empty());
+    }
+
+    #[test]
+    pub fn reports_lost_blocks() {
+        let input = sample(250_000);
+        let mut encoded = encode(&input, 1);
+        let index = BlockIndex::build(&encoded[..]).unwrap();
+        let damaged = &index.blocks()[1];
+        encoded[((damaged.bit_offset + damaged.bit_length / 2) / 8) as usize] ^= 0x10;
+
+        let mut blocks = vec![];
+        let summary = recover(&encoded[..], |block| {
+            blocks.push(block.clone());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(summary.num_blocks, 2);
+        assert_eq!(blocks[0].bit_offset, index.blocks()[0].bit_offset);
+        assert_eq!(blocks[1].bit_offset, index.blocks()[2].bit_offset);
+        assert_eq!(
+            summary.lost,
+            vec![damaged.bit_offset..damaged.bit_offset + damaged.bit_length]
+        );
+        let first_length = blocks[0].decoded().len();
+        assert_eq!(blocks[0].decoded(), &input[..first_length]);
+        assert!(input.ends_with(blocks[1].decoded()));
+    }
+}
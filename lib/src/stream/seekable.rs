@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+use std::io::{Read, Seek, SeekFrom};
+
+use super::BlockIndex;
+
+const DEFAULT_CACHE_SIZE: usize = 4;
+
+/// Random access to the uncompressed data of a bzip2 file.
+///
+/// A [BlockIndex] with the uncompressed location of every block is used to find the block
+/// containing the current position, so only that block is decoded. The most recently used
+/// blocks are kept in memory (4 unless configured using [SeekableBz2Reader::with_cache_size]).
+pub struct SeekableBz2Reader<R: Read + Seek> {
+    reader: R,
+    index: BlockIndex,
+    uncompressed_length: u64,
+    position: u64,
+    cache: VecDeque<(usize, Vec<u8>)>,
+    cache_size: usize,
+}
+
+impl<R: Read + Seek> SeekableBz2Reader<R> {
+    /// Create a reader for the bzip2 file `reader`. The whole file is decoded once to build the index,
+    /// use [SeekableBz2Reader::with_index] to avoid this if an index is available.
+    pub fn new(mut reader: R) -> std::io::Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let index = BlockIndex::build_decoded(&mut reader)?;
+        SeekableBz2Reader::with_index(reader, index)
+    }
+
+    /// Create a reader for the bzip2 file `reader` using an index built before, which must
+    /// contain the uncompressed location of every block (see [BlockIndex::build_decoded]).
+    pub fn with_index(reader: R, index: BlockIndex) -> std::io::Result<Self> {
+        let mut uncompressed_length = 0;
+        for block in index.blocks() {
+            match (block.uncompressed_offset, block.uncompressed_length) {
+                (Some(offset), Some(length)) if offset == uncompressed_length => {
+                    uncompressed_length += length;
+                }
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "block index lacks the uncompressed location of the blocks",
+                    ))
+                }
+            }
+        }
+        Ok(SeekableBz2Reader {
+            reader,
+            index,
+            uncompressed_length,
+            position: 0,
+            cache: VecDeque::new(),
+            cache_size: DEFAULT_CACHE_SIZE,
+        })
+    }
+
+    /// Create a reader for the bzip2 file `reader` using an index stored by [BlockIndex::write_sidecar]
+    /// at compression time, hence nothing needs to be decoded in advance.
+    /// Fails if the sidecar was stored for a file of another length.
+    pub fn with_sidecar(mut reader: R, sidecar: impl Read) -> std::io::Result<Self> {
+        let index = BlockIndex::read_sidecar(sidecar)?;
+        index.check_compressed_length(&mut reader)?;
+        SeekableBz2Reader::with_index(reader, index)
+    }
+
+    /// Number of decoded blocks kept in memory (at least 1).
+    pub fn with_cache_size(mut self, num_blocks: usize) -> Self {
+        self.cache_size = num_blocks.max(1);
+        self.cache.truncate(self.cache_size);
+        self
+    }
+
+    /// The index used to locate the blocks, e.g. to store it for later use.
+    pub fn index(&self) -> &BlockIndex {
+        &self.index
+    }
+
+    /// Length of the uncompressed data.
+    pub fn uncompressed_length(&self) -> u64 {
+        self.uncompressed_length
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// The decoded data of the block with the given number, from the cache if possible.
+    fn block(&mut self, block_index: usize) -> std::io::Result<&[u8]> {
+        match self
+            .cache
+            .iter()
+            .position(|(index, _)| *index == block_index)
+        {
+            Some(position) => {
+                let entry = self.cache.remove(position).unwrap();
+                self.cache.push_back(entry);
+            }
+            None => {
+                let data = self.decode(block_index)?;
+                if self.cache.len() == self.cache_size {
+                    self.cache.pop_front();
+                }
+                self.cache.push_back((block_index, data));
+            }
+        }
+        Ok(&self.cache.back().unwrap().1)
+    }
+
+    /// Decode the block with the given number, failing if it does not match the index, e.g.
+    /// because the index belongs to another version of the file.
+    fn decode(&mut self, block_index: usize) -> std::io::Result<Vec<u8>> {
+        let block = &self.index.blocks()[block_index];
+        let mut data = Vec::with_capacity(block.block_size.max_block_length());
+        self.index
+            .decode_block(block_index, &mut self.reader, &mut data)?;
+        Ok(data)
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableBz2Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() || self.position >= self.uncompressed_length {
+            return Ok(0);
+        }
+        let position = self.position;
+        let block_index = self.index.blocks().partition_point(|block| {
+            block.uncompressed_offset.unwrap() + block.uncompressed_length.unwrap() <= position
+        });
+        let block_offset = self.index.blocks()[block_index]
+            .uncompressed_offset
+            .unwrap();
+        let data = self.block(block_index)?;
+        let start = (position - block_offset) as usize;
+        let size = buf.len().min(data.len() - start);
+        buf[..size].copy_from_slice(&data[start..start + size]);
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableBz2Reader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.uncompressed_length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::{BlockSize, EncoderBuilder};
+    use crate::test_util::{encode, sample};
+    use std::io::Cursor;
+
+    #[test]
+    pub fn reads_ranges_from_anywhere() {
+        let input = sample(350_000);
+        let mut reader = SeekableBz2Reader::new(Cursor::new(encode(&input, 1)))
+            .unwrap()
+            .with_cache_size(1);
+        assert_eq!(reader.uncompressed_length(), input.len() as u64);
+
+        for offset in [300_000, 5, 99_990, 250_000, 0] {
+            reader.seek(SeekFrom::Start(offset)).unwrap();
+            let mut buf = vec![0u8; 20_000];
+            reader.read_exact(&mut buf).unwrap();
+            let offset = offset as usize;
+            assert_eq!(buf, input[offset..offset + 20_000]);
+        }
+
+        reader.seek(SeekFrom::End(-10)).unwrap();
+        let mut tail = vec![];
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, input[input.len() - 10..]);
+        assert_eq!(reader.read(&mut [0u8; 10]).unwrap(), 0);
+        assert!(reader.seek(SeekFrom::Current(-1_000_000)).is_err());
+    }
+
+    #[test]
+    pub fn uses_given_index() {
+        let input = sample(150_000);
+        let encoded = encode(&input, 1);
+        let index = BlockIndex::build_decoded(&encoded[..]).unwrap();
+        let mut reader =
+            SeekableBz2Reader::with_index(Cursor::new(encoded.clone()), index).unwrap();
+        reader.seek(SeekFrom::Start(120_000)).unwrap();
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, input[120_000..]);
+
+        let index = BlockIndex::build(&encoded[..]).unwrap();
+        assert!(SeekableBz2Reader::with_index(Cursor::new(encoded), index).is_err());
+    }
+
+    #[test]
+    pub fn uses_sidecar_written_by_encoder() {
+        let input = sample(250_000);
+        let mut encoded = vec![];
+        let summary = EncoderBuilder::new()
+            .block_size(BlockSize::new(1).unwrap())
+            .build_index(true)
+            .encode_stream(&input[..], &mut encoded)
+            .unwrap();
+        let mut sidecar = vec![];
+        summary.index.unwrap().write_sidecar(&mut sidecar).unwrap();
+
+        let mut longer = encoded.clone();
+        longer.push(0);
+        assert!(SeekableBz2Reader::with_sidecar(Cursor::new(longer), &sidecar[..]).is_err());
+
+        let mut reader =
+            SeekableBz2Reader::with_sidecar(Cursor::new(encoded), &sidecar[..]).unwrap();
+        reader.seek(SeekFrom::Start(199_999)).unwrap();
+        let mut buf = [0u8; 2];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, input[199_999..200_001]);
+    }
+
+    #[test]
+    pub fn rejects_stale_index() {
+        let input = sample(250_000);
+        let encoded = encode(&input, 1);
+        let index = BlockIndex::build_decoded(&encoded[..]).unwrap();
+
+        let mut longer = index.blocks().to_vec();
+        *longer[0].uncompressed_length.as_mut().unwrap() += 10;
+        for block in longer[1..].iter_mut() {
+            *block.uncompressed_offset.as_mut().unwrap() += 10;
+        }
+        let mut other_crc = index.blocks().to_vec();
+        other_crc[1].crc ^= 1;
+
+        for blocks in [longer, other_crc] {
+            let stale = BlockIndex::new(blocks, index.compressed_length());
+            let mut reader =
+                SeekableBz2Reader::with_index(Cursor::new(encoded.clone()), stale).unwrap();
+            let err = reader.read_to_end(&mut vec![]).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+}
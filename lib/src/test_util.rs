@@ -0,0 +1,22 @@
+//! Helpers shared by the tests of the crate.
+
+use crate::stream::{BlockSize, EncoderBuilder};
+
+/// `len` bytes of eight letters in an order without long runs, which compress to about three
+/// bits per byte.
+pub(crate) fn sample(len: usize) -> Vec<u8> {
+    (0..len as u32)
+        .map(|x| b"abcdefgh"[(x.wrapping_mul(2_654_435_761) >> 29) as usize])
+        .collect()
+}
+
+/// Encode `input` as a single stream using the block size level `level`.
+pub(crate) fn encode(input: &[u8], level: u8) -> Vec<u8> {
+    let mut encoded = vec![];
+    EncoderBuilder::new()
+        .num_threads(2)
+        .block_size(BlockSize::new(level).unwrap())
+        .encode_stream(input, &mut encoded)
+        .unwrap();
+    encoded
+}